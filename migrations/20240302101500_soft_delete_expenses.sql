@@ -0,0 +1,7 @@
+-- Add migration script here
+ALTER TABLE expenses ADD COLUMN deleted_at TEXT;
+
+ALTER TABLE split_transactions ADD COLUMN deleted_at TEXT;
+
+CREATE INDEX idx_expenses_deleted_at ON expenses (deleted_at);
+CREATE INDEX idx_split_transactions_deleted_at ON split_transactions (deleted_at);
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE expenses ADD COLUMN split_strategy TEXT;
@@ -0,0 +1,19 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS expense_payers (
+  id TEXT PRIMARY KEY NOT NULL,
+  expense_id TEXT NOT NULL,
+  user_id TEXT NOT NULL,
+  amount INTEGER NOT NULL,
+
+  CONSTRAINT fk_expense
+    FOREIGN KEY(expense_id)
+    REFERENCES expenses(id),
+
+  CONSTRAINT fk_user
+    FOREIGN KEY(user_id)
+    REFERENCES users(id),
+
+  CONSTRAINT unq UNIQUE (expense_id, user_id)
+);
+
+CREATE INDEX idx_expense_payers_expense_id ON expense_payers (expense_id);
@@ -0,0 +1,42 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS expense_items (
+  id TEXT PRIMARY KEY NOT NULL,
+  expense_id TEXT NOT NULL,
+  name TEXT NOT NULL,
+  quantity INTEGER NOT NULL,
+  unit_price INTEGER NOT NULL,
+  position INTEGER NOT NULL,
+
+  CONSTRAINT fk_expense
+    FOREIGN KEY(expense_id)
+    REFERENCES expenses(id)
+);
+
+CREATE INDEX idx_expense_items_expense_id ON expense_items (expense_id);
+
+CREATE TABLE IF NOT EXISTS expense_item_users (
+  item_id TEXT NOT NULL,
+  user_id TEXT NOT NULL,
+
+  CONSTRAINT fk_item
+    FOREIGN KEY(item_id)
+    REFERENCES expense_items(id)
+    ON DELETE CASCADE,
+
+  CONSTRAINT fk_user
+    FOREIGN KEY(user_id)
+    REFERENCES users(id),
+
+  PRIMARY KEY (item_id, user_id)
+);
+
+CREATE TABLE IF NOT EXISTS expense_receipts (
+  expense_id TEXT PRIMARY KEY NOT NULL,
+  tax INTEGER NOT NULL DEFAULT 0,
+  service_charge INTEGER NOT NULL DEFAULT 0,
+  tip INTEGER NOT NULL DEFAULT 0,
+
+  CONSTRAINT fk_expense
+    FOREIGN KEY(expense_id)
+    REFERENCES expenses(id)
+);
@@ -0,0 +1,32 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS recurring_expenses (
+  id TEXT PRIMARY KEY NOT NULL,
+  group_id TEXT NOT NULL,
+  created_by TEXT NOT NULL,
+  title TEXT NOT NULL,
+  amount INTEGER NOT NULL,
+  currency_id TEXT NOT NULL,
+  category TEXT NOT NULL DEFAULT 'MISC',
+  note TEXT,
+  split_strategy TEXT NOT NULL,
+  rule TEXT NOT NULL,
+  next_run_at TEXT NOT NULL,
+  paused_at TEXT,
+  created_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  CONSTRAINT fk_group
+    FOREIGN KEY(group_id)
+    REFERENCES groups(id),
+
+  CONSTRAINT fk_created_by
+    FOREIGN KEY(created_by)
+    REFERENCES users(id),
+
+  CONSTRAINT fk_currency
+    FOREIGN KEY(currency_id)
+    REFERENCES currency(id)
+);
+
+CREATE INDEX idx_recurring_expenses_next_run_at ON recurring_expenses (next_run_at);
+CREATE INDEX idx_recurring_expenses_group_id ON recurring_expenses (group_id);
@@ -0,0 +1,18 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS revisions (
+  id TEXT PRIMARY KEY NOT NULL,
+  entity TEXT NOT NULL,
+  row_id TEXT NOT NULL,
+  expense_id TEXT,
+  actor_id TEXT NOT NULL,
+  action TEXT NOT NULL,
+  diff TEXT NOT NULL,
+  created_at TEXT NOT NULL,
+
+  CONSTRAINT fk_actor
+    FOREIGN KEY(actor_id)
+    REFERENCES users(id)
+);
+
+CREATE INDEX idx_revisions_row_id ON revisions (row_id, created_at);
+CREATE INDEX idx_revisions_expense_id ON revisions (expense_id, created_at);
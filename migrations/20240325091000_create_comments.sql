@@ -0,0 +1,34 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS comments (
+  id TEXT PRIMARY KEY NOT NULL,
+  expense_id TEXT,
+  split_id TEXT,
+  parent_id TEXT,
+  author_id TEXT NOT NULL,
+  body TEXT NOT NULL,
+  image_id TEXT,
+  created_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+  deleted_at TEXT,
+
+  CONSTRAINT fk_expense
+    FOREIGN KEY(expense_id)
+    REFERENCES expenses(id),
+
+  CONSTRAINT fk_split
+    FOREIGN KEY(split_id)
+    REFERENCES split_transactions(id),
+
+  CONSTRAINT fk_parent
+    FOREIGN KEY(parent_id)
+    REFERENCES comments(id),
+
+  CONSTRAINT fk_author
+    FOREIGN KEY(author_id)
+    REFERENCES users(id),
+
+  CONSTRAINT chk_comment_target CHECK ((expense_id IS NULL) <> (split_id IS NULL))
+);
+
+CREATE INDEX idx_comments_expense_id ON comments (expense_id, created_at);
+CREATE INDEX idx_comments_split_id ON comments (split_id, created_at);
@@ -0,0 +1,92 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS categories (
+  id TEXT PRIMARY KEY NOT NULL,
+  display_name TEXT NOT NULL,
+  icon_key TEXT NOT NULL,
+  group_id TEXT,
+  user_id TEXT,
+  created_at TEXT NOT NULL,
+
+  CONSTRAINT fk_group
+    FOREIGN KEY(group_id)
+    REFERENCES groups(id),
+
+  CONSTRAINT fk_user
+    FOREIGN KEY(user_id)
+    REFERENCES users(id)
+);
+
+CREATE INDEX idx_categories_group_id ON categories (group_id);
+CREATE INDEX idx_categories_user_id ON categories (user_id);
+
+INSERT INTO categories(id, display_name, icon_key, created_at) VALUES
+  ('MISC', 'Miscellaneous', 'misc', '2024-03-28T06:45:00+00:00'),
+  ('FOOD', 'Food & Drinks', 'food', '2024-03-28T06:45:00+00:00'),
+  ('GROCERIES', 'Groceries', 'groceries', '2024-03-28T06:45:00+00:00'),
+  ('TRAVEL', 'Travel', 'travel', '2024-03-28T06:45:00+00:00'),
+  ('TRANSPORT', 'Transport', 'transport', '2024-03-28T06:45:00+00:00'),
+  ('FUEL', 'Fuel', 'fuel', '2024-03-28T06:45:00+00:00'),
+  ('RENT', 'Rent', 'rent', '2024-03-28T06:45:00+00:00'),
+  ('UTILITIES', 'Utilities', 'utilities', '2024-03-28T06:45:00+00:00'),
+  ('SUBSCRIPTIONS', 'Subscriptions', 'subscriptions', '2024-03-28T06:45:00+00:00'),
+  ('ENTERTAINMENT', 'Entertainment', 'entertainment', '2024-03-28T06:45:00+00:00'),
+  ('SHOPPING', 'Shopping', 'shopping', '2024-03-28T06:45:00+00:00'),
+  ('HEALTH', 'Health', 'health', '2024-03-28T06:45:00+00:00'),
+  ('EDUCATION', 'Education', 'education', '2024-03-28T06:45:00+00:00'),
+  ('GIFTS', 'Gifts', 'gifts', '2024-03-28T06:45:00+00:00');
+
+-- Fold the spellings clients used so far into the system categories
+UPDATE expenses SET category = 'MISC' WHERE TRIM(category) = '';
+UPDATE expenses SET category = UPPER(TRIM(category))
+  WHERE UPPER(TRIM(category)) IN (SELECT id FROM categories);
+UPDATE expenses SET category = (SELECT id FROM categories WHERE UPPER(display_name) = UPPER(TRIM(expenses.category)))
+  WHERE UPPER(TRIM(category)) IN (SELECT UPPER(display_name) FROM categories);
+UPDATE expenses SET category = 'FOOD'
+  WHERE UPPER(TRIM(category)) IN ('FOOD & DRINKS', 'FOOD AND DRINKS', 'FOOD & DRINK', 'DRINKS', 'DINING', 'RESTAURANT', 'RESTAURANTS');
+UPDATE expenses SET category = 'GROCERIES' WHERE UPPER(TRIM(category)) IN ('GROCERY');
+UPDATE expenses SET category = 'TRANSPORT' WHERE UPPER(TRIM(category)) IN ('TRANSPORTATION', 'TAXI', 'CAB');
+UPDATE expenses SET category = 'UTILITIES' WHERE UPPER(TRIM(category)) IN ('UTILITY', 'BILLS');
+UPDATE expenses SET category = 'SUBSCRIPTIONS' WHERE UPPER(TRIM(category)) IN ('SUBSCRIPTION');
+UPDATE expenses SET category = 'GIFTS' WHERE UPPER(TRIM(category)) IN ('GIFT');
+
+UPDATE recurring_expenses SET category = 'MISC' WHERE TRIM(category) = '';
+UPDATE recurring_expenses SET category = UPPER(TRIM(category))
+  WHERE UPPER(TRIM(category)) IN (SELECT id FROM categories);
+UPDATE recurring_expenses SET category = (SELECT id FROM categories WHERE UPPER(display_name) = UPPER(TRIM(recurring_expenses.category)))
+  WHERE UPPER(TRIM(category)) IN (SELECT UPPER(display_name) FROM categories);
+UPDATE recurring_expenses SET category = 'FOOD'
+  WHERE UPPER(TRIM(category)) IN ('FOOD & DRINKS', 'FOOD AND DRINKS', 'FOOD & DRINK', 'DRINKS', 'DINING', 'RESTAURANT', 'RESTAURANTS');
+UPDATE recurring_expenses SET category = 'GROCERIES' WHERE UPPER(TRIM(category)) IN ('GROCERY');
+UPDATE recurring_expenses SET category = 'TRANSPORT' WHERE UPPER(TRIM(category)) IN ('TRANSPORTATION', 'TAXI', 'CAB');
+UPDATE recurring_expenses SET category = 'UTILITIES' WHERE UPPER(TRIM(category)) IN ('UTILITY', 'BILLS');
+UPDATE recurring_expenses SET category = 'SUBSCRIPTIONS' WHERE UPPER(TRIM(category)) IN ('SUBSCRIPTION');
+UPDATE recurring_expenses SET category = 'GIFTS' WHERE UPPER(TRIM(category)) IN ('GIFT');
+
+-- Anything else becomes a custom category of the group it was used in
+CREATE TEMP TABLE legacy_categories AS
+  SELECT lower(hex(randomblob(16))) AS id, TRIM(category) AS display_name, group_id
+  FROM (
+    SELECT group_id, category FROM expenses
+    UNION
+    SELECT group_id, category FROM recurring_expenses
+  )
+  WHERE category NOT IN (SELECT id FROM categories)
+  GROUP BY group_id, UPPER(TRIM(category));
+
+INSERT INTO categories(id, display_name, icon_key, group_id, created_at)
+  SELECT id, display_name, 'misc', group_id, '2024-03-28T06:45:00+00:00' FROM legacy_categories;
+
+UPDATE expenses SET category = (
+    SELECT id FROM legacy_categories
+    WHERE legacy_categories.group_id = expenses.group_id
+      AND UPPER(legacy_categories.display_name) = UPPER(TRIM(expenses.category))
+  )
+  WHERE category NOT IN (SELECT id FROM categories);
+UPDATE recurring_expenses SET category = (
+    SELECT id FROM legacy_categories
+    WHERE legacy_categories.group_id = recurring_expenses.group_id
+      AND UPPER(legacy_categories.display_name) = UPPER(TRIM(recurring_expenses.category))
+  )
+  WHERE category NOT IN (SELECT id FROM categories);
+
+DROP TABLE legacy_categories;
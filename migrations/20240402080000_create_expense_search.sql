@@ -0,0 +1,46 @@
+-- Add migration script here
+CREATE VIRTUAL TABLE IF NOT EXISTS expense_search USING fts5(
+  expense_id UNINDEXED,
+  title,
+  note,
+  comments,
+  tokenize = 'unicode61 remove_diacritics 2'
+);
+
+INSERT INTO expense_search(expense_id, title, note, comments)
+SELECT
+  e.id,
+  e.title,
+  COALESCE(e.note, ''),
+  COALESCE((SELECT group_concat(c.body, ' ') FROM comments c WHERE c.expense_id = e.id AND c.deleted_at IS NULL), '')
+FROM expenses e;
+
+CREATE TRIGGER expense_search_insert AFTER INSERT ON expenses BEGIN
+  INSERT INTO expense_search(expense_id, title, note, comments)
+  VALUES (new.id, new.title, COALESCE(new.note, ''), '');
+END;
+
+CREATE TRIGGER expense_search_update AFTER UPDATE OF title, note ON expenses BEGIN
+  UPDATE expense_search SET title = new.title, note = COALESCE(new.note, '')
+  WHERE expense_id = new.id;
+END;
+
+CREATE TRIGGER expense_search_delete AFTER DELETE ON expenses BEGIN
+  DELETE FROM expense_search WHERE expense_id = old.id;
+END;
+
+CREATE TRIGGER expense_search_comment_insert AFTER INSERT ON comments
+WHEN new.expense_id IS NOT NULL BEGIN
+  UPDATE expense_search SET comments = COALESCE((
+    SELECT group_concat(c.body, ' ') FROM comments c WHERE c.expense_id = new.expense_id AND c.deleted_at IS NULL
+  ), '')
+  WHERE expense_id = new.expense_id;
+END;
+
+CREATE TRIGGER expense_search_comment_update AFTER UPDATE ON comments
+WHEN new.expense_id IS NOT NULL BEGIN
+  UPDATE expense_search SET comments = COALESCE((
+    SELECT group_concat(c.body, ' ') FROM comments c WHERE c.expense_id = new.expense_id AND c.deleted_at IS NULL
+  ), '')
+  WHERE expense_id = new.expense_id;
+END;
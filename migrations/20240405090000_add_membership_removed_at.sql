@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE group_memberships ADD COLUMN removed_at TEXT;
@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE group_memberships ADD COLUMN role TEXT NOT NULL DEFAULT 'Member';
+
+UPDATE group_memberships SET role = 'Owner'
+WHERE user_id = (SELECT creator_id FROM groups WHERE groups.id = group_memberships.group_id);
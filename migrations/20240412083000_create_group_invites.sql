@@ -0,0 +1,23 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS group_invites (
+  id TEXT PRIMARY KEY NOT NULL,
+  token TEXT NOT NULL UNIQUE,
+  group_id TEXT NOT NULL,
+  created_by TEXT NOT NULL,
+  role TEXT NOT NULL DEFAULT 'Member',
+  max_uses INTEGER,
+  uses INTEGER NOT NULL DEFAULT 0,
+  expires_at TEXT NOT NULL,
+  revoked_at TEXT,
+  created_at TEXT NOT NULL,
+
+  CONSTRAINT fk_group
+    FOREIGN KEY(group_id)
+    REFERENCES groups(id),
+
+  CONSTRAINT fk_created_by
+    FOREIGN KEY(created_by)
+    REFERENCES users(id)
+);
+
+CREATE INDEX idx_group_invites_group_id ON group_invites (group_id);
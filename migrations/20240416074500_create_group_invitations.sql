@@ -0,0 +1,28 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS group_invitations (
+  id TEXT PRIMARY KEY NOT NULL,
+  group_id TEXT NOT NULL,
+  user_id TEXT NOT NULL,
+  invited_by TEXT NOT NULL,
+  role TEXT NOT NULL DEFAULT 'Member',
+  status TEXT NOT NULL DEFAULT 'Pending',
+  created_at TEXT NOT NULL,
+  responded_at TEXT,
+
+  CONSTRAINT fk_group
+    FOREIGN KEY(group_id)
+    REFERENCES groups(id),
+
+  CONSTRAINT fk_user
+    FOREIGN KEY(user_id)
+    REFERENCES users(id),
+
+  CONSTRAINT fk_invited_by
+    FOREIGN KEY(invited_by)
+    REFERENCES users(id)
+);
+
+CREATE INDEX idx_group_invitations_user_id ON group_invitations (user_id);
+CREATE UNIQUE INDEX idx_group_invitations_pending ON group_invitations (group_id, user_id) WHERE status = 'Pending';
+
+ALTER TABLE user_config ADD COLUMN invitation_policy TEXT NOT NULL DEFAULT 'AutoAcceptContacts';
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE groups ADD COLUMN archived_at TEXT;
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE groups ADD COLUMN description TEXT;
+ALTER TABLE groups ADD COLUMN image_id TEXT;
+ALTER TABLE groups ADD COLUMN default_currency_id TEXT REFERENCES currency(id);
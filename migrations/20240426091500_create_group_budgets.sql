@@ -0,0 +1,43 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS group_budgets (
+  id TEXT PRIMARY KEY NOT NULL,
+  group_id TEXT NOT NULL,
+  category TEXT,
+  amount INTEGER NOT NULL,
+  currency_id TEXT NOT NULL,
+  thresholds TEXT NOT NULL DEFAULT '[80,100]',
+  created_by TEXT NOT NULL,
+  created_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  CONSTRAINT fk_group
+    FOREIGN KEY(group_id)
+    REFERENCES groups(id),
+
+  CONSTRAINT fk_category
+    FOREIGN KEY(category)
+    REFERENCES categories(id),
+
+  CONSTRAINT fk_currency
+    FOREIGN KEY(currency_id)
+    REFERENCES currency(id),
+
+  CONSTRAINT fk_created_by
+    FOREIGN KEY(created_by)
+    REFERENCES users(id)
+);
+
+CREATE UNIQUE INDEX idx_group_budgets_group_category ON group_budgets (group_id, COALESCE(category, ''));
+
+-- Thresholds a budget has already alerted for, so members hear about each once.
+CREATE TABLE IF NOT EXISTS group_budget_alerts (
+  budget_id TEXT NOT NULL,
+  threshold INTEGER NOT NULL,
+  sent_at TEXT NOT NULL,
+
+  PRIMARY KEY (budget_id, threshold),
+
+  CONSTRAINT fk_budget
+    FOREIGN KEY(budget_id)
+    REFERENCES group_budgets(id)
+);
@@ -0,0 +1,35 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS group_events (
+  id TEXT PRIMARY KEY NOT NULL,
+  group_id TEXT NOT NULL,
+  actor_id TEXT NOT NULL,
+  event_type TEXT NOT NULL,
+  expense_id TEXT,
+  split_id TEXT,
+  -- Member the event is about, e.g. who joined or was removed.
+  user_id TEXT,
+  details TEXT,
+  created_at TEXT NOT NULL,
+
+  CONSTRAINT fk_group
+    FOREIGN KEY(group_id)
+    REFERENCES groups(id),
+
+  CONSTRAINT fk_actor
+    FOREIGN KEY(actor_id)
+    REFERENCES users(id),
+
+  CONSTRAINT fk_expense
+    FOREIGN KEY(expense_id)
+    REFERENCES expenses(id),
+
+  CONSTRAINT fk_split
+    FOREIGN KEY(split_id)
+    REFERENCES split_transactions(id),
+
+  CONSTRAINT fk_user
+    FOREIGN KEY(user_id)
+    REFERENCES users(id)
+);
+
+CREATE INDEX idx_group_events_group_created ON group_events (group_id, created_at);
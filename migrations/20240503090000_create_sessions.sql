@@ -0,0 +1,18 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS sessions (
+  id TEXT PRIMARY KEY NOT NULL,
+  user_id TEXT NOT NULL,
+  device_name TEXT,
+  ip TEXT,
+  -- Id of the only refresh token of the session that may still be used.
+  refresh_token_id TEXT NOT NULL,
+  created_at TEXT NOT NULL,
+  last_used_at TEXT NOT NULL,
+  revoked_at TEXT,
+
+  CONSTRAINT fk_user
+    FOREIGN KEY(user_id)
+    REFERENCES users(id)
+);
+
+CREATE INDEX idx_sessions_user ON sessions (user_id);
@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS otp_codes (
+  -- Email or phone number the code was sent to.
+  key TEXT PRIMARY KEY NOT NULL,
+  code_hash TEXT NOT NULL,
+  expires_at TEXT NOT NULL
+);
@@ -0,0 +1,29 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS pending_expenses (
+  id TEXT PRIMARY KEY NOT NULL,
+  group_id TEXT NOT NULL,
+  created_by TEXT NOT NULL,
+  title TEXT NOT NULL,
+  amount INTEGER NOT NULL,
+  currency_id TEXT NOT NULL,
+  category TEXT NOT NULL DEFAULT 'MISC',
+  note TEXT,
+  image_id TEXT,
+  split_strategy TEXT NOT NULL,
+  transaction_at TEXT,
+  created_at TEXT NOT NULL,
+
+  CONSTRAINT fk_group
+    FOREIGN KEY(group_id)
+    REFERENCES groups(id),
+
+  CONSTRAINT fk_created_by
+    FOREIGN KEY(created_by)
+    REFERENCES users(id),
+
+  CONSTRAINT fk_currency
+    FOREIGN KEY(currency_id)
+    REFERENCES currency(id)
+);
+
+CREATE INDEX idx_pending_expenses_group_id ON pending_expenses (group_id);
@@ -0,0 +1,14 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS otp_limits (
+  -- What is counted, e.g. sends or failures, and for which email, phone number or ip.
+  kind TEXT NOT NULL,
+  key TEXT NOT NULL,
+  count INTEGER NOT NULL,
+  window_started_at TEXT NOT NULL,
+  last_at TEXT NOT NULL,
+  locked_until TEXT,
+
+  PRIMARY KEY (kind, key)
+);
+
+CREATE INDEX idx_otp_limits_window_started_at ON otp_limits (window_started_at);
@@ -0,0 +1,248 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use async_graphql::Enum;
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use strum::Display;
+use tokio::sync::RwLock;
+
+use crate::REQWEST_CLIENT;
+
+/// How long keys fetched from a provider are trusted before fetching again.
+const JWKS_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Least time between fetches forced by tokens with an unknown key, so such
+/// tokens can not make every request fetch the keys again.
+const JWKS_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+static JWKS_CACHE: Lazy<RwLock<HashMap<IdTokenProvider, (Instant, JwkSet)>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+static JWKS_REFRESHED: Lazy<RwLock<HashMap<IdTokenProvider, Instant>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// OpenID Connect providers whose ID tokens can be used to sign in.
+///
+/// Each one is configured through environment variables prefixed with its
+/// name, e.g. for Google:
+/// - `GOOGLE_CLIENT_IDS`: comma separated client ids tokens may be issued to.
+/// - `GOOGLE_JWKS_FILE`: optional path of a JWKS to use instead of fetching
+///   the provider's keys, for testing offline.
+#[derive(Enum, Clone, Copy, PartialEq, Eq, Hash, Debug, Display)]
+pub enum IdTokenProvider {
+    Google,
+    Apple,
+}
+
+impl IdTokenProvider {
+    fn env_prefix(&self) -> &'static str {
+        match self {
+            IdTokenProvider::Google => "GOOGLE",
+            IdTokenProvider::Apple => "APPLE",
+        }
+    }
+
+    fn jwks_url(&self) -> &'static str {
+        match self {
+            IdTokenProvider::Google => "https://www.googleapis.com/oauth2/v3/certs",
+            IdTokenProvider::Apple => "https://appleid.apple.com/auth/keys",
+        }
+    }
+
+    fn issuers(&self) -> &'static [&'static str] {
+        match self {
+            IdTokenProvider::Google => &["https://accounts.google.com", "accounts.google.com"],
+            IdTokenProvider::Apple => &["https://appleid.apple.com"],
+        }
+    }
+
+    fn client_ids(&self) -> anyhow::Result<Vec<String>> {
+        let client_ids = std::env::var(format!("{}_CLIENT_IDS", self.env_prefix()))
+            .map_err(|_e| anyhow::anyhow!("Sign in with {self} is not configured"))?;
+        Ok(client_ids
+            .split(',')
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+            .collect())
+    }
+
+    /// Whether a forced fetch of the keys is allowed now, counting it if so.
+    async fn may_refresh_jwks(&self) -> bool {
+        let mut refreshed = JWKS_REFRESHED.write().await;
+        if refreshed
+            .get(self)
+            .is_some_and(|last| last.elapsed() < JWKS_MIN_REFRESH_INTERVAL)
+        {
+            return false;
+        }
+        refreshed.insert(*self, Instant::now());
+        true
+    }
+
+    /// Keys of the provider, from the configured file or fetched and cached.
+    /// `refresh` skips the cache, for when a token uses a key we do not know.
+    async fn get_jwks(&self, refresh: bool) -> anyhow::Result<JwkSet> {
+        if let Ok(path) = std::env::var(format!("{}_JWKS_FILE", self.env_prefix())) {
+            let data = tokio::fs::read_to_string(path).await?;
+            return Ok(serde_json::from_str(&data)?);
+        }
+        if !refresh {
+            if let Some((fetched, jwks)) = JWKS_CACHE.read().await.get(self) {
+                if fetched.elapsed() < JWKS_TTL {
+                    return Ok(jwks.clone());
+                }
+            }
+        }
+        let jwks = REQWEST_CLIENT
+            .get(self.jwks_url())
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<JwkSet>()
+            .await?;
+        JWKS_CACHE
+            .write()
+            .await
+            .insert(*self, (Instant::now(), jwks.clone()));
+        Ok(jwks)
+    }
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    email: Option<String>,
+    /// Google sends a bool, Apple sometimes the string `"true"`.
+    email_verified: Option<serde_json::Value>,
+}
+
+/// Checks the signature, issuer, audience and expiry of an ID token and
+/// returns its verified email.
+pub async fn verify_id_token(provider: IdTokenProvider, token: &str) -> anyhow::Result<String> {
+    let header = jsonwebtoken::decode_header(token)?;
+    let kid = header
+        .kid
+        .ok_or_else(|| anyhow::anyhow!("ID token has no key id"))?;
+    let jwk = match provider.get_jwks(false).await?.find(&kid) {
+        Some(jwk) => jwk.clone(),
+        None if provider.may_refresh_jwks().await => provider
+            .get_jwks(true)
+            .await?
+            .find(&kid)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("ID token signed with unknown key"))?,
+        None => return Err(anyhow::anyhow!("ID token signed with unknown key")),
+    };
+    let key = DecodingKey::from_jwk(&jwk)?;
+
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.set_audience(&provider.client_ids()?);
+    validation.set_issuer(provider.issuers());
+    let claims = jsonwebtoken::decode::<IdTokenClaims>(token, &key, &validation)?.claims;
+
+    let verified = match claims.email_verified {
+        Some(serde_json::Value::Bool(verified)) => verified,
+        Some(serde_json::Value::String(verified)) => verified == "true",
+        _ => false,
+    };
+    match claims.email {
+        Some(email) if verified => Ok(email),
+        _ => Err(anyhow::anyhow!("ID token has no verified email")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+
+    const CLIENT_ID: &str = "test-client";
+
+    fn setup() {
+        std::env::set_var("GOOGLE_CLIENT_IDS", CLIENT_ID);
+        std::env::set_var(
+            "GOOGLE_JWKS_FILE",
+            concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/auth/test_data/id_token_jwks.json"
+            ),
+        );
+    }
+
+    fn sign(kid: &str, claims: serde_json::Value) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_string());
+        let key = EncodingKey::from_rsa_pem(include_bytes!("test_data/id_token_key.pem")).unwrap();
+        jsonwebtoken::encode(&header, &claims, &key).unwrap()
+    }
+
+    fn claims() -> serde_json::Value {
+        json!({
+            "iss": "https://accounts.google.com",
+            "aud": CLIENT_ID,
+            "exp": chrono::Utc::now().timestamp() + 3600,
+            "email": "user@example.com",
+            "email_verified": true,
+        })
+    }
+
+    async fn verify(claims: serde_json::Value) -> anyhow::Result<String> {
+        setup();
+        verify_id_token(IdTokenProvider::Google, &sign("test-key", claims)).await
+    }
+
+    #[tokio::test]
+    async fn accepts_valid_token() {
+        assert_eq!(verify(claims()).await.unwrap(), "user@example.com");
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_audience() {
+        let mut claims = claims();
+        claims["aud"] = json!("other-client");
+        assert!(verify(claims).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_issuer() {
+        let mut claims = claims();
+        claims["iss"] = json!("https://appleid.apple.com");
+        assert!(verify(claims).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_token() {
+        let mut claims = claims();
+        claims["exp"] = json!(chrono::Utc::now().timestamp() - 3600);
+        assert!(verify(claims).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_unverified_email() {
+        for verified in [json!(false), json!("false")] {
+            let mut claims = claims();
+            claims["email_verified"] = verified;
+            assert!(verify(claims).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_key() {
+        setup();
+        let token = sign("other-key", claims());
+        assert!(verify_id_token(IdTokenProvider::Google, &token)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn limits_forced_refreshes() {
+        assert!(IdTokenProvider::Apple.may_refresh_jwks().await);
+        assert!(!IdTokenProvider::Apple.may_refresh_jwks().await);
+    }
+}
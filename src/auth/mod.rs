@@ -2,12 +2,31 @@ use std::net::Ipv4Addr;
 
 use async_graphql::{SimpleObject, Union};
 use jsonwebtoken::{DecodingKey, EncodingKey, Validation};
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
-use crate::models::user::User;
+use crate::models::{session::Session, user::User};
+
+pub mod id_token;
+
+/// Number of proxies in front of the server that append to
+/// `X-Forwarded-For`, from `TRUSTED_PROXY_HOPS`. 0 ignores the header, for
+/// deployments without a proxy.
+static TRUSTED_PROXY_HOPS: Lazy<usize> = Lazy::new(|| {
+    std::env::var("TRUSTED_PROXY_HOPS")
+        .ok()
+        .and_then(|hops| hops.parse().ok())
+        .unwrap_or(1)
+});
 
 pub struct ForwardedHeader(pub String);
 
+/// Address of the socket the request came from, usually the closest proxy.
+pub struct PeerAddress(pub String);
+
+/// Id of the session the request was made with.
+pub struct CurrentSession(pub String);
+
 #[derive(Debug)]
 pub enum AuthTypes {
     UnAuthorized,
@@ -35,6 +54,11 @@ pub struct Claims {
     pub email: Option<String>,
     pub user_id: Option<String>,
     pub token_type: TokenType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// Which refresh token of the session this is, see [`Session::rotate`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -105,10 +129,13 @@ pub struct UserSignedUp {
     pub refresh_token: String,
 }
 
+/// Signup token when there is no `user_id`, otherwise access and refresh
+/// tokens for `session`.
 pub fn create_tokens(
     user_id: Option<String>,
     email: Option<String>,
     phone_number: Option<String>,
+    session: Option<&Session>,
 ) -> anyhow::Result<AuthResult> {
     let now = std::time::SystemTime::now();
     let exp = now.duration_since(std::time::UNIX_EPOCH)?.as_secs() as usize + (15 * 60);
@@ -121,6 +148,8 @@ pub fn create_tokens(
             user_id: user_id.clone(),
             exp,
             token_type: TokenType::Signup,
+            session_id: None,
+            token_id: None,
         };
         let access_token = jsonwebtoken::encode(
             &jsonwebtoken::Header::default(),
@@ -131,12 +160,15 @@ pub fn create_tokens(
             signup_token: access_token,
         }))
     } else {
+        let session = session.ok_or_else(|| anyhow::anyhow!("No session for tokens"))?;
         let claims = Claims {
             phone_number: phone_number.clone(),
             email: email.clone(),
             user_id: user_id.clone(),
             exp,
             token_type: TokenType::Access,
+            session_id: Some(session.id.clone()),
+            token_id: None,
         };
         let access_token = jsonwebtoken::encode(
             &jsonwebtoken::Header::default(),
@@ -153,6 +185,8 @@ pub fn create_tokens(
             user_id,
             exp,
             token_type: TokenType::Refresh,
+            session_id: Some(session.id.clone()),
+            token_id: Some(session.refresh_token_id.clone()),
         };
         let refresh_token = jsonwebtoken::encode(
             &jsonwebtoken::Header::default(),
@@ -196,9 +230,42 @@ pub fn decode_refresh_token(token: &str) -> anyhow::Result<Claims> {
 }
 
 impl ForwardedHeader {
+    /// Address of the client as seen by our own proxies. Each proxy appends
+    /// the address it was called from, so only the last `TRUSTED_PROXY_HOPS`
+    /// entries can be trusted, anything before them is up to the client.
+    /// None without trusted proxies.
+    pub fn client_ip(&self) -> Option<&str> {
+        self.client_ip_after(*TRUSTED_PROXY_HOPS)
+    }
+
+    fn client_ip_after(&self, hops: usize) -> Option<&str> {
+        self.0
+            .rsplit(',')
+            .take(hops)
+            .last()
+            .map(str::trim)
+            .filter(|ip| !ip.is_empty())
+    }
+
     pub fn determine_country(&self, db: &ip2country::AsnDB) -> anyhow::Result<String> {
         let ipv4 = self.0.parse::<Ipv4Addr>()?;
         let country = db.lookup_ipv4(ipv4).ok_or(anyhow::anyhow!("Unknown ip"))?;
         Ok(format!("{}{}", country[0], country[1]))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_ip_uses_trusted_hops() {
+        let header = ForwardedHeader("6.6.6.6, 1.2.3.4, 10.0.0.1".to_string());
+        assert_eq!(header.client_ip_after(0), None);
+        assert_eq!(header.client_ip_after(1), Some("10.0.0.1"));
+        assert_eq!(header.client_ip_after(2), Some("1.2.3.4"));
+        // Fewer entries than proxies, the first one was added by ours.
+        assert_eq!(header.client_ip_after(5), Some("6.6.6.6"));
+        assert_eq!(ForwardedHeader(String::new()).client_ip_after(1), None);
+    }
+}
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::net::SocketAddr;
 
 use async_graphql::{
     http::{playground_source, GraphQLPlaygroundConfig},
@@ -6,32 +6,28 @@ use async_graphql::{
 };
 use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
 use axum::{
-    extract::State,
+    extract::{ConnectInfo, State},
     http::{HeaderMap, Method, StatusCode},
     response::{Html, IntoResponse},
     routing::{get, post},
     Extension, Router, Server,
 };
 use axum_auth::AuthBearer;
-use expire_map::ExpiringHashMap;
 use http_cache::{CACacheManager, CacheMode, HttpCache};
 use http_cache_reqwest::Cache;
 
-use models::currency::Currency;
+use models::{currency::Currency, recurring_expense::RecurringExpense};
 use once_cell::sync::Lazy;
 use reqwest::Client;
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
-use schema::{
-    mutation::{Mutation, OtpMap},
-    query::Query,
-};
+use schema::{mutation::Mutation, query::Query};
 
 use sqlx::SqlitePool;
 use tower_http::{compression::CompressionLayer, cors::CorsLayer};
 
 use crate::{
-    auth::{decode_access_token, AuthTypes, ForwardedHeader},
-    models::user::User,
+    auth::{decode_access_token, AuthTypes, CurrentSession, ForwardedHeader, PeerAddress},
+    models::{session::Session, user::User},
 };
 
 use serde::{Deserialize, Serialize};
@@ -41,8 +37,11 @@ pub mod email;
 pub mod expire_map;
 pub mod models;
 pub mod notification;
+pub mod otp_limit;
+pub mod otp_store;
 pub mod s3;
 pub mod schema;
+pub mod sms;
 
 type MainSchema = Schema<Query, Mutation, EmptySubscription>;
 
@@ -94,10 +93,26 @@ async fn main() -> Result<(), ()> {
     .expect("Cannot connect to pool");
     sqlx::migrate!().run(&pool).await.expect("Cant migrate");
 
-    let otp_map: OtpMap = OtpMap::new(ExpiringHashMap::new(Duration::from_secs(5 * 60)));
+    let otp_store = otp_store::store_from_env(&pool).expect("Cannot initialize otp store");
+    let otp_limiter = otp_limit::limiter_from_env(&pool).expect("Cannot initialize otp limiter");
+    let sms = sms::provider_from_env().expect("Cannot initialize sms provider");
+
+    let mut recurring_interval = tokio::time::interval(std::time::Duration::from_secs(60 * 5));
+    let recurring_pool = pool.clone();
+    let recurring_s3 = s3.clone();
+    tokio::spawn(async move {
+        loop {
+            recurring_interval.tick().await;
+            if let Err(err) = RecurringExpense::run_due(&recurring_pool, &recurring_s3).await {
+                log::warn!("Failed to run recurring expenses {err:?}");
+            }
+        }
+    });
 
     let schema = MainSchema::build(Query, Mutation, EmptySubscription)
-        .data(otp_map)
+        .data(otp_store)
+        .data(sms)
+        .data(otp_limiter)
         .data(asn_db)
         .data(s3)
         .extension(async_graphql::extensions::ApolloTracing)
@@ -126,7 +141,7 @@ async fn main() -> Result<(), ()> {
         let _ = Currency::fill_currencies(&pool).await;
     });
     Server::bind(&format!("0.0.0.0:{port}").parse().unwrap())
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 
@@ -138,10 +153,12 @@ async fn graphql_handler(
 
     token: Option<AuthBearer>,
     State(pool): State<SqlitePool>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
     req: GraphQLRequest,
 ) -> Result<GraphQLResponse, (StatusCode, String)> {
     let mut req = req.into_inner();
+    let mut current_session = None;
     let auth_type = 'auth_type: {
         if let Some(AuthBearer(token)) = token {
             let claims = decode_access_token(&token);
@@ -149,6 +166,17 @@ async fn graphql_handler(
                 if claims.token_type.is_signup() {
                     break 'auth_type AuthTypes::AuthorizedNotSignedUp(claims);
                 } else if claims.token_type.is_access() && claims.user_id.is_some() {
+                    // Access tokens from before sessions existed have none and
+                    // run out within minutes.
+                    if let Some(session_id) = claims.session_id {
+                        if !Session::is_active(&session_id, &pool)
+                            .await
+                            .unwrap_or(false)
+                        {
+                            break 'auth_type AuthTypes::UnAuthorized;
+                        }
+                        current_session = Some(CurrentSession(session_id));
+                    }
                     let user = User::get_from_id(&claims.user_id.unwrap(), &pool).await;
                     if let Ok(user) = user {
                         break 'auth_type AuthTypes::AuthorizedUser(user);
@@ -165,7 +193,11 @@ async fn graphql_handler(
 
     log::debug!("Setting authType {auth_type:#?}");
     req = req.data(auth_type);
+    if let Some(current_session) = current_session {
+        req = req.data(current_session);
+    }
     req = req.data(pool);
+    req = req.data(PeerAddress(peer.ip().to_string()));
     if let Some(forwarded) = headers.get("X-Forwarded-For").and_then(|f| f.to_str().ok()) {
         req = req.data(ForwardedHeader(forwarded.to_string()));
     }
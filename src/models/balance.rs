@@ -0,0 +1,50 @@
+use async_graphql::{ComplexObject, Context, SimpleObject};
+
+use crate::schema::get_pool_from_context;
+
+use super::{amount::Amount, user::User};
+
+/// Net debt between two members in one currency, `from_user` owes `to_user`.
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct PairBalance {
+    pub from_user_id: String,
+    pub to_user_id: String,
+    pub amount: Amount,
+}
+
+#[ComplexObject]
+impl PairBalance {
+    pub async fn from_user<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<User> {
+        let pool = get_pool_from_context(context).await?;
+        User::get_from_id(&self.from_user_id, pool).await
+    }
+
+    pub async fn to_user<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<User> {
+        let pool = get_pool_from_context(context).await?;
+        User::get_from_id(&self.to_user_id, pool).await
+    }
+}
+
+/// Where a member stands in the group per currency, positive when they are
+/// owed and negative when they owe.
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct MemberBalance {
+    pub user_id: String,
+    pub net: Vec<Amount>,
+}
+
+#[ComplexObject]
+impl MemberBalance {
+    pub async fn user<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<User> {
+        let pool = get_pool_from_context(context).await?;
+        User::get_from_id(&self.user_id, pool).await
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct GroupBalances {
+    pub pairs: Vec<PairBalance>,
+    pub members: Vec<MemberBalance>,
+}
@@ -0,0 +1,326 @@
+use async_graphql::{Context, Object, SimpleObject};
+use sqlx::SqlitePool;
+
+use crate::{notification::notify_user, schema::get_pool_from_context};
+
+use super::{amount::Amount, category::Category, currency::Currency, group::Group};
+
+const DEFAULT_THRESHOLDS: [i64; 2] = [80, 100];
+
+/// Spending limit for a group, in total or for one category. Only expenses
+/// in the budget's currency count towards it.
+pub struct Budget {
+    pub id: String,
+    pub group_id: String,
+    pub category: Option<String>,
+    pub amount: i64,
+    pub currency_id: String,
+    pub thresholds: String,
+    pub created_by: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[Object]
+impl Budget {
+    pub async fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub async fn group_id(&self) -> &str {
+        &self.group_id
+    }
+
+    /// Empty for the budget of the whole group.
+    pub async fn category(&self) -> &Option<String> {
+        &self.category
+    }
+
+    pub async fn category_details<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+    ) -> anyhow::Result<Option<Category>> {
+        let pool = get_pool_from_context(context).await?;
+        match &self.category {
+            Some(category) => Ok(Some(Category::get_from_id(category, pool).await?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn amount(&self) -> Amount {
+        Amount {
+            amount: self.amount,
+            currency_id: self.currency_id.clone(),
+        }
+    }
+
+    /// Percentages of the budget at which members are alerted.
+    pub async fn thresholds(&self) -> anyhow::Result<Vec<i64>> {
+        self.get_thresholds()
+    }
+
+    pub async fn created_by(&self) -> &str {
+        &self.created_by
+    }
+
+    pub async fn created_at(&self) -> &str {
+        &self.created_at
+    }
+
+    pub async fn updated_at(&self) -> &str {
+        &self.updated_at
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct BudgetStatus {
+    pub budget: Budget,
+    pub spent: Amount,
+    pub remaining: Amount,
+    /// Share of the budget spent, in percent. Goes over 100 once exceeded.
+    pub percent_used: i64,
+}
+
+impl Budget {
+    pub fn get_thresholds(&self) -> anyhow::Result<Vec<i64>> {
+        Ok(serde_json::from_str(&self.thresholds)?)
+    }
+
+    fn thresholds_to_json(thresholds: Option<Vec<i64>>) -> anyhow::Result<String> {
+        let mut thresholds = thresholds.unwrap_or_else(|| DEFAULT_THRESHOLDS.to_vec());
+        if thresholds.iter().any(|t| !(1..=1000).contains(t)) {
+            return Err(anyhow::anyhow!("Thresholds must be between 1 and 1000"));
+        }
+        thresholds.sort();
+        thresholds.dedup();
+        Ok(serde_json::to_string(&thresholds)?)
+    }
+
+    pub async fn create(
+        group_id: &str,
+        category: Option<&str>,
+        amount: &Amount,
+        thresholds: Option<Vec<i64>>,
+        created_by: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Budget> {
+        if amount.amount <= 0 {
+            return Err(anyhow::anyhow!("Amount must be greater than 0"));
+        }
+        let thresholds = Self::thresholds_to_json(thresholds)?;
+        let id = uuid::Uuid::new_v4().to_string();
+        let time = chrono::Utc::now().to_rfc3339();
+        let budget = sqlx::query_as!(
+            Budget,
+            r#"INSERT INTO group_budgets(id, group_id, category, amount, currency_id, thresholds, created_by, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $8)
+            RETURNING *
+            "#,
+            id,
+            group_id,
+            category,
+            amount.amount,
+            amount.currency_id,
+            thresholds,
+            created_by,
+            time
+        )
+        .fetch_one(pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(err) if err.is_unique_violation() => {
+                anyhow::anyhow!("Budget already exists")
+            }
+            e => e.into(),
+        })?;
+        Ok(budget)
+    }
+
+    /// Changes a budget. Alerts already sent are forgotten, so members hear
+    /// about the new limits again.
+    pub async fn edit(
+        id: &str,
+        amount: Option<i64>,
+        currency_id: Option<String>,
+        thresholds: Option<Vec<i64>>,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Budget> {
+        if amount.is_some_and(|amount| amount <= 0) {
+            return Err(anyhow::anyhow!("Amount must be greater than 0"));
+        }
+        let thresholds = match thresholds {
+            Some(thresholds) => Some(Self::thresholds_to_json(Some(thresholds))?),
+            None => None,
+        };
+        let time = chrono::Utc::now().to_rfc3339();
+        let mut transaction = pool.begin().await?;
+        let budget = sqlx::query_as!(
+            Budget,
+            r#"UPDATE group_budgets SET
+                amount = COALESCE($2, amount),
+                currency_id = COALESCE($3, currency_id),
+                thresholds = COALESCE($4, thresholds),
+                updated_at = $5
+            WHERE id = $1
+            RETURNING *
+            "#,
+            id,
+            amount,
+            currency_id,
+            thresholds,
+            time
+        )
+        .fetch_one(transaction.as_mut())
+        .await?;
+        sqlx::query!("DELETE FROM group_budget_alerts WHERE budget_id = $1", id)
+            .execute(transaction.as_mut())
+            .await?;
+        transaction.commit().await?;
+        Ok(budget)
+    }
+
+    pub async fn delete(id: &str, pool: &SqlitePool) -> anyhow::Result<()> {
+        let mut transaction = pool.begin().await?;
+        sqlx::query!("DELETE FROM group_budget_alerts WHERE budget_id = $1", id)
+            .execute(transaction.as_mut())
+            .await?;
+        sqlx::query!("DELETE FROM group_budgets WHERE id = $1", id)
+            .execute(transaction.as_mut())
+            .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_from_id(id: &str, pool: &SqlitePool) -> anyhow::Result<Budget> {
+        let budget = sqlx::query_as!(Budget, "SELECT * FROM group_budgets WHERE id = $1", id)
+            .fetch_one(pool)
+            .await?;
+        Ok(budget)
+    }
+
+    pub async fn get_for_group(group_id: &str, pool: &SqlitePool) -> anyhow::Result<Vec<Budget>> {
+        let budgets = sqlx::query_as!(
+            Budget,
+            "SELECT * FROM group_budgets WHERE group_id = $1 ORDER BY category IS NOT NULL, created_at",
+            group_id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(budgets)
+    }
+
+    pub async fn get_status(self, pool: &SqlitePool) -> anyhow::Result<BudgetStatus> {
+        let spent = sqlx::query!(
+            r#"
+            SELECT COALESCE(SUM(amount), 0) AS "spent!: i64" FROM expenses
+            WHERE group_id = $1 AND currency_id = $2 AND deleted_at IS NULL
+                AND ($3 IS NULL OR category = $3)
+            "#,
+            self.group_id,
+            self.currency_id,
+            self.category
+        )
+        .fetch_one(pool)
+        .await?
+        .spent;
+        let percent_used = (spent as i128 * 100 / self.amount as i128) as i64;
+        Ok(BudgetStatus {
+            spent: Amount {
+                amount: spent,
+                currency_id: self.currency_id.clone(),
+            },
+            remaining: Amount {
+                amount: self.amount - spent,
+                currency_id: self.currency_id.clone(),
+            },
+            percent_used,
+            budget: self,
+        })
+    }
+
+    pub async fn get_status_for_group(
+        group_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<BudgetStatus>> {
+        let mut statuses = vec![];
+        for budget in Self::get_for_group(group_id, pool).await? {
+            statuses.push(budget.get_status(pool).await?);
+        }
+        Ok(statuses)
+    }
+
+    /// Alerts the members of a group about every budget threshold spending has
+    /// crossed since the last check. Each threshold is only alerted once.
+    pub async fn check_alerts(group_id: &str, pool: &SqlitePool) -> anyhow::Result<()> {
+        let statuses = Self::get_status_for_group(group_id, pool).await?;
+        if statuses.is_empty() {
+            return Ok(());
+        }
+        let group = Group::get_from_id(group_id, pool).await?;
+        let group_name = group.name.as_deref().unwrap_or("Direct Payment");
+        let members = Group::get_users(group_id, pool).await?;
+        let time = chrono::Utc::now().to_rfc3339();
+        for status in statuses {
+            let crossed = status
+                .budget
+                .get_thresholds()?
+                .into_iter()
+                .filter(|threshold| status.percent_used >= *threshold)
+                .max();
+            let Some(threshold) = crossed else {
+                continue;
+            };
+            // Lower thresholds crossed at the same time are recorded without
+            // an alert of their own.
+            let mut alerted = false;
+            for threshold in status
+                .budget
+                .get_thresholds()?
+                .into_iter()
+                .filter(|t| *t <= threshold)
+            {
+                let inserted = sqlx::query!(
+                    "INSERT OR IGNORE INTO group_budget_alerts(budget_id, threshold, sent_at) VALUES ($1, $2, $3)",
+                    status.budget.id,
+                    threshold,
+                    time
+                )
+                .execute(pool)
+                .await?;
+                alerted |= inserted.rows_affected() > 0;
+            }
+            if !alerted {
+                continue;
+            }
+            let budget_name = match &status.budget.category {
+                Some(category) => format!(
+                    "{} budget",
+                    Category::get_from_id(category, pool).await?.display_name
+                ),
+                None => "budget".to_string(),
+            };
+            let currency = Currency::get_for_id(pool, &status.budget.currency_id).await?;
+            let format_amount = |amount: i64| {
+                format!(
+                    "{}{}",
+                    currency.symbol,
+                    ((amount as f64) / 10_f64.powi(currency.decimals as i32)) as i64
+                )
+            };
+            for member in members.iter() {
+                notify_user(
+                    member,
+                    &format!("{group_name} has used {threshold}% of its {budget_name}"),
+                    &format!(
+                        "{} of {} spent in {group_name}",
+                        format_amount(status.spent.amount),
+                        format_amount(status.budget.amount)
+                    ),
+                    Some("budget_alert"),
+                )
+                .await;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use async_graphql::SimpleObject;
+use sqlx::SqlitePool;
+
+/// Expense category. System categories have neither `group_id` nor
+/// `user_id`, custom ones belong to a group or to a single user.
+#[derive(SimpleObject)]
+pub struct Category {
+    pub id: String,
+    pub display_name: String,
+    pub icon_key: String,
+    pub group_id: Option<String>,
+    pub user_id: Option<String>,
+    pub created_at: String,
+}
+
+impl Category {
+    /// System categories together with the custom ones `user_id` can use,
+    /// including those of `group_id` if given.
+    pub async fn get_available(
+        user_id: &str,
+        group_id: Option<&str>,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<Category>> {
+        let categories = sqlx::query_as!(
+            Category,
+            r#"
+            SELECT * FROM categories
+            WHERE (group_id IS NULL AND user_id IS NULL)
+                OR user_id = $1
+                OR (group_id = $2 AND $2 IS NOT NULL)
+            ORDER BY group_id IS NOT NULL OR user_id IS NOT NULL, display_name
+            "#,
+            user_id,
+            group_id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(categories)
+    }
+
+    /// Maps what a client sent to the id of an available category. Ids and
+    /// display names are both accepted, ignoring case.
+    pub async fn resolve(
+        category: &str,
+        user_id: &str,
+        group_id: Option<&str>,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<String> {
+        let category = category.trim();
+        Self::get_available(user_id, group_id, pool)
+            .await?
+            .into_iter()
+            .find(|c| {
+                c.id.eq_ignore_ascii_case(category) || c.display_name.eq_ignore_ascii_case(category)
+            })
+            .map(|c| c.id)
+            .ok_or_else(|| anyhow::anyhow!("Unknown category {category}"))
+    }
+
+    pub async fn get_from_id(id: &str, pool: &SqlitePool) -> anyhow::Result<Category> {
+        let category = sqlx::query_as!(Category, "SELECT * FROM categories WHERE id = $1", id)
+            .fetch_one(pool)
+            .await?;
+        Ok(category)
+    }
+
+    pub async fn new_category(
+        display_name: &str,
+        icon_key: &str,
+        group_id: Option<&str>,
+        user_id: Option<&str>,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Category> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let time = chrono::Utc::now().to_rfc3339();
+        let category = sqlx::query_as!(
+            Category,
+            r#"INSERT INTO categories(id, display_name, icon_key, group_id, user_id, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#,
+            id,
+            display_name,
+            icon_key,
+            group_id,
+            user_id,
+            time
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(category)
+    }
+}
@@ -0,0 +1,243 @@
+use async_graphql::{Context, Object};
+use sqlx::SqlitePool;
+
+use crate::{s3::S3, schema::get_pool_from_context};
+
+use super::{expense::Expense, split::Split, user::User};
+
+/// A comment on an expense or a settlement. Replies point at their parent
+/// through `parent_id`, deleted comments stay so threads keep their shape.
+pub struct Comment {
+    pub id: String,
+    pub expense_id: Option<String>,
+    pub split_id: Option<String>,
+    pub parent_id: Option<String>,
+    pub author_id: String,
+    pub body: String,
+    pub image_id: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub deleted_at: Option<String>,
+}
+
+#[Object]
+impl Comment {
+    pub async fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub async fn expense_id(&self) -> &Option<String> {
+        &self.expense_id
+    }
+
+    pub async fn split_id(&self) -> &Option<String> {
+        &self.split_id
+    }
+
+    pub async fn parent_id(&self) -> &Option<String> {
+        &self.parent_id
+    }
+
+    pub async fn author_id(&self) -> &str {
+        &self.author_id
+    }
+
+    pub async fn author<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<User> {
+        let pool = get_pool_from_context(context).await?;
+        User::get_from_id(&self.author_id, pool).await
+    }
+
+    pub async fn body(&self) -> Option<&str> {
+        self.deleted_at.is_none().then_some(self.body.as_str())
+    }
+
+    pub async fn image_id(&self) -> Option<&str> {
+        self.deleted_at
+            .is_none()
+            .then_some(self.image_id.as_deref())
+            .flatten()
+    }
+
+    pub async fn created_at(&self) -> &str {
+        &self.created_at
+    }
+
+    pub async fn updated_at(&self) -> &str {
+        &self.updated_at
+    }
+
+    pub async fn deleted_at(&self) -> &Option<String> {
+        &self.deleted_at
+    }
+}
+
+/// What a comment thread is about.
+pub struct CommentThread {
+    pub group_id: String,
+    pub title: String,
+    /// Everyone with a part in the expense or settlement.
+    pub user_ids: Vec<String>,
+}
+
+impl CommentThread {
+    pub async fn get(
+        expense_id: Option<&str>,
+        split_id: Option<&str>,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<CommentThread> {
+        let mut thread = match (expense_id, split_id) {
+            (Some(expense_id), None) => {
+                let expense = Expense::get_from_id(expense_id, pool).await?;
+                let mut user_ids = vec![expense.created_by.clone()];
+                for split in expense.get_splits(pool).await? {
+                    user_ids.push(split.from_user);
+                    user_ids.push(split.to_user);
+                }
+                for payer in expense.get_payers(pool).await? {
+                    user_ids.push(payer.user_id);
+                }
+                CommentThread {
+                    group_id: expense.group_id,
+                    title: expense.title,
+                    user_ids,
+                }
+            }
+            (None, Some(split_id)) => {
+                let split = Split::get_from_id(split_id, pool).await?;
+                CommentThread {
+                    group_id: split.group_id,
+                    title: "payment".to_string(),
+                    user_ids: vec![split.created_by, split.from_user, split.to_user],
+                }
+            }
+            _ => return Err(anyhow::anyhow!("Must have either expense id or split id")),
+        };
+        thread.user_ids.sort();
+        thread.user_ids.dedup();
+        Ok(thread)
+    }
+}
+
+impl Comment {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new_comment(
+        author_id: &str,
+        expense_id: Option<&str>,
+        split_id: Option<&str>,
+        parent_id: Option<&str>,
+        body: &str,
+        image_id: Option<String>,
+        s3: &S3,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Comment> {
+        if let Some(parent_id) = parent_id {
+            let parent = Self::get_from_id(parent_id, pool).await?;
+            if parent.expense_id.as_deref() != expense_id || parent.split_id.as_deref() != split_id
+            {
+                return Err(anyhow::anyhow!("Reply must be in the same thread"));
+            }
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        let time = chrono::Utc::now().to_rfc3339();
+        let mut transaction = pool.begin().await?;
+        let comment = sqlx::query_as!(
+            Comment,
+            r#"INSERT INTO comments(id, expense_id, split_id, parent_id, author_id, body, image_id, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $8)
+            RETURNING *
+            "#,
+            id,
+            expense_id,
+            split_id,
+            parent_id,
+            author_id,
+            body,
+            image_id,
+            time
+        )
+        .fetch_one(transaction.as_mut())
+        .await?;
+        if let Some(image_id) = &comment.image_id {
+            s3.move_to_be(image_id).await?;
+        }
+        transaction.commit().await?;
+        Ok(comment)
+    }
+
+    pub async fn edit_comment(
+        comment_id: &str,
+        body: Option<&str>,
+        image_id: Option<String>,
+        s3: &S3,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Comment> {
+        let old_comment = Self::get_from_id(comment_id, pool).await?;
+        let time = chrono::Utc::now().to_rfc3339();
+        let mut transaction = pool.begin().await?;
+        let comment = sqlx::query_as!(
+            Comment,
+            r#"UPDATE comments SET
+                body = COALESCE($2, body),
+                image_id = COALESCE($3, image_id),
+                updated_at = $4
+            WHERE id = $1 AND deleted_at IS NULL
+            RETURNING *
+            "#,
+            comment_id,
+            body,
+            image_id,
+            time
+        )
+        .fetch_optional(transaction.as_mut())
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Comment is deleted"))?;
+        if let Some(image_id) = &comment.image_id {
+            if old_comment.image_id.as_ref() != Some(image_id) {
+                s3.move_to_be(image_id).await?;
+            }
+        }
+        transaction.commit().await?;
+        Ok(comment)
+    }
+
+    pub async fn delete_comment(comment_id: &str, pool: &SqlitePool) -> anyhow::Result<Comment> {
+        let time = chrono::Utc::now().to_rfc3339();
+        let comment = sqlx::query_as!(
+            Comment,
+            r#"UPDATE comments SET deleted_at = $2, updated_at = $2
+            WHERE id = $1 AND deleted_at IS NULL
+            RETURNING *
+            "#,
+            comment_id,
+            time
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Comment already deleted"))?;
+        Ok(comment)
+    }
+
+    pub async fn get_from_id(id: &str, pool: &SqlitePool) -> anyhow::Result<Comment> {
+        let comment = sqlx::query_as!(Comment, "SELECT * FROM comments WHERE id = $1", id)
+            .fetch_one(pool)
+            .await?;
+        Ok(comment)
+    }
+
+    /// All comments of a thread, oldest first.
+    pub async fn get_for_thread(
+        expense_id: Option<&str>,
+        split_id: Option<&str>,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<Comment>> {
+        let comments = sqlx::query_as!(
+            Comment,
+            "SELECT * FROM comments WHERE expense_id IS $1 AND split_id IS $2 ORDER BY created_at",
+            expense_id,
+            split_id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(comments)
+    }
+}
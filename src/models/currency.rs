@@ -30,6 +30,7 @@ use serde::Deserialize;
 
 use crate::REQWEST_CLIENT;
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct FreeCurrency {
     symbol: String,
@@ -1,19 +1,37 @@
-use async_graphql::{Context, Object};
+use std::collections::BTreeMap;
+
+use async_graphql::{Context, InputObject, Object};
 use chrono::TimeZone;
+use serde::Serialize;
 use sqlx::{Sqlite, SqlitePool, Transaction};
 
 use crate::{
+    notification::send_message_notification_with_retry,
     s3::S3,
-    schema::{get_pool_from_context, mutation::SplitInput},
+    schema::{
+        get_pool_from_context,
+        mutation::{PayerInput, SplitInput},
+        DateTimeValidator, IdValidator,
+    },
 };
 
 use super::{
     amount::Amount,
+    category::Category,
+    comment::Comment,
+    currency::Currency,
+    expense_item::{ExpenseItem, ReceiptCharges, ReceiptInput},
     group::Group,
+    revision::Revision,
     split::{Split, TransactionType},
+    split_strategy::{allocate, checked_sum, SplitStrategy},
     user::User,
 };
 
+/// How long a deleted expense can still be restored.
+pub const RESTORE_WINDOW_DAYS: i64 = 30;
+
+#[derive(Serialize)]
 pub struct Expense {
     pub id: String,
     pub title: String,
@@ -33,6 +51,10 @@ pub struct Expense {
 
     pub updated_at: String,
     pub transaction_at: String,
+
+    pub split_strategy: Option<String>,
+
+    pub deleted_at: Option<String>,
 }
 
 #[Object]
@@ -58,6 +80,14 @@ impl Expense {
         &self.category
     }
 
+    pub async fn category_details<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+    ) -> anyhow::Result<Category> {
+        let pool = get_pool_from_context(context).await?;
+        Category::get_from_id(&self.category, pool).await
+    }
+
     pub async fn creator_id(&self) -> &str {
         &self.created_by
     }
@@ -74,6 +104,34 @@ impl Expense {
         }
     }
 
+    pub async fn payers<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Vec<ExpensePayer>> {
+        let pool = get_pool_from_context(context).await?;
+        self.get_payers(pool).await
+    }
+
+    pub async fn items<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Vec<ExpenseItem>> {
+        let pool = get_pool_from_context(context).await?;
+        ExpenseItem::get_for_expense(&self.id, pool).await
+    }
+
+    pub async fn receipt_charges<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+    ) -> anyhow::Result<Option<ReceiptCharges>> {
+        let pool = get_pool_from_context(context).await?;
+        ReceiptCharges::get_for_expense(&self.id, pool).await
+    }
+
+    pub async fn comments<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Vec<Comment>> {
+        let pool = get_pool_from_context(context).await?;
+        Comment::get_for_thread(Some(&self.id), None, pool).await
+    }
+
+    pub async fn history<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Vec<Revision>> {
+        let pool = get_pool_from_context(context).await?;
+        Revision::get_for_expense(&self.id, pool).await
+    }
+
     pub async fn splits<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Vec<Split>> {
         let pool = get_pool_from_context(context).await?;
 
@@ -95,6 +153,17 @@ impl Expense {
     pub async fn transaction_at(&self) -> &str {
         &self.transaction_at
     }
+
+    pub async fn split_strategy(&self) -> anyhow::Result<Option<SplitStrategy>> {
+        self.split_strategy
+            .as_deref()
+            .map(SplitStrategy::from_json)
+            .transpose()
+    }
+
+    pub async fn deleted_at(&self) -> &Option<String> {
+        &self.deleted_at
+    }
 }
 
 impl Expense {
@@ -105,6 +174,9 @@ impl Expense {
         group_id: &str,
         amount: &Amount,
         splits: Vec<SplitInput>,
+        payers: Vec<PayerInput>,
+        split_strategy: Option<&SplitStrategy>,
+        receipt: Option<&ReceiptInput>,
         category: &str,
         note: Option<String>,
         image_id: Option<String>,
@@ -113,10 +185,52 @@ impl Expense {
         pool: &SqlitePool,
     ) -> anyhow::Result<Expense> {
         let mut transaction = pool.begin().await?;
+        let expense = Self::insert_new_expense(
+            user_id,
+            title,
+            group_id,
+            amount,
+            splits,
+            payers,
+            split_strategy,
+            receipt,
+            category,
+            note,
+            image_id,
+            transaction_time,
+            s3,
+            &mut transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(expense)
+    }
+
+    /// Like [`Expense::new_expense`], for callers that need more writes to
+    /// commit together with the expense.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert_new_expense<'a>(
+        user_id: &str,
+        title: &str,
+        group_id: &str,
+        amount: &Amount,
+        splits: Vec<SplitInput>,
+        payers: Vec<PayerInput>,
+        split_strategy: Option<&SplitStrategy>,
+        receipt: Option<&ReceiptInput>,
+        category: &str,
+        note: Option<String>,
+        image_id: Option<String>,
+        transaction_time: Option<String>,
+        s3: &S3,
+        transaction: &mut Transaction<'a, Sqlite>,
+    ) -> anyhow::Result<Expense> {
+        let split_strategy = split_strategy.map(|s| s.to_json()).transpose()?;
         let id = uuid::Uuid::new_v4().to_string();
         let time = chrono::Utc::now().to_rfc3339();
         let transaction_at = transaction_time
-            .and_then(|time| chrono::DateTime::parse_from_rfc3339(&time).ok())
+            .map(|time| chrono::DateTime::parse_from_rfc3339(&time))
+            .transpose()?
             .map(|time| {
                 chrono::Utc
                     .from_utc_datetime(&time.naive_utc())
@@ -125,10 +239,10 @@ impl Expense {
             .unwrap_or(time.clone());
         let expense = sqlx::query_as!(
             Expense,
-            r#"INSERT INTO expenses(id, title, created_at, updated_at, transaction_at, created_by, group_id, amount, currency_id, category, note, image_id)
-            VALUES ($1, $2, $3, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            r#"INSERT INTO expenses(id, title, created_at, updated_at, transaction_at, created_by, group_id, amount, currency_id, category, note, image_id, split_strategy)
+            VALUES ($1, $2, $3, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
             RETURNING
-            id as "id!", title as "title!", created_at as "created_at!", created_by as "created_by!", group_id as "group_id!", amount as "amount!", currency_id as "currency_id!", category as "category!", note, image_id, updated_at, transaction_at
+            id as "id!", title as "title!", created_at as "created_at!", created_by as "created_by!", group_id as "group_id!", amount as "amount!", currency_id as "currency_id!", category as "category!", note, image_id, updated_at, transaction_at, split_strategy, deleted_at
             "#,
             id,
             title,
@@ -140,104 +254,489 @@ impl Expense {
             amount.currency_id,
             category,
             note,
-            image_id
+            image_id,
+            split_strategy
         ).fetch_one(transaction.as_mut()).await?;
-        let ttype = TransactionType::ExpenseSplit.to_string();
-
-        for split in splits.iter() {
-            let id = uuid::Uuid::new_v4().to_string();
-
-            let _data = sqlx::query!("
-                INSERT INTO split_transactions(id,expense_id,amount,currency_id,from_user,to_user,transaction_type,created_at,updated_at, transaction_at, created_by, group_id)
-                VALUES ($1, $2, $3,$4,$5,$6,$7, $8,$8,$9, $10,$11)
-            ",
-            id,
-            expense.id,
-            split.amount,
-            amount.currency_id,
-            split.user_id,
-            user_id,
-            ttype,
-            time,
-            transaction_at,
-            user_id,
-            group_id,
-        ).execute(transaction.as_mut()).await.map_err(|e|
-       { log::warn!("FAILED {e:#?} VALUES id:{} expense:{} split_amount:{} userid:{} split_user:{}, amount:{}",
-       id,
-       expense.id,
-       split.amount,
-       user_id,
-       split.user_id,
-       0
-    );
-            e}
-        )?;
+        Revision::record_expense(user_id, None, Some(&expense), transaction).await?;
+        Self::insert_expense_splits(&expense, &splits, &payers, user_id, transaction).await?;
+        if let Some(receipt) = receipt {
+            receipt.insert(&expense.id, transaction).await?;
         }
         if let Some(image_id) = image_id {
             s3.move_to_be(&image_id).await?;
         }
+        Ok(expense)
+    }
+
+    /// Updates the given fields of an expense, rebuilding its splits when
+    /// `splits` and `payers` are given. Changing the amount or currency
+    /// requires new splits since the old ones are denominated in the old
+    /// values. New splits also replace the saved `split_strategy` and receipt
+    /// items. An empty `note` clears it.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn edit_expense(
+        expense_id: &str,
+        actor_id: &str,
+        title: Option<&str>,
+        amount: Option<i64>,
+        currency_id: Option<String>,
+        splits: Option<Vec<SplitInput>>,
+        payers: Option<Vec<PayerInput>>,
+        split_strategy: Option<&SplitStrategy>,
+        receipt: Option<&ReceiptInput>,
+        category: Option<String>,
+        note: Option<String>,
+        image_id: Option<String>,
+        transaction_time: Option<String>,
+        s3: &S3,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Expense> {
+        if (amount.is_some() || currency_id.is_some()) && splits.is_none() {
+            return Err(anyhow::anyhow!(
+                "Must have split with amount and currency id"
+            ));
+        }
+        if splits.is_some() != payers.is_some() {
+            return Err(anyhow::anyhow!("Must have both splits and payers"));
+        }
+        let replace_strategy = splits.is_some();
+        let split_strategy = split_strategy.map(|s| s.to_json()).transpose()?;
+        let mut transaction = pool.begin().await?;
+        let old_expense =
+            sqlx::query_as!(Expense, "SELECT * from expenses WHERE id = $1", expense_id)
+                .fetch_one(transaction.as_mut())
+                .await?;
+        let update_time = chrono::Utc::now().to_rfc3339();
+        let transaction_at = transaction_time
+            .map(|time| chrono::DateTime::parse_from_rfc3339(&time))
+            .transpose()?
+            .map(|time| {
+                chrono::Utc
+                    .from_utc_datetime(&time.naive_utc())
+                    .to_rfc3339()
+            });
+        let expense = sqlx::query_as!(
+            Expense,
+            r#"UPDATE expenses SET
+                title = COALESCE($2, title),
+                amount = COALESCE($3, amount),
+                currency_id = COALESCE($4, currency_id),
+                category = COALESCE($5, category),
+                note = CASE WHEN $6 IS NULL THEN note ELSE NULLIF($6, '') END,
+                image_id = COALESCE($7, image_id),
+                transaction_at = COALESCE($8, transaction_at),
+                updated_at = $9,
+                split_strategy = CASE WHEN $10 THEN $11 ELSE split_strategy END
+            WHERE id = $1
+            RETURNING
+            id as "id!", title as "title!", created_at as "created_at!", created_by as "created_by!", group_id as "group_id!", amount as "amount!", currency_id as "currency_id!", category as "category!", note, image_id, updated_at, transaction_at, split_strategy, deleted_at
+            "#,
+            expense_id,
+            title,
+            amount,
+            currency_id,
+            category,
+            note,
+            image_id,
+            transaction_at,
+            update_time,
+            replace_strategy,
+            split_strategy
+        )
+        .fetch_one(transaction.as_mut())
+        .await?;
+        Revision::record_expense(
+            actor_id,
+            Some(&old_expense),
+            Some(&expense),
+            &mut transaction,
+        )
+        .await?;
+
+        if let (Some(splits), Some(payers)) = (splits, payers) {
+            Self::edit_expense_splits(
+                &expense,
+                splits,
+                payers,
+                receipt,
+                actor_id,
+                &mut transaction,
+            )
+            .await?;
+        } else if expense.transaction_at != old_expense.transaction_at {
+            let old_splits = Self::get_live_splits(&expense.id, &mut transaction).await?;
+            let new_splits = sqlx::query_as!(
+                Split,
+                "UPDATE split_transactions SET transaction_at = $2, updated_at = $3 WHERE expense_id = $1 AND deleted_at IS NULL RETURNING *",
+                expense.id,
+                expense.transaction_at,
+                update_time
+            )
+            .fetch_all(transaction.as_mut())
+            .await?;
+            Revision::record_splits(actor_id, &old_splits, &new_splits, &mut transaction).await?;
+        }
+        if let Some(image_id) = &expense.image_id {
+            if old_expense.image_id.as_ref() != Some(image_id) {
+                s3.move_to_be(image_id).await?;
+            }
+        }
         transaction.commit().await?;
         Ok(expense)
     }
 
+    /// Tells everyone with a share in `splits` about a newly added expense.
+    pub async fn notify_new_expense(
+        &self,
+        creator: &User,
+        splits: &[SplitInput],
+        single_payer: bool,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<()> {
+        let currency = Currency::get_for_id(pool, &self.currency_id).await?;
+        let group = Group::get_from_id(&self.group_id, pool).await?;
+        for split in splits.iter() {
+            let to_user_model = User::get_from_id(&split.user_id, pool).await;
+            if let Ok(to_user_model) = to_user_model {
+                if let Some(token) = to_user_model.notification_token {
+                    let share =
+                        ((split.amount as f64) / 10_f64.powi(currency.decimals as i32)) as i64;
+                    let group_name = group.name.as_deref().unwrap_or("Direct Payment");
+                    let description = if single_payer {
+                        format!(
+                            "you owe {}{} to {} in group {}",
+                            currency.symbol,
+                            share,
+                            creator.name.as_ref().unwrap_or(&"Someone".to_string()),
+                            group_name
+                        )
+                    } else {
+                        format!(
+                            "you owe {}{} for {} in group {}",
+                            currency.symbol, share, self.title, group_name
+                        )
+                    };
+                    if let Err(err) = send_message_notification_with_retry(
+                        format!(
+                            "{} added expense {}",
+                            creator.name.as_ref().unwrap_or(&"Someone".to_string()),
+                            self.title,
+                        )
+                        .as_str(),
+                        "/",
+                        "https://billdivide.app/",
+                        description.as_str(),
+                        &token,
+                        Some("new_expense"),
+                    )
+                    .await
+                    {
+                        log::warn!("Failed to send notification {err:?}")
+                    } else {
+                        log::info!("Notification sent")
+                    }
+                } else {
+                    log::info!("Skipping notification, no token")
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces all splits, payers and receipt items of `expense`.
     pub async fn edit_expense_splits<'a>(
-        expense_id: &str,
+        expense: &Expense,
         splits: Vec<SplitInput>,
-        user_id: &str,
-        s3: &S3,
+        payers: Vec<PayerInput>,
+        receipt: Option<&ReceiptInput>,
+        actor_id: &str,
         transaction: &mut Transaction<'a, Sqlite>,
     ) -> anyhow::Result<()> {
-        let expense = sqlx::query_as!(Expense, "SELECT * from expenses WHERE id = $1", expense_id)
-            .fetch_one(transaction.as_mut())
-            .await?;
         let old_splits = sqlx::query_as!(
             Split,
-            "SELECT * from split_transactions WHERE expense_id = $1",
-            expense_id
+            "DELETE from split_transactions WHERE expense_id = $1 AND deleted_at IS NULL RETURNING *",
+            expense.id
         )
         .fetch_all(transaction.as_mut())
         .await?;
-        let update_time = chrono::Utc::now().to_rfc3339();
-        let delete_old_splits = sqlx::query!(
-            "DELETE from split_transactions WHERE expense_id = $1",
-            expense_id
+        Revision::record_splits(actor_id, &old_splits, &[], transaction).await?;
+        sqlx::query!(
+            "DELETE from expense_payers WHERE expense_id = $1",
+            expense.id
         )
         .execute(transaction.as_mut())
         .await?;
+        ExpenseItem::delete_for_expense(&expense.id, transaction).await?;
+        Self::insert_expense_splits(expense, &splits, &payers, actor_id, transaction).await?;
+        if let Some(receipt) = receipt {
+            receipt.insert(&expense.id, transaction).await?;
+        }
+        Ok(())
+    }
+
+    /// Records who paid for `expense` and what everyone owes the payers.
+    /// `splits` are the shares of everyone except the creator, whose own
+    /// share is whatever is left of the amount.
+    async fn insert_expense_splits<'a>(
+        expense: &Expense,
+        splits: &[SplitInput],
+        payers: &[PayerInput],
+        actor_id: &str,
+        transaction: &mut Transaction<'a, Sqlite>,
+    ) -> anyhow::Result<()> {
+        for payer in payers.iter() {
+            let id = uuid::Uuid::new_v4().to_string();
+            sqlx::query!(
+                "INSERT INTO expense_payers(id, expense_id, user_id, amount) VALUES ($1, $2, $3, $4)",
+                id,
+                expense.id,
+                payer.user_id,
+                payer.amount
+            )
+            .execute(transaction.as_mut())
+            .await?;
+        }
         let ttype = TransactionType::ExpenseSplit.to_string();
+        let mut new_splits = vec![];
 
-        for split in splits.iter() {
+        for obligation in payer_obligations(expense, splits, payers)?.iter() {
             let id = uuid::Uuid::new_v4().to_string();
 
-            let _data = sqlx::query!("
-                INSERT INTO split_transactions(id,expense_id,amount,currency_id,from_user,to_user,transaction_type,created_at,updated_at, transaction_at, created_by, group_id, updated_at)
-                VALUES ($1, $2, $3,$4,$5,$6,$7, $8,$8,$8, $9,$10, $11)
+            let split = sqlx::query_as!(Split, "
+                INSERT INTO split_transactions(id,expense_id,amount,currency_id,from_user,to_user,transaction_type,created_at,updated_at, transaction_at, created_by, group_id)
+                VALUES ($1, $2, $3,$4,$5,$6,$7, $8,$9,$10, $11,$12)
+                RETURNING *
                 ",
                 id,
                 expense.id,
-                split.amount,
+                obligation.amount,
                 expense.currency_id,
-                split.user_id,
-                user_id,
+                obligation.from_user,
+                obligation.to_user,
                 ttype,
                 expense.created_at,
+                expense.updated_at,
+                expense.transaction_at,
                 expense.created_by,
                 expense.group_id,
-                update_time
-            ).execute(transaction.as_mut()).await.map_err(|e|
+            ).fetch_one(transaction.as_mut()).await.map_err(|e|
             { log::warn!("FAILED {e:#?} VALUES id:{} expense:{} split_amount:{} userid:{} split_user:{}, amount:{}",
                     id,
                     expense.id,
-                    split.amount,
-                    user_id,
-                    split.user_id,
-                    0,
+                    obligation.amount,
+                    obligation.to_user,
+                    obligation.from_user,
+                    expense.amount,
                     );
                 e}
             )?;
+            new_splits.push(split);
         }
-        Ok(())
+        Revision::record_splits(actor_id, &[], &new_splits, transaction).await
+    }
+
+    async fn get_live_splits<'a>(
+        expense_id: &str,
+        transaction: &mut Transaction<'a, Sqlite>,
+    ) -> anyhow::Result<Vec<Split>> {
+        let splits = sqlx::query_as!(
+            Split,
+            "SELECT * FROM split_transactions WHERE expense_id = $1 AND deleted_at IS NULL",
+            expense_id
+        )
+        .fetch_all(transaction.as_mut())
+        .await?;
+        Ok(splits)
+    }
+
+    pub async fn get_payers(&self, pool: &SqlitePool) -> anyhow::Result<Vec<ExpensePayer>> {
+        let payers = sqlx::query!(
+            "SELECT user_id, amount FROM expense_payers WHERE expense_id = $1",
+            self.id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|payer| ExpensePayer {
+            user_id: payer.user_id,
+            amount: Amount {
+                amount: payer.amount,
+                currency_id: self.currency_id.clone(),
+            },
+        })
+        .collect::<Vec<_>>();
+        if payers.is_empty() {
+            // Expenses from before multiple payers were paid by their creator.
+            Ok(vec![ExpensePayer {
+                user_id: self.created_by.clone(),
+                amount: Amount {
+                    amount: self.amount,
+                    currency_id: self.currency_id.clone(),
+                },
+            }])
+        } else {
+            Ok(payers)
+        }
+    }
+
+    /// Tombstones the expense along with its splits so they drop out of
+    /// balances and listings. Splits keep the same `deleted_at` as the
+    /// expense, which is how [`Expense::restore_expense`] finds them again.
+    pub async fn delete_expense(
+        expense_id: &str,
+        actor_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Expense> {
+        let mut transaction = pool.begin().await?;
+        let time = chrono::Utc::now().to_rfc3339();
+        let old_expense =
+            sqlx::query_as!(Expense, "SELECT * FROM expenses WHERE id = $1", expense_id)
+                .fetch_one(transaction.as_mut())
+                .await?;
+        let expense = sqlx::query_as!(
+            Expense,
+            r#"UPDATE expenses SET deleted_at = $2, updated_at = $2
+            WHERE id = $1 AND deleted_at IS NULL
+            RETURNING
+            id as "id!", title as "title!", created_at as "created_at!", created_by as "created_by!", group_id as "group_id!", amount as "amount!", currency_id as "currency_id!", category as "category!", note, image_id, updated_at, transaction_at, split_strategy, deleted_at
+            "#,
+            expense_id,
+            time
+        )
+        .fetch_optional(transaction.as_mut())
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Expense already deleted"))?;
+        Revision::record_expense(
+            actor_id,
+            Some(&old_expense),
+            Some(&expense),
+            &mut transaction,
+        )
+        .await?;
+        let old_splits = Self::get_live_splits(expense_id, &mut transaction).await?;
+        let new_splits = sqlx::query_as!(
+            Split,
+            "UPDATE split_transactions SET deleted_at = $2, updated_at = $2 WHERE expense_id = $1 AND deleted_at IS NULL RETURNING *",
+            expense_id,
+            time
+        )
+        .fetch_all(transaction.as_mut())
+        .await?;
+        Revision::record_splits(actor_id, &old_splits, &new_splits, &mut transaction).await?;
+        transaction.commit().await?;
+        Ok(expense)
+    }
+
+    pub async fn restore_expense(
+        expense_id: &str,
+        actor_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Expense> {
+        let mut transaction = pool.begin().await?;
+        let expense = sqlx::query_as!(Expense, "SELECT * FROM expenses WHERE id = $1", expense_id)
+            .fetch_one(transaction.as_mut())
+            .await?;
+        let Some(deleted_at) = &expense.deleted_at else {
+            return Err(anyhow::anyhow!("Expense is not deleted"));
+        };
+        let deleted_at = chrono::DateTime::parse_from_rfc3339(deleted_at)?;
+        if chrono::Utc::now().signed_duration_since(deleted_at)
+            > chrono::Duration::days(RESTORE_WINDOW_DAYS)
+        {
+            return Err(anyhow::anyhow!("Expense can no longer be restored"));
+        }
+        let time = chrono::Utc::now().to_rfc3339();
+        let old_splits = sqlx::query_as!(
+            Split,
+            "SELECT * FROM split_transactions WHERE expense_id = $1 AND deleted_at = $2",
+            expense_id,
+            expense.deleted_at
+        )
+        .fetch_all(transaction.as_mut())
+        .await?;
+        let new_splits = sqlx::query_as!(
+            Split,
+            "UPDATE split_transactions SET deleted_at = NULL, updated_at = $3 WHERE expense_id = $1 AND deleted_at = $2 RETURNING *",
+            expense_id,
+            expense.deleted_at,
+            time
+        )
+        .fetch_all(transaction.as_mut())
+        .await?;
+        Revision::record_splits(actor_id, &old_splits, &new_splits, &mut transaction).await?;
+        let old_expense = expense;
+        let expense = sqlx::query_as!(
+            Expense,
+            r#"UPDATE expenses SET deleted_at = NULL, updated_at = $2
+            WHERE id = $1
+            RETURNING
+            id as "id!", title as "title!", created_at as "created_at!", created_by as "created_by!", group_id as "group_id!", amount as "amount!", currency_id as "currency_id!", category as "category!", note, image_id, updated_at, transaction_at, split_strategy, deleted_at
+            "#,
+            expense_id,
+            time
+        )
+        .fetch_one(transaction.as_mut())
+        .await?;
+        Revision::record_expense(
+            actor_id,
+            Some(&old_expense),
+            Some(&expense),
+            &mut transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(expense)
+    }
+
+    /// Full text search over title, note and comments of expenses in groups
+    /// `user_id` belongs to, best matches first.
+    pub async fn search(
+        user_id: &str,
+        query: &str,
+        filter: &ExpenseSearchFilter,
+        limit: u32,
+        offset: u32,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<Expense>> {
+        let query = fts_query(query).ok_or_else(|| anyhow::anyhow!("Search query is empty"))?;
+        let from_time = filter.from_time.as_deref().map(to_utc).transpose()?;
+        let to_time = filter.to_time.as_deref().map(to_utc).transpose()?;
+        let expenses = sqlx::query_as!(
+            Expense,
+            r#"SELECT
+            e.id as "id!", e.title as "title!", e.created_at as "created_at!", e.created_by as "created_by!", e.group_id as "group_id!", e.amount as "amount!", e.currency_id as "currency_id!", e.category as "category!", e.note, e.image_id, e.updated_at as "updated_at!", e.transaction_at as "transaction_at!", e.split_strategy, e.deleted_at
+            FROM expense_search JOIN expenses e ON e.id = expense_search.expense_id
+            WHERE expense_search MATCH $2
+                AND e.deleted_at IS NULL
+                AND e.group_id IN (SELECT group_id FROM group_memberships WHERE user_id = $1)
+                AND ($3 IS NULL OR e.group_id = $3)
+                AND ($4 IS NULL OR e.created_by = $4 OR EXISTS (
+                    SELECT 1 FROM split_transactions st
+                    WHERE st.expense_id = e.id AND st.deleted_at IS NULL AND (st.from_user = $4 OR st.to_user = $4)
+                ))
+                AND ($5 IS NULL OR e.category = $5)
+                AND ($6 IS NULL OR e.amount >= $6)
+                AND ($7 IS NULL OR e.amount <= $7)
+                AND ($8 IS NULL OR e.transaction_at >= $8)
+                AND ($9 IS NULL OR e.transaction_at <= $9)
+            ORDER BY bm25(expense_search, 0.0, 10.0, 5.0, 1.0), e.transaction_at DESC
+            LIMIT $10 OFFSET $11
+            "#,
+            user_id,
+            query,
+            filter.group_id,
+            filter.with_user,
+            filter.category,
+            filter.min_amount,
+            filter.max_amount,
+            from_time,
+            to_time,
+            limit,
+            offset
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(expenses)
     }
 
     pub async fn get_from_id(id: &str, pool: &SqlitePool) -> anyhow::Result<Expense> {
@@ -250,11 +749,140 @@ impl Expense {
     pub async fn get_splits(&self, pool: &SqlitePool) -> anyhow::Result<Vec<Split>> {
         let splits = sqlx::query_as!(
             Split,
-            "SELECT * FROM split_transactions WHERE expense_id=$1",
-            self.id
+            "SELECT * FROM split_transactions WHERE expense_id=$1 AND deleted_at IS $2",
+            self.id,
+            self.deleted_at
         )
         .fetch_all(pool)
         .await?;
         Ok(splits)
     }
 }
+
+pub struct ExpensePayer {
+    pub user_id: String,
+    pub amount: Amount,
+}
+
+#[Object]
+impl ExpensePayer {
+    pub async fn user_id(&self) -> &str {
+        &self.user_id
+    }
+
+    pub async fn user<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<User> {
+        let pool = get_pool_from_context(context).await?;
+        User::get_from_id(&self.user_id, pool).await
+    }
+
+    pub async fn amount(&self) -> &Amount {
+        &self.amount
+    }
+}
+
+struct Obligation {
+    from_user: String,
+    to_user: String,
+    amount: i64,
+}
+
+/// Works out what each participant owes each payer. Every share is owed to
+/// the payers in proportion to what they paid, and obligations between the
+/// same two people are netted so only one of them owes the other.
+fn payer_obligations(
+    expense: &Expense,
+    splits: &[SplitInput],
+    payers: &[PayerInput],
+) -> anyhow::Result<Vec<Obligation>> {
+    if let [payer] = payers {
+        if payer.user_id == expense.created_by {
+            return Ok(splits
+                .iter()
+                .map(|split| Obligation {
+                    from_user: split.user_id.clone(),
+                    to_user: payer.user_id.clone(),
+                    amount: split.amount,
+                })
+                .collect());
+        }
+    }
+    let creator_share = expense
+        .amount
+        .checked_sub(checked_sum(splits.iter().map(|split| split.amount))?)
+        .ok_or_else(|| anyhow::anyhow!("Amounts are too large"))?;
+    if creator_share < 0 {
+        return Err(anyhow::anyhow!("Splits can not exceed total"));
+    }
+    let weights = payers.iter().map(|payer| payer.amount).collect::<Vec<_>>();
+    let mut owed = BTreeMap::new();
+    let shares = splits
+        .iter()
+        .map(|split| (split.user_id.as_str(), split.amount))
+        .chain(std::iter::once((
+            expense.created_by.as_str(),
+            creator_share,
+        )));
+    for (user_id, share) in shares {
+        for (payer, amount) in payers.iter().zip(allocate(share, &weights)) {
+            if payer.user_id == user_id || amount == 0 {
+                continue;
+            }
+            // Keyed by the ordered pair so both directions land on one entry.
+            if user_id < payer.user_id.as_str() {
+                *owed.entry((user_id, payer.user_id.as_str())).or_insert(0) += amount;
+            } else {
+                *owed.entry((payer.user_id.as_str(), user_id)).or_insert(0) -= amount;
+            }
+        }
+    }
+    Ok(owed
+        .into_iter()
+        .filter(|(_, amount)| *amount != 0)
+        .map(|((first, second), amount)| {
+            let (from_user, to_user) = if amount > 0 {
+                (first, second)
+            } else {
+                (second, first)
+            };
+            Obligation {
+                from_user: from_user.to_string(),
+                to_user: to_user.to_string(),
+                amount: amount.abs(),
+            }
+        })
+        .collect())
+}
+
+#[derive(InputObject, Default)]
+pub struct ExpenseSearchFilter {
+    #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))]
+    pub group_id: Option<String>,
+    /// Only expenses this user is part of.
+    #[graphql(validator(custom = r#"IdValidator::new("with_user")"#))]
+    pub with_user: Option<String>,
+    #[graphql(validator(max_length = 100))]
+    pub category: Option<String>,
+    pub min_amount: Option<i64>,
+    pub max_amount: Option<i64>,
+    #[graphql(validator(custom = r#"DateTimeValidator::new("from_time")"#))]
+    pub from_time: Option<String>,
+    #[graphql(validator(custom = r#"DateTimeValidator::new("to_time")"#))]
+    pub to_time: Option<String>,
+}
+
+/// Turns user input into an FTS5 query where every word has to match as a
+/// prefix, so operators and stray quotes in the input are never parsed.
+fn fts_query(query: &str) -> Option<String> {
+    let terms = query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"*", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+fn to_utc(time: &str) -> anyhow::Result<String> {
+    let time = chrono::DateTime::parse_from_rfc3339(time)?;
+    Ok(chrono::Utc
+        .from_utc_datetime(&time.naive_utc())
+        .to_rfc3339())
+}
@@ -0,0 +1,309 @@
+use std::collections::BTreeMap;
+
+use async_graphql::{Context, InputObject, Object, SimpleObject};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::schema::{get_pool_from_context, mutation::SplitInput, IdValidator};
+
+use super::{
+    amount::Amount,
+    split_strategy::{allocate, checked_sum},
+    user::User,
+};
+
+#[derive(InputObject, Clone)]
+pub struct ExpenseItemInput {
+    #[graphql(validator(min_length = 1, max_length = 100))]
+    pub name: String,
+    #[graphql(default = 1)]
+    pub quantity: i64,
+    pub unit_price: i64,
+    #[graphql(validator(list, custom = r#"IdValidator::new("user_ids")"#))]
+    pub user_ids: Vec<String>,
+}
+
+/// Line items of a receipt. Tax, service charge and tip are shared in
+/// proportion to what everyone had.
+#[derive(InputObject, Clone)]
+pub struct ReceiptInput {
+    pub items: Vec<ExpenseItemInput>,
+    #[graphql(default)]
+    pub tax: i64,
+    #[graphql(default)]
+    pub service_charge: i64,
+    #[graphql(default)]
+    pub tip: i64,
+}
+
+impl ReceiptInput {
+    /// Sum of all items and charges, which must match the expense amount.
+    pub fn total(&self) -> anyhow::Result<i64> {
+        self.subtotal()?
+            .checked_add(self.charges()?)
+            .ok_or_else(|| anyhow::anyhow!("Amounts are too large"))
+    }
+
+    fn subtotal(&self) -> anyhow::Result<i64> {
+        checked_sum(
+            self.items
+                .iter()
+                .map(item_total)
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        )
+    }
+
+    fn charges(&self) -> anyhow::Result<i64> {
+        checked_sum([self.tax, self.service_charge, self.tip])
+    }
+
+    /// Divides the receipt between everyone with an item. Every item is split
+    /// equally between its users, then the charges are spread over the
+    /// subtotals using the same largest remainder rounding as split strategies.
+    pub fn resolve(&self) -> anyhow::Result<Vec<SplitInput>> {
+        if self.items.is_empty() {
+            return Err(anyhow::anyhow!("Receipt must have at least one item"));
+        }
+        if self.tax < 0 || self.service_charge < 0 || self.tip < 0 {
+            return Err(anyhow::anyhow!("Charges can not be negative"));
+        }
+        // Every share below is at most the total, so none of them overflow
+        // once it is known to fit.
+        self.total()?;
+        let charges = self.charges()?;
+        if self.subtotal()? == 0 && charges != 0 {
+            return Err(anyhow::anyhow!("Charges need items with a price"));
+        }
+        let mut subtotals = BTreeMap::new();
+        for item in self.items.iter() {
+            let total = item_total(item)?;
+            let mut user_ids = item.user_ids.clone();
+            user_ids.sort();
+            user_ids.dedup();
+            if user_ids.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Item {} is not assigned to anyone",
+                    item.name
+                ));
+            }
+            if user_ids.len() != item.user_ids.len() {
+                return Err(anyhow::anyhow!("Item {} has duplicate users", item.name));
+            }
+            let shares = allocate(total, &vec![1; user_ids.len()]);
+            for (user_id, share) in user_ids.into_iter().zip(shares) {
+                *subtotals.entry(user_id).or_insert(0) += share;
+            }
+        }
+        let weights = subtotals.values().copied().collect::<Vec<_>>();
+        let charges = allocate(charges, &weights);
+        Ok(subtotals
+            .into_iter()
+            .zip(charges)
+            .map(|((user_id, subtotal), charge)| SplitInput {
+                user_id,
+                amount: subtotal + charge,
+            })
+            .collect())
+    }
+
+    /// Like [`ReceiptInput::resolve`], but leaves out the payer's own share.
+    pub fn resolve_owed_to(&self, payer_id: &str) -> anyhow::Result<Vec<SplitInput>> {
+        Ok(self
+            .resolve()?
+            .into_iter()
+            .filter(|split| split.user_id != payer_id)
+            .collect())
+    }
+
+    pub async fn insert<'a>(
+        &self,
+        expense_id: &str,
+        transaction: &mut Transaction<'a, Sqlite>,
+    ) -> anyhow::Result<()> {
+        for (position, item) in self.items.iter().enumerate() {
+            let id = uuid::Uuid::new_v4().to_string();
+            let position = position as i64;
+            sqlx::query!(
+                "INSERT INTO expense_items(id, expense_id, name, quantity, unit_price, position) VALUES ($1, $2, $3, $4, $5, $6)",
+                id,
+                expense_id,
+                item.name,
+                item.quantity,
+                item.unit_price,
+                position
+            )
+            .execute(transaction.as_mut())
+            .await?;
+            for user_id in item.user_ids.iter() {
+                sqlx::query!(
+                    "INSERT INTO expense_item_users(item_id, user_id) VALUES ($1, $2)",
+                    id,
+                    user_id
+                )
+                .execute(transaction.as_mut())
+                .await?;
+            }
+        }
+        sqlx::query!(
+            "INSERT INTO expense_receipts(expense_id, tax, service_charge, tip) VALUES ($1, $2, $3, $4)",
+            expense_id,
+            self.tax,
+            self.service_charge,
+            self.tip
+        )
+        .execute(transaction.as_mut())
+        .await?;
+        Ok(())
+    }
+}
+
+fn item_total(item: &ExpenseItemInput) -> anyhow::Result<i64> {
+    if item.quantity <= 0 {
+        return Err(anyhow::anyhow!("Item quantity must be greater than 0"));
+    }
+    if item.unit_price < 0 {
+        return Err(anyhow::anyhow!("Item price can not be negative"));
+    }
+    item.quantity
+        .checked_mul(item.unit_price)
+        .ok_or_else(|| anyhow::anyhow!("Item total is too large"))
+}
+
+pub struct ExpenseItem {
+    pub id: String,
+    pub name: String,
+    pub quantity: i64,
+    pub unit_price: i64,
+    pub currency_id: String,
+}
+
+impl ExpenseItem {
+    pub async fn get_for_expense(
+        expense_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<ExpenseItem>> {
+        let items = sqlx::query_as!(
+            ExpenseItem,
+            r#"
+            SELECT ei.id, ei.name, ei.quantity, ei.unit_price, e.currency_id
+            FROM expense_items ei JOIN expenses e ON ei.expense_id = e.id
+            WHERE ei.expense_id = $1
+            ORDER BY ei.position
+            "#,
+            expense_id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(items)
+    }
+
+    /// Removes the items and charges of an expense, e.g. when its splits are
+    /// replaced.
+    pub async fn delete_for_expense<'a>(
+        expense_id: &str,
+        transaction: &mut Transaction<'a, Sqlite>,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "DELETE FROM expense_item_users WHERE item_id IN (SELECT id FROM expense_items WHERE expense_id = $1)",
+            expense_id
+        )
+        .execute(transaction.as_mut())
+        .await?;
+        sqlx::query!(
+            "DELETE FROM expense_items WHERE expense_id = $1",
+            expense_id
+        )
+        .execute(transaction.as_mut())
+        .await?;
+        sqlx::query!(
+            "DELETE FROM expense_receipts WHERE expense_id = $1",
+            expense_id
+        )
+        .execute(transaction.as_mut())
+        .await?;
+        Ok(())
+    }
+}
+
+#[Object]
+impl ExpenseItem {
+    pub async fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub async fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub async fn quantity(&self) -> i64 {
+        self.quantity
+    }
+
+    pub async fn unit_price(&self) -> Amount {
+        Amount {
+            amount: self.unit_price,
+            currency_id: self.currency_id.clone(),
+        }
+    }
+
+    pub async fn total(&self) -> Amount {
+        Amount {
+            amount: self.quantity * self.unit_price,
+            currency_id: self.currency_id.clone(),
+        }
+    }
+
+    pub async fn users<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Vec<User>> {
+        let pool = get_pool_from_context(context).await?;
+        let users = sqlx::query_as!(
+            User,
+            r#"
+            SELECT users.* FROM users JOIN expense_item_users ON users.id = expense_item_users.user_id
+            WHERE expense_item_users.item_id = $1
+            "#,
+            self.id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(users)
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct ReceiptCharges {
+    pub tax: Amount,
+    pub service_charge: Amount,
+    pub tip: Amount,
+}
+
+impl ReceiptCharges {
+    pub async fn get_for_expense(
+        expense_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Option<ReceiptCharges>> {
+        let charges = sqlx::query!(
+            r#"
+            SELECT r.tax, r.service_charge, r.tip, e.currency_id
+            FROM expense_receipts r JOIN expenses e ON r.expense_id = e.id
+            WHERE r.expense_id = $1
+            "#,
+            expense_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .map(|row| ReceiptCharges {
+            tax: Amount {
+                amount: row.tax,
+                currency_id: row.currency_id.clone(),
+            },
+            service_charge: Amount {
+                amount: row.service_charge,
+                currency_id: row.currency_id.clone(),
+            },
+            tip: Amount {
+                amount: row.tip,
+                currency_id: row.currency_id,
+            },
+        });
+        Ok(charges)
+    }
+}
@@ -1,16 +1,25 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
 
 use anyhow::Ok;
-use async_graphql::{Context, Object, SimpleObject};
-use sqlx::SqlitePool;
+use async_graphql::{Context, Enum, Object, SimpleObject};
+use sqlx::{SqliteExecutor, SqlitePool};
+use strum::{Display, EnumString};
 use uuid::Uuid;
 
-use crate::{auth::AuthTypes, schema::get_pool_from_context};
+use crate::{auth::AuthTypes, s3::S3, schema::get_pool_from_context};
 
 use super::{
     amount::Amount,
+    balance::{GroupBalances, MemberBalance, PairBalance},
     expense::Expense,
-    split::{Split, TransactionType},
+    group_event::GroupEvent,
+    group_invite::GroupInvite,
+    recurring_expense::RecurringExpense,
+    revision::Revision,
+    split::{Split, SuggestedSettlement, TransactionType},
     user::User,
 };
 
@@ -20,14 +29,63 @@ pub struct Group {
     pub name: Option<String>,
     pub created_at: String,
     pub creator_id: String,
+    pub archived_at: Option<String>,
+    pub description: Option<String>,
+    pub image_id: Option<String>,
+    pub default_currency_id: Option<String>,
 }
 
 #[derive(SimpleObject)]
 pub struct GroupMember {
     pub member: User,
+    pub role: GroupRole,
     pub owed_in_group: Vec<Amount>,
 }
 
+/// What a member is owed in the group, negative when they owe.
+pub struct NetBalance {
+    pub user_id: String,
+    pub amount: Amount,
+}
+
+#[derive(EnumString, Enum, Clone, Copy, PartialEq, Eq, Display)]
+pub enum GroupRole {
+    Owner,
+    Admin,
+    Member,
+    Viewer,
+}
+
+impl GroupRole {
+    /// Adding and removing members.
+    pub fn can_manage_members(self) -> bool {
+        matches!(self, GroupRole::Owner | GroupRole::Admin)
+    }
+
+    /// Changing the name, description, image and default currency.
+    pub fn can_edit_details(self) -> bool {
+        matches!(self, GroupRole::Owner | GroupRole::Admin)
+    }
+
+    /// Adding expenses and settlements. Viewers can only look.
+    pub fn can_add_expenses(self) -> bool {
+        self != GroupRole::Viewer
+    }
+
+    /// Editing and deleting what other members added.
+    pub fn can_edit_others_expenses(self) -> bool {
+        matches!(self, GroupRole::Owner | GroupRole::Admin)
+    }
+
+    pub fn can_archive(self) -> bool {
+        matches!(self, GroupRole::Owner | GroupRole::Admin)
+    }
+
+    pub fn can_change_roles(self) -> bool {
+        self == GroupRole::Owner
+    }
+}
+
 #[Object]
 impl Group {
     pub async fn id(&self) -> &str {
@@ -45,6 +103,29 @@ impl Group {
         User::get_from_id(&self.creator_id, pool).await
     }
 
+    pub async fn description(&self) -> &Option<String> {
+        &self.description
+    }
+
+    pub async fn image_id(&self) -> &Option<String> {
+        &self.image_id
+    }
+
+    /// Currency new expenses use when none is given.
+    pub async fn default_currency_id(&self) -> &Option<String> {
+        &self.default_currency_id
+    }
+
+    pub async fn archived_at(&self) -> &Option<String> {
+        &self.archived_at
+    }
+
+    /// Whether nobody owes anyone anything in the group.
+    pub async fn is_settled<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<bool> {
+        let pool = get_pool_from_context(context).await?;
+        Ok(self.get_net_balances(pool).await?.is_empty())
+    }
+
     pub async fn members<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Vec<GroupMember>> {
         let user = context
             .data::<AuthTypes>()
@@ -64,6 +145,64 @@ impl Group {
         let pool = get_pool_from_context(context).await?;
         self.get_expenses(limit, from_time, pool).await
     }
+
+    /// What happened in the group, newest first. Pass the `createdAt` of the
+    /// last event as `fromTime` to load older ones.
+    pub async fn activity<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        from_time: Option<String>,
+        #[graphql(default = 20)] limit: u32,
+    ) -> anyhow::Result<Vec<GroupEvent>> {
+        let pool = get_pool_from_context(context).await?;
+        GroupEvent::get_for_group(&self.id, limit, from_time, pool).await
+    }
+
+    /// Role of the caller, none for former members.
+    pub async fn my_role<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+    ) -> anyhow::Result<Option<GroupRole>> {
+        let user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or_else(|| anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        Ok(Self::get_roles(&self.id, pool).await?.remove(&user.id))
+    }
+
+    /// Invite links that still work. Only visible to members who can add
+    /// members.
+    pub async fn invites<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Vec<GroupInvite>> {
+        let user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or_else(|| anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        if !Self::get_role(&self.id, &user.id, pool)
+            .await?
+            .can_manage_members()
+        {
+            return Err(anyhow::anyhow!("Unauthorized"));
+        }
+        GroupInvite::get_for_group(&self.id, pool).await
+    }
+
+    /// Who owes whom across the whole group, not just relative to the caller.
+    pub async fn balances<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<GroupBalances> {
+        let pool = get_pool_from_context(context).await?;
+        self.get_balances(pool).await
+    }
+
+    pub async fn recurring_expenses<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+    ) -> anyhow::Result<Vec<RecurringExpense>> {
+        let pool = get_pool_from_context(context).await?;
+        RecurringExpense::get_for_group(&self.id, pool).await
+    }
 }
 
 impl Group {
@@ -83,16 +222,26 @@ impl Group {
             WHERE g.name IS NULL
             AND NOT EXISTS (
                 SELECT 1
-                FROM group_memberships gm
+                FROM ({MEMBERS}) gm
                 WHERE gm.group_id = g.id
                 AND gm.user_id NOT IN ({QUERY_IN})
             )
             AND (
                 SELECT COUNT(*)
-                FROM group_memberships gm
+                FROM ({MEMBERS}) gm
                 WHERE gm.group_id = g.id
             ) = ${END_BIND}
         "##
+        // Users with a pending invitation count as members, so the same group is
+        // found again while they decide.
+        .replace(
+            "{MEMBERS}",
+            r#"
+                SELECT user_id, group_id FROM group_memberships WHERE removed_at IS NULL
+                UNION
+                SELECT user_id, group_id FROM group_invitations WHERE status = 'Pending'
+            "#,
+        )
         .replace("{QUERY_IN}", &in_string)
         .replace("{END_BIND}", (users.len() + 1).to_string().as_str());
         let mut query = sqlx::query_as::<_, Group>(&query_string);
@@ -122,7 +271,7 @@ impl Group {
         let current_time = chrono::Utc::now().to_rfc3339();
         let group = sqlx::query_as!(
             Group,
-        r#"INSERT INTO groups(id,name,created_at,creator_id) VALUES ($1,$2,$3,$4) RETURNING id as "id!", name, created_at as "created_at!", creator_id as "creator_id!""#,
+        r#"INSERT INTO groups(id,name,created_at,creator_id) VALUES ($1,$2,$3,$4) RETURNING id as "id!", name, created_at as "created_at!", creator_id as "creator_id!", archived_at, description, image_id, default_currency_id"#,
         id,
         name,
         current_time,
@@ -132,11 +281,13 @@ impl Group {
         .await?;
 
         let membership_id = uuid::Uuid::new_v4().to_string();
+        let role = GroupRole::Owner.to_string();
         sqlx::query!(
-            "INSERT INTO group_memberships(id,user_id,group_id) VALUES ($1,$2,$3)",
+            "INSERT INTO group_memberships(id,user_id,group_id,role) VALUES ($1,$2,$3,$4)",
             membership_id,
             creator_id,
-            group.id
+            group.id,
+            role
         )
         .execute(transaction.as_mut())
         .await?;
@@ -144,18 +295,25 @@ impl Group {
         Ok(group)
     }
 
+    /// Adds `user_id` with `role`. Former members are let back in, current
+    /// members keep the role they have.
     pub async fn add_to_group(
         group_id: &str,
         user_id: &str,
+        role: GroupRole,
         pool: &SqlitePool,
     ) -> anyhow::Result<()> {
         let membership_id = uuid::Uuid::new_v4().to_string();
+        let role = role.to_string();
 
         let _group_membership = sqlx::query!(
-            "INSERT INTO group_memberships(id,user_id,group_id) VALUES ($1,$2,$3)",
+            r#"INSERT INTO group_memberships(id,user_id,group_id,role) VALUES ($1,$2,$3,$4)
+            ON CONFLICT(user_id, group_id) DO UPDATE SET removed_at = NULL, role = excluded.role
+            WHERE removed_at IS NOT NULL"#,
             membership_id,
             user_id,
             group_id,
+            role
         )
         .execute(pool)
         .await?;
@@ -169,6 +327,7 @@ impl Group {
             SELECT users.* FROM 
                 users JOIN group_memberships ON users.id=group_memberships.user_id 
                 JOIN groups ON group_memberships.group_id=groups.id AND groups.id=$1
+            WHERE group_memberships.removed_at IS NULL
             "#,
             group_id
         )
@@ -177,12 +336,203 @@ impl Group {
         Ok(users)
     }
 
+    /// Roles of the current members by user id.
+    pub async fn get_roles(
+        group_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<HashMap<String, GroupRole>> {
+        let roles = sqlx::query!(
+            "SELECT user_id, role FROM group_memberships WHERE group_id = $1 AND removed_at IS NULL",
+            group_id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|row| {
+            let role = GroupRole::from_str(&row.role).unwrap_or(GroupRole::Member);
+            (row.user_id, role)
+        })
+        .collect();
+        Ok(roles)
+    }
+
+    /// Role of a current member, fails for everyone else.
+    pub async fn get_role(
+        group_id: &str,
+        user_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<GroupRole> {
+        let role = sqlx::query!(
+            "SELECT role FROM group_memberships WHERE group_id = $1 AND user_id = $2 AND removed_at IS NULL",
+            group_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Not a group member"))?;
+        Ok(GroupRole::from_str(&role.role).unwrap_or(GroupRole::Member))
+    }
+
+    pub async fn set_role(
+        group_id: &str,
+        user_id: &str,
+        role: GroupRole,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<()> {
+        let role = role.to_string();
+        let updated = sqlx::query!(
+            "UPDATE group_memberships SET role = $3 WHERE group_id = $1 AND user_id = $2 AND removed_at IS NULL",
+            group_id,
+            user_id,
+            role
+        )
+        .execute(pool)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Not a group member"));
+        }
+        Ok(())
+    }
+
+    pub fn check_not_archived(&self) -> anyhow::Result<()> {
+        if self.archived_at.is_some() {
+            return Err(anyhow::anyhow!("Group is archived"));
+        }
+        Ok(())
+    }
+
+    /// Archives or unarchives the group. Archiving needs every balance to be
+    /// zero unless `force` is set.
+    pub async fn set_archived(
+        &self,
+        archived: bool,
+        force: bool,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Group> {
+        if self.name.is_none() {
+            return Err(anyhow::anyhow!("Can not archive a direct payment group"));
+        }
+        if archived && !force && !self.get_net_balances(pool).await?.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Group has outstanding balances, settle up or confirm to archive anyway"
+            ));
+        }
+        let time = archived.then(|| chrono::Utc::now().to_rfc3339());
+        let group = sqlx::query_as!(
+            Group,
+            r#"UPDATE groups SET archived_at = $2 WHERE id = $1 RETURNING id as "id!", name, created_at as "created_at!", creator_id as "creator_id!", archived_at, description, image_id, default_currency_id"#,
+            self.id,
+            time
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(group)
+    }
+
+    /// Changes the given details, an empty description, image or default
+    /// currency clears it. A new image is moved out of the upload area before
+    /// the change is committed.
+    pub async fn update(
+        &self,
+        name: Option<&str>,
+        description: Option<&str>,
+        image_id: Option<&str>,
+        default_currency_id: Option<&str>,
+        s3: &S3,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Group> {
+        if self.name.is_none() {
+            return Err(anyhow::anyhow!("Can not change a direct payment group"));
+        }
+        let mut transaction = pool.begin().await?;
+        let group = sqlx::query_as!(
+            Group,
+            r#"UPDATE groups SET
+                name = COALESCE($2, name),
+                description = CASE WHEN $3 IS NULL THEN description ELSE NULLIF($3, '') END,
+                image_id = CASE WHEN $4 IS NULL THEN image_id ELSE NULLIF($4, '') END,
+                default_currency_id = CASE WHEN $5 IS NULL THEN default_currency_id ELSE NULLIF($5, '') END
+            WHERE id = $1
+            RETURNING id as "id!", name, created_at as "created_at!", creator_id as "creator_id!", archived_at, description, image_id, default_currency_id"#,
+            self.id,
+            name,
+            description,
+            image_id,
+            default_currency_id
+        )
+        .fetch_one(transaction.as_mut())
+        .await?;
+        if let Some(image_id) = &group.image_id {
+            if self.image_id.as_ref() != Some(image_id) {
+                s3.move_to_be(image_id).await?;
+            }
+        }
+        transaction.commit().await?;
+        Ok(group)
+    }
+
+    /// Whether `user_id` is or ever was in the group. Removed members keep
+    /// read access to what happened while they were part of it.
+    pub async fn had_member(
+        group_id: &str,
+        user_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<bool> {
+        let membership = sqlx::query!(
+            "SELECT id FROM group_memberships WHERE group_id = $1 AND user_id = $2",
+            group_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(membership.is_some())
+    }
+
+    /// Takes `user_id` out of the group. Fails while they still owe or are
+    /// owed anything in it, unless `force` is set.
+    pub async fn remove_member(
+        &self,
+        user_id: &str,
+        force: bool,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<()> {
+        if self.name.is_none() {
+            return Err(anyhow::anyhow!("Can not leave a direct payment group"));
+        }
+        if !force {
+            let outstanding = self
+                .get_group_members(user_id, pool)
+                .await?
+                .into_iter()
+                .any(|member| member.owed_in_group.iter().any(|owed| owed.amount != 0));
+            if outstanding {
+                return Err(anyhow::anyhow!(
+                    "Member has outstanding balance in group, settle up or confirm to remove anyway"
+                ));
+            }
+        }
+        let time = chrono::Utc::now().to_rfc3339();
+        let removed = sqlx::query!(
+            "UPDATE group_memberships SET removed_at = $3 WHERE group_id = $1 AND user_id = $2 AND removed_at IS NULL",
+            self.id,
+            user_id,
+            time
+        )
+        .execute(pool)
+        .await?;
+        if removed.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Not a group member"));
+        }
+        Ok(())
+    }
+
     pub async fn get_group_members(
         &self,
         user_id: &str,
         pool: &SqlitePool,
     ) -> anyhow::Result<Vec<GroupMember>> {
         let orig_users = Self::get_users(&self.id, pool).await?;
+        let roles = Self::get_roles(&self.id, pool).await?;
         let mut grouped = HashMap::new();
         sqlx::query!(
             r#"
@@ -198,7 +548,7 @@ impl Group {
         FROM 
             group_memberships m
         INNER JOIN 
-            split_transactions st ON m.group_id = st.group_id
+            split_transactions st ON m.group_id = st.group_id AND st.deleted_at IS NULL
         INNER JOIN 
             users u ON u.id = m.user_id
         WHERE 
@@ -232,6 +582,7 @@ impl Group {
                     .get(u.id.as_str())
                     .map(|val| val.1.clone())
                     .unwrap_or_default(),
+                role: roles.get(&u.id).copied().unwrap_or(GroupRole::Member),
                 member: u,
             })
             .collect();
@@ -249,8 +600,8 @@ impl Group {
             sqlx::query_as!(
                 Expense,
                 r#"SELECT 
-                id as "id!", title as  "title!", amount as "amount!", created_at as "created_at!", group_id as "group_id!", created_by as "created_by!", currency_id as "currency_id!", category as "category!", note, image_id, updated_at, transaction_at
-                FROM expenses where group_id=$1 AND created_at<$3 ORDER BY transaction_at DESC LIMIT $2"#,
+                id as "id!", title as  "title!", amount as "amount!", created_at as "created_at!", group_id as "group_id!", created_by as "created_by!", currency_id as "currency_id!", category as "category!", note, image_id, updated_at, transaction_at, split_strategy, deleted_at
+                FROM expenses where group_id=$1 AND deleted_at IS NULL AND created_at<$3 ORDER BY transaction_at DESC LIMIT $2"#,
                 self.id,
                 limit,
                 from_time
@@ -261,8 +612,8 @@ impl Group {
             sqlx::query_as!(
                 Expense,
                 r#"SELECT 
-                id as "id!", title as  "title!", amount as "amount!", created_at as "created_at!", group_id as "group_id!", created_by as "created_by!", currency_id as "currency_id!", category as "category!", note, image_id, updated_at, transaction_at
-                FROM expenses where group_id=$1 ORDER BY transaction_at DESC LIMIT $2"#,
+                id as "id!", title as  "title!", amount as "amount!", created_at as "created_at!", group_id as "group_id!", created_by as "created_by!", currency_id as "currency_id!", category as "category!", note, image_id, updated_at, transaction_at, split_strategy, deleted_at
+                FROM expenses where group_id=$1 AND deleted_at IS NULL ORDER BY transaction_at DESC LIMIT $2"#,
                 self.id,
                 limit,
             )
@@ -272,6 +623,7 @@ impl Group {
         Ok(expenses)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn settle_for_group<'a>(
         group_id: &str,
         from_user: &str,
@@ -344,7 +696,471 @@ impl Group {
         )
         .fetch_one(transaction.as_mut())
         .await?;
+        Revision::record_splits(creator_id, &[], std::slice::from_ref(&split), transaction).await?;
 
         Ok(split)
     }
+
+    /// Moves balances between `user_id` and `with_user` across their shared
+    /// groups so that, per currency, they only owe each other in one direction.
+    pub async fn simplify_cross_group(
+        user_id: &str,
+        with_user: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<Split>> {
+        let owes = User::get_owes_with_group(user_id, with_user, pool).await?;
+        let mut grouped_positives = HashMap::new();
+        for owe in owes {
+            grouped_positives
+                .entry(owe.amount.currency_id.clone())
+                .or_insert_with(Vec::new)
+                .push((owe.amount.amount, owe.group_id));
+        }
+        let mut transaction = pool.begin().await?;
+        let mut splits = vec![];
+
+        for (currency, owes) in grouped_positives.iter() {
+            let positives = owes.iter().filter(|ow| ow.0 > 0);
+            let mut negatives = owes.iter().filter(|ow| ow.0 < 0);
+            let mut negative = negatives.next();
+            let mut negative_settled = 0_i64;
+            let part_id = uuid::Uuid::new_v4().to_string();
+            'po: for positive in positives {
+                log::info!("Positive: {positive:?}");
+                let mut remaining_positive = positive.0;
+                while let Some(negative_val) = negative.or_else(|| negatives.next()) {
+                    negative = Some(negative_val);
+                    log::info!("Negative: {negative:?}");
+                    let remaining_negative = negative_val.0.abs() - negative_settled;
+                    if remaining_negative > 0 {
+                        let (amt_settle, is_neg) = if remaining_negative > remaining_positive {
+                            negative_settled += remaining_positive;
+                            (remaining_positive, true)
+                        } else {
+                            remaining_positive -= remaining_negative;
+                            (remaining_negative, false)
+                        };
+                        log::info!("Amount settle {amt_settle} is_neg {is_neg}");
+                        splits.push(
+                            Group::settle_for_group(
+                                &positive.1,
+                                user_id,
+                                with_user,
+                                amt_settle,
+                                user_id,
+                                Some(part_id.clone()),
+                                TransactionType::CrossGroupSettlement,
+                                &mut transaction,
+                                Some(negative_val.1.clone()),
+                                currency,
+                                None,
+                                None,
+                                None,
+                            )
+                            .await?,
+                        );
+                        splits.push(
+                            Group::settle_for_group(
+                                &negative_val.1,
+                                with_user,
+                                user_id,
+                                amt_settle,
+                                user_id,
+                                Some(part_id.clone()),
+                                TransactionType::CrossGroupSettlement,
+                                &mut transaction,
+                                Some(positive.1.clone()),
+                                currency,
+                                None,
+                                None,
+                                None,
+                            )
+                            .await?,
+                        );
+                        if is_neg {
+                            continue 'po;
+                        }
+                    }
+                    negative = None;
+                    negative_settled = 0;
+                    log::info!("Next!")
+                }
+            }
+        }
+
+        transaction.commit().await?;
+
+        Ok(splits)
+    }
+
+    /// Net balance of every member with one, per currency.
+    pub async fn get_net_balances<'c>(
+        &self,
+        executor: impl SqliteExecutor<'c>,
+    ) -> anyhow::Result<Vec<NetBalance>> {
+        let balances = sqlx::query!(
+            r#"
+            SELECT user_id AS "user_id!", currency_id AS "currency_id!", SUM(amount) AS "amount!: i64" FROM (
+                SELECT to_user AS user_id, currency_id, amount
+                FROM split_transactions WHERE group_id = $1 AND deleted_at IS NULL
+                UNION ALL
+                SELECT from_user AS user_id, currency_id, -amount
+                FROM split_transactions WHERE group_id = $1 AND deleted_at IS NULL
+            )
+            GROUP BY user_id, currency_id
+            HAVING SUM(amount) != 0
+            ORDER BY currency_id, user_id
+            "#,
+            self.id
+        )
+        .fetch_all(executor)
+        .await?
+        .into_iter()
+        .map(|row| NetBalance {
+            user_id: row.user_id,
+            amount: Amount {
+                amount: row.amount,
+                currency_id: row.currency_id,
+            },
+        })
+        .collect();
+        Ok(balances)
+    }
+
+    /// Net debt between every two members who owe each other something, per
+    /// currency.
+    pub async fn get_pair_balances<'c>(
+        &self,
+        executor: impl SqliteExecutor<'c>,
+    ) -> anyhow::Result<Vec<PairBalance>> {
+        let owed = sqlx::query!(
+            r#"
+            SELECT from_user, to_user, currency_id, SUM(amount) AS "amount!: i64"
+            FROM split_transactions WHERE group_id = $1 AND deleted_at IS NULL
+            GROUP BY from_user, to_user, currency_id
+            "#,
+            self.id
+        )
+        .fetch_all(executor)
+        .await?;
+        // Keyed by currency and the ordered pair, positive when the first
+        // user owes the second.
+        let mut netted: BTreeMap<(String, String, String), i64> = BTreeMap::new();
+        for row in owed {
+            if row.from_user < row.to_user {
+                *netted
+                    .entry((row.currency_id, row.from_user, row.to_user))
+                    .or_insert(0) += row.amount;
+            } else {
+                *netted
+                    .entry((row.currency_id, row.to_user, row.from_user))
+                    .or_insert(0) -= row.amount;
+            }
+        }
+        Ok(netted
+            .into_iter()
+            .filter(|(_, amount)| *amount != 0)
+            .map(|((currency_id, first, second), amount)| {
+                let (from_user_id, to_user_id) = if amount > 0 {
+                    (first, second)
+                } else {
+                    (second, first)
+                };
+                PairBalance {
+                    from_user_id,
+                    to_user_id,
+                    amount: Amount {
+                        amount: amount.abs(),
+                        currency_id,
+                    },
+                }
+            })
+            .collect())
+    }
+
+    /// Pairwise balances together with every member's net position. Current
+    /// members are always listed, former ones only while they have a balance.
+    pub async fn get_balances(&self, pool: &SqlitePool) -> anyhow::Result<GroupBalances> {
+        let mut members = Self::get_users(&self.id, pool)
+            .await?
+            .into_iter()
+            .map(|user| MemberBalance {
+                user_id: user.id,
+                net: vec![],
+            })
+            .collect::<Vec<_>>();
+        for balance in self.get_net_balances(pool).await? {
+            match members.iter_mut().find(|m| m.user_id == balance.user_id) {
+                Some(member) => member.net.push(balance.amount),
+                None => members.push(MemberBalance {
+                    user_id: balance.user_id,
+                    net: vec![balance.amount],
+                }),
+            }
+        }
+        Ok(GroupBalances {
+            pairs: self.get_pair_balances(pool).await?,
+            members,
+        })
+    }
+
+    /// Payments that clear every balance in the group. Per currency the
+    /// largest debtor pays the largest creditor until both sides are empty,
+    /// which needs at most one payment less than there are members with a
+    /// balance.
+    pub async fn suggested_settlements(
+        &self,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<SuggestedSettlement>> {
+        Ok(plan_settlements(self.get_net_balances(pool).await?))
+    }
+
+    /// Rewrites who owes whom so the debts match
+    /// [`Group::suggested_settlements`]. Nobody's balance changes, every pair
+    /// only gets a correcting row for the difference and all rows share one
+    /// `part_transaction`.
+    pub async fn apply_suggested_settlements(
+        &self,
+        actor_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<Split>> {
+        // Keyed by currency and the ordered pair, positive when the first
+        // user owes the second.
+        let mut changes: BTreeMap<(String, String, String), i64> = BTreeMap::new();
+        let mut add = |currency_id: String, from_user: String, to_user: String, amount: i64| {
+            if from_user < to_user {
+                *changes
+                    .entry((currency_id, from_user, to_user))
+                    .or_insert(0) += amount;
+            } else {
+                *changes
+                    .entry((currency_id, to_user, from_user))
+                    .or_insert(0) -= amount;
+            }
+        };
+        // Read in the transaction that writes the corrections, so a change
+        // made meanwhile can not be overwritten.
+        let mut transaction = pool.begin().await?;
+        let net_balances = self.get_net_balances(transaction.as_mut()).await?;
+        for settlement in plan_settlements(net_balances) {
+            add(
+                settlement.amount.currency_id,
+                settlement.from_user_id,
+                settlement.to_user_id,
+                settlement.amount.amount,
+            );
+        }
+        for pair in self.get_pair_balances(transaction.as_mut()).await? {
+            add(
+                pair.amount.currency_id,
+                pair.from_user_id,
+                pair.to_user_id,
+                -pair.amount.amount,
+            );
+        }
+
+        let part_id = uuid::Uuid::new_v4().to_string();
+        let mut splits = vec![];
+        for ((currency_id, first, second), amount) in changes {
+            let (from_user, to_user) = match amount.cmp(&0) {
+                std::cmp::Ordering::Greater => (&first, &second),
+                std::cmp::Ordering::Less => (&second, &first),
+                std::cmp::Ordering::Equal => continue,
+            };
+            splits.push(
+                Group::settle_for_group(
+                    &self.id,
+                    from_user,
+                    to_user,
+                    amount.abs(),
+                    actor_id,
+                    Some(part_id.clone()),
+                    TransactionType::GroupSimplification,
+                    &mut transaction,
+                    None,
+                    &currency_id,
+                    None,
+                    None,
+                    None,
+                )
+                .await?,
+            );
+        }
+        transaction.commit().await?;
+        Ok(splits)
+    }
+}
+
+/// Payments that clear `balances`, planned per currency.
+fn plan_settlements(balances: Vec<NetBalance>) -> Vec<SuggestedSettlement> {
+    let mut by_currency: BTreeMap<String, Vec<(String, i64)>> = BTreeMap::new();
+    for balance in balances {
+        by_currency
+            .entry(balance.amount.currency_id)
+            .or_default()
+            .push((balance.user_id, balance.amount.amount));
+    }
+    let mut settlements = vec![];
+    for (currency_id, balances) in by_currency {
+        for (from_user_id, to_user_id, amount) in minimum_transfers(balances) {
+            settlements.push(SuggestedSettlement {
+                from_user_id,
+                to_user_id,
+                amount: Amount {
+                    amount,
+                    currency_id: currency_id.clone(),
+                },
+            });
+        }
+    }
+    settlements
+}
+
+/// Greedy minimum cash flow over net balances, positive when owed. Returns
+/// `(from, to, amount)` payments.
+fn minimum_transfers(balances: Vec<(String, i64)>) -> Vec<(String, String, i64)> {
+    let mut creditors = balances
+        .iter()
+        .filter(|(_, amount)| *amount > 0)
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut debtors = balances
+        .into_iter()
+        .filter(|(_, amount)| *amount < 0)
+        .map(|(user_id, amount)| (user_id, -amount))
+        .collect::<Vec<_>>();
+    let mut transfers = vec![];
+    loop {
+        // Largest first, ties by id so the plan is stable between calls.
+        let largest = |entries: &[(String, i64)]| {
+            entries
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, amount))| *amount > 0)
+                .max_by(|(_, a), (_, b)| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0)))
+                .map(|(index, _)| index)
+        };
+        let (Some(creditor), Some(debtor)) = (largest(&creditors), largest(&debtors)) else {
+            break;
+        };
+        let amount = creditors[creditor].1.min(debtors[debtor].1);
+        creditors[creditor].1 -= amount;
+        debtors[debtor].1 -= amount;
+        transfers.push((
+            debtors[debtor].0.clone(),
+            creditors[creditor].0.clone(),
+            amount,
+        ));
+    }
+    transfers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn balances(entries: &[(&str, i64)]) -> Vec<(String, i64)> {
+        entries
+            .iter()
+            .map(|(user_id, amount)| (user_id.to_string(), *amount))
+            .collect()
+    }
+
+    /// Applies the transfers and checks every balance ends up at zero
+    /// within at most one transfer less than there are balances.
+    fn assert_settles(entries: &[(&str, i64)]) -> Vec<(String, String, i64)> {
+        let transfers = minimum_transfers(balances(entries));
+        let mut remaining = balances(entries).into_iter().collect::<HashMap<_, _>>();
+        for (from, to, amount) in transfers.iter() {
+            assert!(*amount > 0);
+            *remaining.get_mut(from).unwrap() += amount;
+            *remaining.get_mut(to).unwrap() -= amount;
+        }
+        assert!(remaining.values().all(|amount| *amount == 0));
+        let with_balance = entries.iter().filter(|(_, amount)| *amount != 0).count();
+        assert!(transfers.len() < with_balance.max(1));
+        transfers
+    }
+
+    #[test]
+    fn settles_single_debt() {
+        assert_eq!(
+            assert_settles(&[("a", 100), ("b", -100)]),
+            vec![("b".to_string(), "a".to_string(), 100)]
+        );
+    }
+
+    #[test]
+    fn settles_nothing_without_balances() {
+        assert!(assert_settles(&[]).is_empty());
+        assert!(assert_settles(&[("a", 0), ("b", 0)]).is_empty());
+    }
+
+    #[test]
+    fn breaks_ties_by_user_id() {
+        let transfers = assert_settles(&[("b", 50), ("a", 50), ("d", -50), ("c", -50)]);
+        assert_eq!(
+            transfers,
+            vec![
+                ("c".to_string(), "a".to_string(), 50),
+                ("d".to_string(), "b".to_string(), 50),
+            ]
+        );
+        assert_eq!(
+            minimum_transfers(balances(&[("a", 50), ("c", -50), ("b", 50), ("d", -50)])),
+            transfers
+        );
+    }
+
+    #[test]
+    fn stays_below_member_count() {
+        assert_settles(&[("a", 300), ("b", -100), ("c", -100), ("d", -100)]);
+        assert_settles(&[("a", -300), ("b", 100), ("c", 100), ("d", 100)]);
+        assert_settles(&[("a", 70), ("b", 30), ("c", -45), ("d", -45), ("e", -10)]);
+        assert_settles(&[
+            ("a", 1),
+            ("b", 2),
+            ("c", 3),
+            ("d", -4),
+            ("e", -1),
+            ("f", -1),
+        ]);
+    }
+
+    #[test]
+    fn plans_each_currency_apart() {
+        let net = [
+            ("a", "INR", 100),
+            ("b", "INR", -100),
+            ("a", "USD", -5),
+            ("b", "USD", 5),
+        ]
+        .into_iter()
+        .map(|(user_id, currency_id, amount)| NetBalance {
+            user_id: user_id.to_string(),
+            amount: Amount {
+                amount,
+                currency_id: currency_id.to_string(),
+            },
+        })
+        .collect();
+        let settlements = plan_settlements(net)
+            .into_iter()
+            .map(|s| {
+                (
+                    s.from_user_id,
+                    s.to_user_id,
+                    s.amount.currency_id,
+                    s.amount.amount,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            settlements,
+            vec![
+                ("b".to_string(), "a".to_string(), "INR".to_string(), 100),
+                ("a".to_string(), "b".to_string(), "USD".to_string(), 5),
+            ]
+        );
+    }
 }
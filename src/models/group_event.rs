@@ -0,0 +1,165 @@
+use std::str::FromStr;
+
+use async_graphql::{Context, Enum, Object};
+use sqlx::SqlitePool;
+use strum::{Display, EnumString};
+
+use crate::schema::get_pool_from_context;
+
+use super::{expense::Expense, split::Split, user::User};
+
+#[derive(EnumString, Enum, Clone, Copy, PartialEq, Eq, Display)]
+pub enum GroupEventType {
+    GroupCreated,
+    GroupRenamed,
+    GroupUpdated,
+    GroupArchived,
+    GroupUnarchived,
+    MemberJoined,
+    MemberLeft,
+    MemberRemoved,
+    RoleChanged,
+    ExpenseAdded,
+    ExpenseEdited,
+    ExpenseDeleted,
+    ExpenseRestored,
+    PaymentRecorded,
+    PaymentDeleted,
+    CurrencyConverted,
+    SettlementsSimplified,
+}
+
+/// One entry of a group's activity feed. `expense_id` or `split_id` point at
+/// what the event is about, `user_id` at the member it concerns.
+pub struct GroupEvent {
+    pub id: String,
+    pub group_id: String,
+    pub actor_id: String,
+    pub event_type: String,
+    pub expense_id: Option<String>,
+    pub split_id: Option<String>,
+    pub user_id: Option<String>,
+    pub details: Option<String>,
+    pub created_at: String,
+}
+
+#[Object]
+impl GroupEvent {
+    pub async fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub async fn group_id(&self) -> &str {
+        &self.group_id
+    }
+
+    pub async fn event_type(&self) -> GroupEventType {
+        GroupEventType::from_str(&self.event_type).unwrap_or(GroupEventType::GroupUpdated)
+    }
+
+    pub async fn actor_id(&self) -> &str {
+        &self.actor_id
+    }
+
+    pub async fn actor<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<User> {
+        let pool = get_pool_from_context(context).await?;
+        User::get_from_id(&self.actor_id, pool).await
+    }
+
+    pub async fn expense<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Option<Expense>> {
+        let pool = get_pool_from_context(context).await?;
+        match &self.expense_id {
+            Some(expense_id) => Ok(Some(Expense::get_from_id(expense_id, pool).await?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn split<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Option<Split>> {
+        let pool = get_pool_from_context(context).await?;
+        match &self.split_id {
+            Some(split_id) => Ok(Some(Split::get_from_id(split_id, pool).await?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn user<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Option<User>> {
+        let pool = get_pool_from_context(context).await?;
+        match &self.user_id {
+            Some(user_id) => Ok(Some(User::get_from_id(user_id, pool).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Extra context, like the new name of a renamed group or the new role
+    /// of a member.
+    pub async fn details(&self) -> &Option<String> {
+        &self.details
+    }
+
+    pub async fn created_at(&self) -> &str {
+        &self.created_at
+    }
+}
+
+impl GroupEvent {
+    /// Adds an event to the feed of `group_id`. The feed is informational,
+    /// so a failure is only logged and never fails the change it describes.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn record(
+        group_id: &str,
+        actor_id: &str,
+        event_type: GroupEventType,
+        expense_id: Option<&str>,
+        split_id: Option<&str>,
+        user_id: Option<&str>,
+        details: Option<&str>,
+        pool: &SqlitePool,
+    ) {
+        let id = uuid::Uuid::new_v4().to_string();
+        let event_type = event_type.to_string();
+        let time = chrono::Utc::now().to_rfc3339();
+        let result = sqlx::query!(
+            r#"INSERT INTO group_events(id, group_id, actor_id, event_type, expense_id, split_id, user_id, details, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+            id,
+            group_id,
+            actor_id,
+            event_type,
+            expense_id,
+            split_id,
+            user_id,
+            details,
+            time
+        )
+        .execute(pool)
+        .await;
+        if let Err(err) = result {
+            log::warn!("Failed to record group event {err:?}")
+        }
+    }
+
+    /// Events of a group, newest first, older than `from_time` if given.
+    pub async fn get_for_group(
+        group_id: &str,
+        limit: u32,
+        from_time: Option<String>,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<GroupEvent>> {
+        let events = sqlx::query_as!(
+            GroupEvent,
+            r#"
+            SELECT * FROM group_events
+            WHERE group_id = $1 AND ($3 IS NULL OR created_at < $3)
+            ORDER BY created_at DESC
+            LIMIT $2
+            "#,
+            group_id,
+            limit,
+            from_time
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(events)
+    }
+}
@@ -0,0 +1,255 @@
+use std::str::FromStr;
+
+use async_graphql::{Context, Enum, Object};
+use sqlx::SqlitePool;
+use strum::{Display, EnumString};
+
+use crate::{notification::notify_user, s3::S3, schema::get_pool_from_context};
+
+use super::{
+    group::{Group, GroupRole},
+    pending_expense::PendingExpense,
+    user::{InvitationPolicy, User, UserConfig},
+};
+
+#[derive(EnumString, Enum, Clone, Copy, PartialEq, Eq, Display)]
+pub enum InvitationStatus {
+    Pending,
+    Accepted,
+    Declined,
+}
+
+/// A request for a user to join a group, waiting for them to answer.
+pub struct GroupInvitation {
+    pub id: String,
+    pub group_id: String,
+    pub user_id: String,
+    pub invited_by: String,
+    pub role: String,
+    pub status: String,
+    pub created_at: String,
+    pub responded_at: Option<String>,
+}
+
+#[Object]
+impl GroupInvitation {
+    pub async fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub async fn group<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Group> {
+        let pool = get_pool_from_context(context).await?;
+        Group::get_from_id(&self.group_id, pool).await
+    }
+
+    pub async fn user_id(&self) -> &str {
+        &self.user_id
+    }
+
+    pub async fn inviter<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<User> {
+        let pool = get_pool_from_context(context).await?;
+        User::get_from_id(&self.invited_by, pool).await
+    }
+
+    pub async fn role(&self) -> GroupRole {
+        self.get_role()
+    }
+
+    pub async fn status(&self) -> InvitationStatus {
+        InvitationStatus::from_str(&self.status).unwrap_or(InvitationStatus::Pending)
+    }
+
+    pub async fn created_at(&self) -> &str {
+        &self.created_at
+    }
+
+    pub async fn responded_at(&self) -> &Option<String> {
+        &self.responded_at
+    }
+}
+
+impl GroupInvitation {
+    pub fn get_role(&self) -> GroupRole {
+        GroupRole::from_str(&self.role).unwrap_or(GroupRole::Member)
+    }
+
+    /// Adds `user` to the group straight away when their invitation policy
+    /// allows it, otherwise leaves a pending invitation and notifies them.
+    /// Returns the invitation if one is waiting for an answer.
+    pub async fn invite(
+        group: &Group,
+        inviter: &User,
+        user: &User,
+        role: GroupRole,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Option<GroupInvitation>> {
+        if Group::get_role(&group.id, &user.id, pool).await.is_ok() {
+            return Ok(None);
+        }
+        let policy = UserConfig::get_for_user(&user.id, pool)
+            .await
+            .map(|config| config.get_invitation_policy())
+            .unwrap_or(InvitationPolicy::AutoAcceptContacts);
+        if policy == InvitationPolicy::AutoAcceptContacts
+            && Self::are_contacts(&inviter.id, &user.id, pool).await?
+        {
+            Group::add_to_group(&group.id, &user.id, role, pool).await?;
+            return Ok(None);
+        }
+        if let Some(invitation) = Self::get_pending(&group.id, &user.id, pool).await? {
+            return Ok(Some(invitation));
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let role = role.to_string();
+        let status = InvitationStatus::Pending.to_string();
+        let time = chrono::Utc::now().to_rfc3339();
+        let invitation = sqlx::query_as!(
+            GroupInvitation,
+            r#"INSERT INTO group_invitations(id, group_id, user_id, invited_by, role, status, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *
+            "#,
+            id,
+            group.id,
+            user.id,
+            inviter.id,
+            role,
+            status,
+            time
+        )
+        .fetch_one(pool)
+        .await?;
+
+        let inviter_name = inviter.name.as_deref().unwrap_or("Someone");
+        let group_name = group.name.as_deref().unwrap_or("Direct Payment");
+        notify_user(
+            user,
+            &format!("{inviter_name} invited you to group {group_name}"),
+            &format!("Accept or decline the invitation from {inviter_name} to join {group_name}"),
+            Some("group_invitation"),
+        )
+        .await;
+        Ok(Some(invitation))
+    }
+
+    /// Whether the two users share a group, now or in the past.
+    async fn are_contacts(
+        user_id: &str,
+        other_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<bool> {
+        let shared = sqlx::query!(
+            r#"
+            SELECT a.group_id FROM group_memberships a
+                JOIN group_memberships b ON a.group_id = b.group_id
+            WHERE a.user_id = $1 AND b.user_id = $2
+            LIMIT 1
+            "#,
+            user_id,
+            other_id
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(shared.is_some())
+    }
+
+    pub async fn get_from_id(id: &str, pool: &SqlitePool) -> anyhow::Result<GroupInvitation> {
+        let invitation = sqlx::query_as!(
+            GroupInvitation,
+            "SELECT * FROM group_invitations WHERE id = $1",
+            id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(invitation)
+    }
+
+    pub async fn get_pending(
+        group_id: &str,
+        user_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Option<GroupInvitation>> {
+        let invitation = sqlx::query_as!(
+            GroupInvitation,
+            "SELECT * FROM group_invitations WHERE group_id = $1 AND user_id = $2 AND status = 'Pending'",
+            group_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(invitation)
+    }
+
+    /// Invitations the user still has to answer, newest first.
+    pub async fn get_pending_for_user(
+        user_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<GroupInvitation>> {
+        let invitations = sqlx::query_as!(
+            GroupInvitation,
+            "SELECT * FROM group_invitations WHERE user_id = $1 AND status = 'Pending' ORDER BY created_at DESC",
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(invitations)
+    }
+
+    /// Whether anyone invited to the group has not answered yet.
+    pub async fn has_pending(group_id: &str, pool: &SqlitePool) -> anyhow::Result<bool> {
+        let pending = sqlx::query!(
+            "SELECT id FROM group_invitations WHERE group_id = $1 AND status = 'Pending' LIMIT 1",
+            group_id
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(pending.is_some())
+    }
+
+    /// Accepts or declines the invitation, joining the group on accept.
+    /// Expenses waiting on the group are added once the last invitation is
+    /// accepted, and dropped when one is declined.
+    pub async fn respond(
+        &self,
+        accept: bool,
+        s3: &S3,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<GroupInvitation> {
+        let status = if accept {
+            InvitationStatus::Accepted
+        } else {
+            InvitationStatus::Declined
+        }
+        .to_string();
+        let time = chrono::Utc::now().to_rfc3339();
+        let invitation = sqlx::query_as!(
+            GroupInvitation,
+            r#"UPDATE group_invitations SET status = $2, responded_at = $3
+            WHERE id = $1 AND status = 'Pending'
+            RETURNING *
+            "#,
+            self.id,
+            status,
+            time
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Invitation already answered"))?;
+        if accept {
+            Group::add_to_group(
+                &invitation.group_id,
+                &invitation.user_id,
+                invitation.get_role(),
+                pool,
+            )
+            .await?;
+            if !Self::has_pending(&invitation.group_id, pool).await? {
+                PendingExpense::apply_for_group(&invitation.group_id, s3, pool).await;
+            }
+        } else {
+            PendingExpense::delete_for_group(&invitation.group_id, pool).await?;
+        }
+        Ok(invitation)
+    }
+}
@@ -0,0 +1,258 @@
+use std::str::FromStr;
+
+use async_graphql::{Context, Object, SimpleObject};
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use sqlx::SqlitePool;
+
+use crate::schema::get_pool_from_context;
+
+use super::{
+    group::{Group, GroupRole},
+    user::User,
+};
+
+/// How long a link works when no expiry is given.
+const DEFAULT_EXPIRY_DAYS: i64 = 7;
+const TOKEN_LENGTH: usize = 24;
+
+/// A shareable link that lets anyone holding the token join a group.
+pub struct GroupInvite {
+    pub id: String,
+    pub token: String,
+    pub group_id: String,
+    pub created_by: String,
+    pub role: String,
+    pub max_uses: Option<i64>,
+    pub uses: i64,
+    pub expires_at: String,
+    pub revoked_at: Option<String>,
+    pub created_at: String,
+}
+
+/// What someone holding an invite sees before joining.
+#[derive(SimpleObject)]
+pub struct GroupInvitePreview {
+    pub group_id: String,
+    pub name: Option<String>,
+    pub member_count: i64,
+    pub role: GroupRole,
+    pub inviter_name: Option<String>,
+    pub expires_at: String,
+}
+
+#[Object]
+impl GroupInvite {
+    pub async fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub async fn token(&self) -> &str {
+        &self.token
+    }
+
+    pub async fn group<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Group> {
+        let pool = get_pool_from_context(context).await?;
+        Group::get_from_id(&self.group_id, pool).await
+    }
+
+    pub async fn creator<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<User> {
+        let pool = get_pool_from_context(context).await?;
+        User::get_from_id(&self.created_by, pool).await
+    }
+
+    pub async fn role(&self) -> GroupRole {
+        self.get_role()
+    }
+
+    pub async fn max_uses(&self) -> Option<i64> {
+        self.max_uses
+    }
+
+    pub async fn uses(&self) -> i64 {
+        self.uses
+    }
+
+    pub async fn expires_at(&self) -> &str {
+        &self.expires_at
+    }
+
+    pub async fn revoked_at(&self) -> &Option<String> {
+        &self.revoked_at
+    }
+
+    pub async fn created_at(&self) -> &str {
+        &self.created_at
+    }
+
+    pub async fn is_active(&self) -> bool {
+        self.check_usable().is_ok()
+    }
+}
+
+impl GroupInvite {
+    pub fn get_role(&self) -> GroupRole {
+        GroupRole::from_str(&self.role).unwrap_or(GroupRole::Member)
+    }
+
+    /// Fails with the reason the link can not be used any more.
+    pub fn check_usable(&self) -> anyhow::Result<()> {
+        if self.revoked_at.is_some() {
+            return Err(anyhow::anyhow!("Invite was revoked"));
+        }
+        if self.expires_at <= Utc::now().to_rfc3339() {
+            return Err(anyhow::anyhow!("Invite has expired"));
+        }
+        if self.max_uses.is_some_and(|max_uses| self.uses >= max_uses) {
+            return Err(anyhow::anyhow!("Invite has been used up"));
+        }
+        Ok(())
+    }
+
+    pub async fn create(
+        group_id: &str,
+        created_by: &str,
+        role: GroupRole,
+        max_uses: Option<i64>,
+        expires_at: Option<String>,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<GroupInvite> {
+        if max_uses.is_some_and(|max_uses| max_uses <= 0) {
+            return Err(anyhow::anyhow!("Max uses must be greater than 0"));
+        }
+        let now = Utc::now();
+        let expires_at = match expires_at {
+            Some(expires_at) => DateTime::parse_from_rfc3339(&expires_at)?.with_timezone(&Utc),
+            None => now + chrono::Duration::days(DEFAULT_EXPIRY_DAYS),
+        };
+        if expires_at <= now {
+            return Err(anyhow::anyhow!("Expiry must be in the future"));
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        let token = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(TOKEN_LENGTH)
+            .map(char::from)
+            .collect::<String>();
+        let role = role.to_string();
+        let expires_at = expires_at.to_rfc3339();
+        let time = now.to_rfc3339();
+        let invite = sqlx::query_as!(
+            GroupInvite,
+            r#"INSERT INTO group_invites(id, token, group_id, created_by, role, max_uses, expires_at, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING *
+            "#,
+            id,
+            token,
+            group_id,
+            created_by,
+            role,
+            max_uses,
+            expires_at,
+            time
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(invite)
+    }
+
+    pub async fn get_from_id(id: &str, pool: &SqlitePool) -> anyhow::Result<GroupInvite> {
+        let invite = sqlx::query_as!(GroupInvite, "SELECT * FROM group_invites WHERE id = $1", id)
+            .fetch_one(pool)
+            .await?;
+        Ok(invite)
+    }
+
+    pub async fn get_from_token(token: &str, pool: &SqlitePool) -> anyhow::Result<GroupInvite> {
+        let invite = sqlx::query_as!(
+            GroupInvite,
+            "SELECT * FROM group_invites WHERE token = $1",
+            token
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Invalid invite"))?;
+        Ok(invite)
+    }
+
+    /// Links of a group that can still be used, newest first.
+    pub async fn get_for_group(
+        group_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<GroupInvite>> {
+        let time = Utc::now().to_rfc3339();
+        let invites = sqlx::query_as!(
+            GroupInvite,
+            r#"
+            SELECT * FROM group_invites
+            WHERE group_id = $1 AND revoked_at IS NULL AND expires_at > $2
+                AND (max_uses IS NULL OR uses < max_uses)
+            ORDER BY created_at DESC
+            "#,
+            group_id,
+            time
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(invites)
+    }
+
+    pub async fn preview(token: &str, pool: &SqlitePool) -> anyhow::Result<GroupInvitePreview> {
+        let invite = Self::get_from_token(token, pool).await?;
+        invite.check_usable()?;
+        let group = Group::get_from_id(&invite.group_id, pool).await?;
+        let member_count = Group::get_users(&group.id, pool).await?.len() as i64;
+        let inviter = User::get_from_id(&invite.created_by, pool).await?;
+        Ok(GroupInvitePreview {
+            group_id: group.id,
+            name: group.name,
+            member_count,
+            role: invite.get_role(),
+            inviter_name: inviter.name,
+            expires_at: invite.expires_at,
+        })
+    }
+
+    /// Counts one use of the link. Done in a single update so concurrent
+    /// joins can not go over `max_uses`.
+    pub async fn claim(token: &str, pool: &SqlitePool) -> anyhow::Result<GroupInvite> {
+        let time = Utc::now().to_rfc3339();
+        let invite = sqlx::query_as!(
+            GroupInvite,
+            r#"UPDATE group_invites SET uses = uses + 1
+            WHERE token = $1 AND revoked_at IS NULL AND expires_at > $2
+                AND (max_uses IS NULL OR uses < max_uses)
+            RETURNING *
+            "#,
+            token,
+            time
+        )
+        .fetch_optional(pool)
+        .await?;
+        match invite {
+            Some(invite) => Ok(invite),
+            None => {
+                Self::get_from_token(token, pool).await?.check_usable()?;
+                Err(anyhow::anyhow!("Invalid invite"))
+            }
+        }
+    }
+
+    pub async fn revoke(id: &str, pool: &SqlitePool) -> anyhow::Result<GroupInvite> {
+        let time = Utc::now().to_rfc3339();
+        let invite = sqlx::query_as!(
+            GroupInvite,
+            r#"UPDATE group_invites SET revoked_at = $2
+            WHERE id = $1 AND revoked_at IS NULL
+            RETURNING *
+            "#,
+            id,
+            time
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Invite already revoked"))?;
+        Ok(invite)
+    }
+}
@@ -1,6 +1,19 @@
 pub mod amount;
+pub mod balance;
+pub mod budget;
+pub mod category;
+pub mod comment;
 pub mod currency;
 pub mod expense;
+pub mod expense_item;
 pub mod group;
+pub mod group_event;
+pub mod group_invitation;
+pub mod group_invite;
+pub mod pending_expense;
+pub mod recurring_expense;
+pub mod revision;
+pub mod session;
 pub mod split;
+pub mod split_strategy;
 pub mod user;
@@ -0,0 +1,188 @@
+use sqlx::SqlitePool;
+
+use crate::{s3::S3, schema::mutation::PayerInput};
+
+use super::{
+    amount::Amount,
+    budget::Budget,
+    expense::Expense,
+    group::Group,
+    group_event::{GroupEvent, GroupEventType},
+    split_strategy::SplitStrategy,
+    user::User,
+};
+
+/// An expense added to a direct group before everyone invited to it has
+/// accepted. It is added to the group once the last invitation is accepted
+/// and dropped if any of them is declined.
+pub struct PendingExpense {
+    pub id: String,
+    pub group_id: String,
+    pub created_by: String,
+    pub title: String,
+    pub amount: i64,
+    pub currency_id: String,
+    pub category: String,
+    pub note: Option<String>,
+    pub image_id: Option<String>,
+    pub split_strategy: String,
+    pub transaction_at: Option<String>,
+    pub created_at: String,
+}
+
+impl PendingExpense {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create(
+        user_id: &str,
+        group_id: &str,
+        title: &str,
+        amount: &Amount,
+        split_strategy: &SplitStrategy,
+        category: &str,
+        note: Option<String>,
+        image_id: Option<String>,
+        transaction_at: Option<String>,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<PendingExpense> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let time = chrono::Utc::now().to_rfc3339();
+        let split_strategy = split_strategy.to_json()?;
+        let pending = sqlx::query_as!(
+            PendingExpense,
+            r#"INSERT INTO pending_expenses(id, group_id, created_by, title, amount, currency_id, category, note, image_id, split_strategy, transaction_at, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            RETURNING *
+            "#,
+            id,
+            group_id,
+            user_id,
+            title,
+            amount.amount,
+            amount.currency_id,
+            category,
+            note,
+            image_id,
+            split_strategy,
+            transaction_at,
+            time
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(pending)
+    }
+
+    pub async fn get_for_group(
+        group_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<PendingExpense>> {
+        let pending = sqlx::query_as!(
+            PendingExpense,
+            "SELECT * FROM pending_expenses WHERE group_id = $1 ORDER BY created_at",
+            group_id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(pending)
+    }
+
+    pub async fn delete_for_group(group_id: &str, pool: &SqlitePool) -> anyhow::Result<()> {
+        sqlx::query!("DELETE FROM pending_expenses WHERE group_id = $1", group_id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Adds every pending expense of the group. Ones that fail are kept and
+    /// logged so they are not lost.
+    pub async fn apply_for_group(group_id: &str, s3: &S3, pool: &SqlitePool) {
+        let pending = match Self::get_for_group(group_id, pool).await {
+            Ok(pending) => pending,
+            Err(err) => {
+                log::warn!("Failed to get pending expenses for {group_id} {err:?}");
+                return;
+            }
+        };
+        for pending in pending {
+            if let Err(err) = pending.apply(s3, pool).await {
+                log::warn!("Failed to add pending expense {} {err:?}", pending.id);
+            }
+        }
+    }
+
+    async fn apply(&self, s3: &S3, pool: &SqlitePool) -> anyhow::Result<()> {
+        let creator = User::get_from_id(&self.created_by, pool).await?;
+        let strategy = SplitStrategy::from_json(&self.split_strategy)?;
+        let splits = strategy
+            .resolve_owed_to(self.amount, &self.created_by)?
+            .into_iter()
+            .filter(|split| split.amount > 0)
+            .collect::<Vec<_>>();
+        Group::get_from_id(&self.group_id, pool)
+            .await?
+            .check_not_archived()?;
+        let group_members = Group::get_users(&self.group_id, pool).await?;
+        if !splits
+            .iter()
+            .all(|s| group_members.iter().any(|user| user.id == s.user_id))
+        {
+            return Err(anyhow::anyhow!("Not everyone is group member"));
+        }
+        // Claimed in the same transaction, so when two invitees accept at
+        // once only one of them adds the expense.
+        let mut transaction = pool.begin().await?;
+        let claimed = sqlx::query!(
+            "DELETE FROM pending_expenses WHERE id = $1 RETURNING id",
+            self.id
+        )
+        .fetch_optional(transaction.as_mut())
+        .await?;
+        if claimed.is_none() {
+            return Ok(());
+        }
+        let expense = Expense::insert_new_expense(
+            &self.created_by,
+            &self.title,
+            &self.group_id,
+            &Amount {
+                amount: self.amount,
+                currency_id: self.currency_id.clone(),
+            },
+            splits.clone(),
+            vec![PayerInput {
+                amount: self.amount,
+                user_id: self.created_by.clone(),
+            }],
+            None,
+            None,
+            &self.category,
+            self.note.clone(),
+            self.image_id.clone(),
+            self.transaction_at.clone(),
+            s3,
+            &mut transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+        expense
+            .notify_new_expense(&creator, &splits, true, pool)
+            .await?;
+        for split in expense.get_splits(pool).await?.iter() {
+            let _ = Group::simplify_cross_group(&split.to_user, &split.from_user, pool).await;
+        }
+        GroupEvent::record(
+            &self.group_id,
+            &self.created_by,
+            GroupEventType::ExpenseAdded,
+            Some(&expense.id),
+            None,
+            None,
+            None,
+            pool,
+        )
+        .await;
+        if let Err(err) = Budget::check_alerts(&self.group_id, pool).await {
+            log::warn!("Failed to check budgets {err:?}")
+        }
+        Ok(())
+    }
+}
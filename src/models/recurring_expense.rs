@@ -0,0 +1,641 @@
+use async_graphql::{Context, Enum, InputObject, Object, SimpleObject};
+use chrono::{DateTime, Datelike, Months, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::{
+    s3::S3,
+    schema::{get_pool_from_context, mutation::PayerInput},
+};
+
+use super::{
+    amount::Amount,
+    budget::Budget,
+    expense::Expense,
+    group::Group,
+    group_event::{GroupEvent, GroupEventType},
+    split_strategy::SplitStrategy,
+    user::User,
+};
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum RecurrenceFrequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// A small subset of RRULE, e.g. monthly on the 1st or every 2 weeks.
+#[derive(SimpleObject, InputObject, Clone, Debug, Serialize, Deserialize)]
+#[graphql(input_name = "RecurrenceRuleInput")]
+pub struct RecurrenceRule {
+    pub frequency: RecurrenceFrequency,
+    /// Repeat every `interval` days, weeks, months or years.
+    #[graphql(default = 1)]
+    pub interval: u32,
+    /// Day of the month for monthly and yearly rules, months that are too
+    /// short use their last day. Defaults to the day of the first run.
+    pub by_month_day: Option<u32>,
+}
+
+impl RecurrenceRule {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.interval == 0 {
+            return Err(anyhow::anyhow!("Interval must be greater than 0"));
+        }
+        if let Some(day) = self.by_month_day {
+            if !(1..=31).contains(&day) {
+                return Err(anyhow::anyhow!("Day of month must be between 1 and 31"));
+            }
+        }
+        Ok(())
+    }
+
+    /// The first run at or after `start`. Monthly and yearly rules remember
+    /// the day of the month so later runs do not drift after a short month.
+    pub fn first_run(&mut self, start: DateTime<Utc>) -> anyhow::Result<DateTime<Utc>> {
+        let months = match self.frequency {
+            RecurrenceFrequency::Daily | RecurrenceFrequency::Weekly => return Ok(start),
+            RecurrenceFrequency::Monthly => 1,
+            RecurrenceFrequency::Yearly => 12,
+        };
+        let day = *self.by_month_day.get_or_insert(start.day());
+        let run = with_month_day(start, day)?;
+        if run >= start {
+            Ok(run)
+        } else {
+            add_months(start, months, day)
+        }
+    }
+
+    pub fn next_run(&self, last: DateTime<Utc>) -> anyhow::Result<DateTime<Utc>> {
+        let day = self.by_month_day.unwrap_or(last.day());
+        match self.frequency {
+            RecurrenceFrequency::Daily => Ok(last + chrono::Duration::days(self.interval as i64)),
+            RecurrenceFrequency::Weekly => Ok(last + chrono::Duration::weeks(self.interval as i64)),
+            RecurrenceFrequency::Monthly => add_months(last, self.interval, day),
+            RecurrenceFrequency::Yearly => add_months(last, self.interval * 12, day),
+        }
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn from_json(value: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(value)?)
+    }
+}
+
+fn with_month_day(time: DateTime<Utc>, day: u32) -> anyhow::Result<DateTime<Utc>> {
+    let first = time
+        .with_day(1)
+        .ok_or_else(|| anyhow::anyhow!("Invalid date"))?;
+    let last_day = (first + Months::new(1) - chrono::Duration::days(1)).day();
+    first
+        .with_day(day.min(last_day))
+        .ok_or_else(|| anyhow::anyhow!("Invalid date"))
+}
+
+fn add_months(time: DateTime<Utc>, months: u32, day: u32) -> anyhow::Result<DateTime<Utc>> {
+    let month = time
+        .with_day(1)
+        .and_then(|first| first.checked_add_months(Months::new(months)))
+        .ok_or_else(|| anyhow::anyhow!("Invalid date"))?;
+    with_month_day(month, day)
+}
+
+/// Runs added for one template per tick, so a template that is far behind
+/// catches up over several ticks.
+const MAX_CATCH_UP_RUNS: usize = 12;
+
+/// Template for an expense that is added again on a schedule, e.g. rent.
+/// The creator always pays the whole amount of every run.
+pub struct RecurringExpense {
+    pub id: String,
+    pub group_id: String,
+    pub created_by: String,
+    pub title: String,
+    pub amount: i64,
+    pub currency_id: String,
+    pub category: String,
+    pub note: Option<String>,
+    pub split_strategy: String,
+    pub rule: String,
+    pub next_run_at: String,
+    pub paused_at: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[Object]
+impl RecurringExpense {
+    pub async fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub async fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub async fn group<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Group> {
+        let pool = get_pool_from_context(context).await?;
+        Group::get_from_id(&self.group_id, pool).await
+    }
+
+    pub async fn creator<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<User> {
+        let pool = get_pool_from_context(context).await?;
+        User::get_from_id(&self.created_by, pool).await
+    }
+
+    pub async fn creator_id(&self) -> &str {
+        &self.created_by
+    }
+
+    pub async fn amount(&self) -> Amount {
+        Amount {
+            amount: self.amount,
+            currency_id: self.currency_id.clone(),
+        }
+    }
+
+    pub async fn category(&self) -> &str {
+        &self.category
+    }
+
+    pub async fn note(&self) -> &Option<String> {
+        &self.note
+    }
+
+    pub async fn split_strategy(&self) -> anyhow::Result<SplitStrategy> {
+        SplitStrategy::from_json(&self.split_strategy)
+    }
+
+    pub async fn rule(&self) -> anyhow::Result<RecurrenceRule> {
+        RecurrenceRule::from_json(&self.rule)
+    }
+
+    pub async fn next_run_at(&self) -> &str {
+        &self.next_run_at
+    }
+
+    pub async fn paused_at(&self) -> &Option<String> {
+        &self.paused_at
+    }
+
+    pub async fn created_at(&self) -> &str {
+        &self.created_at
+    }
+
+    pub async fn updated_at(&self) -> &str {
+        &self.updated_at
+    }
+}
+
+impl RecurringExpense {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create(
+        user_id: &str,
+        group_id: &str,
+        title: &str,
+        amount: &Amount,
+        split_strategy: &SplitStrategy,
+        category: &str,
+        note: Option<String>,
+        mut rule: RecurrenceRule,
+        starts_at: Option<String>,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<RecurringExpense> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let time = chrono::Utc::now();
+        let start = parse_time(starts_at.as_deref())?.unwrap_or(time);
+        validate_start(start, time)?;
+        let next_run_at = rule.first_run(start)?.to_rfc3339();
+        let time = time.to_rfc3339();
+        let split_strategy = split_strategy.to_json()?;
+        let rule = rule.to_json()?;
+        let recurring = sqlx::query_as!(
+            RecurringExpense,
+            r#"INSERT INTO recurring_expenses(id, group_id, created_by, title, amount, currency_id, category, note, split_strategy, rule, next_run_at, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $12)
+            RETURNING *
+            "#,
+            id,
+            group_id,
+            user_id,
+            title,
+            amount.amount,
+            amount.currency_id,
+            category,
+            note,
+            split_strategy,
+            rule,
+            next_run_at,
+            time
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(recurring)
+    }
+
+    /// Updates the given fields. A new rule or start time moves the next run.
+    /// An empty `note` clears it.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn edit(
+        id: &str,
+        title: Option<&str>,
+        amount: Option<i64>,
+        currency_id: Option<String>,
+        split_strategy: Option<&SplitStrategy>,
+        category: Option<String>,
+        note: Option<String>,
+        rule: Option<RecurrenceRule>,
+        starts_at: Option<String>,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<RecurringExpense> {
+        let time = chrono::Utc::now();
+        let start = parse_time(starts_at.as_deref())?;
+        if let Some(start) = start {
+            validate_start(start, time)?;
+        }
+        let (rule, next_run_at) = match (rule, start) {
+            (None, None) => (None, None),
+            (rule, start) => {
+                let mut rule = match rule {
+                    Some(rule) => rule,
+                    None => Self::get_from_id(id, pool).await?.get_rule()?,
+                };
+                let next_run_at = rule.first_run(start.unwrap_or(time))?.to_rfc3339();
+                (Some(rule.to_json()?), Some(next_run_at))
+            }
+        };
+        let time = time.to_rfc3339();
+        let split_strategy = split_strategy.map(|s| s.to_json()).transpose()?;
+        let recurring = sqlx::query_as!(
+            RecurringExpense,
+            r#"UPDATE recurring_expenses SET
+                title = COALESCE($2, title),
+                amount = COALESCE($3, amount),
+                currency_id = COALESCE($4, currency_id),
+                split_strategy = COALESCE($5, split_strategy),
+                category = COALESCE($6, category),
+                note = CASE WHEN $7 IS NULL THEN note ELSE NULLIF($7, '') END,
+                rule = COALESCE($8, rule),
+                next_run_at = COALESCE($9, next_run_at),
+                updated_at = $10
+            WHERE id = $1
+            RETURNING *
+            "#,
+            id,
+            title,
+            amount,
+            currency_id,
+            split_strategy,
+            category,
+            note,
+            rule,
+            next_run_at,
+            time
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(recurring)
+    }
+
+    /// Pausing stops new expenses. Resuming skips the runs missed while
+    /// paused instead of adding them all at once.
+    pub async fn set_paused(
+        id: &str,
+        paused: bool,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<RecurringExpense> {
+        let recurring = Self::get_from_id(id, pool).await?;
+        let now = chrono::Utc::now();
+        let time = now.to_rfc3339();
+        let (paused_at, next_run_at) = if paused {
+            (Some(time.clone()), recurring.next_run_at.clone())
+        } else {
+            let rule = recurring.get_rule()?;
+            let mut next_run_at = recurring.get_next_run_at()?;
+            while next_run_at < now {
+                next_run_at = rule.next_run(next_run_at)?;
+            }
+            (None, next_run_at.to_rfc3339())
+        };
+        let recurring = sqlx::query_as!(
+            RecurringExpense,
+            r#"UPDATE recurring_expenses SET paused_at = $2, next_run_at = $3, updated_at = $4
+            WHERE id = $1
+            RETURNING *
+            "#,
+            id,
+            paused_at,
+            next_run_at,
+            time
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(recurring)
+    }
+
+    pub async fn delete(id: &str, pool: &SqlitePool) -> anyhow::Result<()> {
+        sqlx::query!("DELETE FROM recurring_expenses WHERE id = $1", id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_from_id(id: &str, pool: &SqlitePool) -> anyhow::Result<RecurringExpense> {
+        let recurring = sqlx::query_as!(
+            RecurringExpense,
+            "SELECT * FROM recurring_expenses WHERE id = $1",
+            id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(recurring)
+    }
+
+    pub async fn get_for_group(
+        group_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<RecurringExpense>> {
+        let recurring = sqlx::query_as!(
+            RecurringExpense,
+            "SELECT * FROM recurring_expenses WHERE group_id = $1 ORDER BY next_run_at",
+            group_id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(recurring)
+    }
+
+    pub fn get_rule(&self) -> anyhow::Result<RecurrenceRule> {
+        RecurrenceRule::from_json(&self.rule)
+    }
+
+    fn get_next_run_at(&self) -> anyhow::Result<DateTime<Utc>> {
+        Ok(parse_time(Some(&self.next_run_at))?.unwrap_or_else(chrono::Utc::now))
+    }
+
+    /// Adds an expense for every run that is due. Runs missed while the
+    /// server was down are added with their original dates, at most
+    /// [`MAX_CATCH_UP_RUNS`] per template each time.
+    pub async fn run_due(pool: &SqlitePool, s3: &S3) -> anyhow::Result<()> {
+        let now = chrono::Utc::now();
+        let time = now.to_rfc3339();
+        let due = sqlx::query_as!(
+            RecurringExpense,
+            "SELECT * FROM recurring_expenses WHERE paused_at IS NULL AND next_run_at <= $1",
+            time
+        )
+        .fetch_all(pool)
+        .await?;
+        for recurring in due {
+            if let Err(err) = recurring.run(now, pool, s3).await {
+                log::warn!("Failed to add recurring expense {} {err:?}", recurring.id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the due runs oldest first. A failed run stops here and keeps
+    /// `next_run_at`, so it is tried again on the next tick.
+    async fn run(&self, now: DateTime<Utc>, pool: &SqlitePool, s3: &S3) -> anyhow::Result<()> {
+        let rule = self.get_rule()?;
+        let mut run_at = self.get_next_run_at()?;
+        let mut stored_run_at = self.next_run_at.clone();
+        for _ in 0..MAX_CATCH_UP_RUNS {
+            if run_at > now {
+                break;
+            }
+            let next_run_at = rule.next_run(run_at)?;
+            self.add_expense(run_at, &stored_run_at, next_run_at, pool, s3)
+                .await?;
+            run_at = next_run_at;
+            stored_run_at = next_run_at.to_rfc3339();
+        }
+        Ok(())
+    }
+
+    /// Adds the expense for `run_at` and moves the template to `next_run_at`
+    /// in one transaction. `stored_run_at` is `next_run_at` as read, if it
+    /// changed meanwhile another instance took the run and nothing is added.
+    async fn add_expense(
+        &self,
+        run_at: DateTime<Utc>,
+        stored_run_at: &str,
+        next_run_at: DateTime<Utc>,
+        pool: &SqlitePool,
+        s3: &S3,
+    ) -> anyhow::Result<()> {
+        let creator = User::get_from_id(&self.created_by, pool).await?;
+        let strategy = SplitStrategy::from_json(&self.split_strategy)?;
+        let splits = strategy
+            .resolve_owed_to(self.amount, &self.created_by)?
+            .into_iter()
+            .filter(|split| split.amount > 0)
+            .collect::<Vec<_>>();
+        Group::get_from_id(&self.group_id, pool)
+            .await?
+            .check_not_archived()?;
+        let group_members = Group::get_users(&self.group_id, pool).await?;
+        if !splits
+            .iter()
+            .all(|s| group_members.iter().any(|user| user.id == s.user_id))
+        {
+            return Err(anyhow::anyhow!("Not everyone is group member"));
+        }
+        let mut transaction = pool.begin().await?;
+        let expense = Expense::insert_new_expense(
+            &self.created_by,
+            &self.title,
+            &self.group_id,
+            &Amount {
+                amount: self.amount,
+                currency_id: self.currency_id.clone(),
+            },
+            splits.clone(),
+            vec![PayerInput {
+                amount: self.amount,
+                user_id: self.created_by.clone(),
+            }],
+            Some(&strategy),
+            None,
+            &self.category,
+            self.note.clone(),
+            None,
+            Some(run_at.to_rfc3339()),
+            s3,
+            &mut transaction,
+        )
+        .await?;
+        let next_run_at = next_run_at.to_rfc3339();
+        let moved = sqlx::query!(
+            "UPDATE recurring_expenses SET next_run_at = $2 WHERE id = $1 AND next_run_at = $3",
+            self.id,
+            next_run_at,
+            stored_run_at
+        )
+        .execute(transaction.as_mut())
+        .await?;
+        if moved.rows_affected() == 0 {
+            transaction.rollback().await?;
+            return Err(anyhow::anyhow!("Run was already added"));
+        }
+        transaction.commit().await?;
+        expense
+            .notify_new_expense(&creator, &splits, true, pool)
+            .await?;
+        for split in expense.get_splits(pool).await?.iter() {
+            let _ = Group::simplify_cross_group(&split.to_user, &split.from_user, pool).await;
+        }
+        GroupEvent::record(
+            &self.group_id,
+            &self.created_by,
+            GroupEventType::ExpenseAdded,
+            Some(&expense.id),
+            None,
+            None,
+            None,
+            pool,
+        )
+        .await;
+        if let Err(err) = Budget::check_alerts(&self.group_id, pool).await {
+            log::warn!("Failed to check budgets {err:?}")
+        }
+        Ok(())
+    }
+}
+
+/// Allows up to a day back, so picking today works in every timezone.
+fn validate_start(start: DateTime<Utc>, now: DateTime<Utc>) -> anyhow::Result<()> {
+    if start < now - chrono::Duration::days(1) {
+        return Err(anyhow::anyhow!("Start can not be in the past"));
+    }
+    Ok(())
+}
+
+fn parse_time(time: Option<&str>) -> anyhow::Result<Option<DateTime<Utc>>> {
+    Ok(time
+        .map(chrono::DateTime::parse_from_rfc3339)
+        .transpose()?
+        .map(|time| time.with_timezone(&Utc)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(value: &str) -> DateTime<Utc> {
+        parse_time(Some(value)).unwrap().unwrap()
+    }
+
+    fn rule(
+        frequency: RecurrenceFrequency,
+        interval: u32,
+        by_month_day: Option<u32>,
+    ) -> RecurrenceRule {
+        RecurrenceRule {
+            frequency,
+            interval,
+            by_month_day,
+        }
+    }
+
+    #[test]
+    fn monthly_on_31st_clamps_to_short_months() {
+        let mut monthly = rule(RecurrenceFrequency::Monthly, 1, None);
+        let first = monthly
+            .first_run(time("2023-01-31T09:00:00+00:00"))
+            .unwrap();
+        assert_eq!(first, time("2023-01-31T09:00:00+00:00"));
+        assert_eq!(monthly.by_month_day, Some(31));
+        let february = monthly.next_run(first).unwrap();
+        assert_eq!(february, time("2023-02-28T09:00:00+00:00"));
+        // The day is remembered, so March is back on the 31st.
+        let march = monthly.next_run(february).unwrap();
+        assert_eq!(march, time("2023-03-31T09:00:00+00:00"));
+        assert_eq!(
+            monthly.next_run(march).unwrap(),
+            time("2023-04-30T09:00:00+00:00")
+        );
+    }
+
+    #[test]
+    fn monthly_uses_29th_february_in_leap_years() {
+        let monthly = rule(RecurrenceFrequency::Monthly, 1, Some(30));
+        assert_eq!(
+            monthly.next_run(time("2024-01-30T00:00:00+00:00")).unwrap(),
+            time("2024-02-29T00:00:00+00:00")
+        );
+        assert_eq!(
+            monthly.next_run(time("2023-01-30T00:00:00+00:00")).unwrap(),
+            time("2023-02-28T00:00:00+00:00")
+        );
+    }
+
+    #[test]
+    fn yearly_on_leap_day() {
+        let mut yearly = rule(RecurrenceFrequency::Yearly, 1, None);
+        let first = yearly.first_run(time("2024-02-29T12:00:00+00:00")).unwrap();
+        assert_eq!(first, time("2024-02-29T12:00:00+00:00"));
+        let next = yearly.next_run(first).unwrap();
+        assert_eq!(next, time("2025-02-28T12:00:00+00:00"));
+        assert_eq!(
+            yearly.next_run(time("2027-02-28T12:00:00+00:00")).unwrap(),
+            time("2028-02-29T12:00:00+00:00")
+        );
+    }
+
+    #[test]
+    fn first_run_moves_to_next_month_when_day_has_passed() {
+        let mut monthly = rule(RecurrenceFrequency::Monthly, 1, Some(1));
+        assert_eq!(
+            monthly
+                .first_run(time("2024-03-15T08:00:00+00:00"))
+                .unwrap(),
+            time("2024-04-01T08:00:00+00:00")
+        );
+        let mut monthly = rule(RecurrenceFrequency::Monthly, 1, Some(20));
+        assert_eq!(
+            monthly
+                .first_run(time("2024-03-15T08:00:00+00:00"))
+                .unwrap(),
+            time("2024-03-20T08:00:00+00:00")
+        );
+    }
+
+    #[test]
+    fn daily_and_weekly_use_interval() {
+        let mut daily = rule(RecurrenceFrequency::Daily, 3, None);
+        let start = time("2024-02-27T00:00:00+00:00");
+        assert_eq!(daily.first_run(start).unwrap(), start);
+        assert_eq!(
+            daily.next_run(start).unwrap(),
+            time("2024-03-01T00:00:00+00:00")
+        );
+        let weekly = rule(RecurrenceFrequency::Weekly, 2, None);
+        assert_eq!(
+            weekly.next_run(start).unwrap(),
+            time("2024-03-12T00:00:00+00:00")
+        );
+    }
+
+    #[test]
+    fn validate_rejects_bad_rules() {
+        assert!(rule(RecurrenceFrequency::Daily, 0, None)
+            .validate()
+            .is_err());
+        assert!(rule(RecurrenceFrequency::Monthly, 1, Some(0))
+            .validate()
+            .is_err());
+        assert!(rule(RecurrenceFrequency::Monthly, 1, Some(32))
+            .validate()
+            .is_err());
+        assert!(rule(RecurrenceFrequency::Monthly, 1, Some(31))
+            .validate()
+            .is_ok());
+    }
+}
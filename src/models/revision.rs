@@ -0,0 +1,252 @@
+use std::str::FromStr;
+
+use async_graphql::{Context, Enum, Object, SimpleObject};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use strum::{Display, EnumString};
+
+use crate::schema::get_pool_from_context;
+
+use super::{expense::Expense, split::Split, user::User};
+
+#[derive(EnumString, Enum, Clone, Copy, PartialEq, Eq, Display)]
+pub enum RevisionEntity {
+    Expense,
+    Split,
+}
+
+#[derive(EnumString, Enum, Clone, Copy, PartialEq, Eq, Display)]
+pub enum RevisionAction {
+    Insert,
+    Update,
+    Delete,
+    Restore,
+}
+
+/// Fields that change on every write and would only add noise to a diff.
+const IGNORED_FIELDS: [&str; 1] = ["updated_at"];
+
+/// Append-only record of a change to an expense or split.
+pub struct Revision {
+    pub id: String,
+    pub entity: String,
+    pub row_id: String,
+    pub expense_id: Option<String>,
+    pub actor_id: String,
+    pub action: String,
+    pub diff: String,
+    pub created_at: String,
+}
+
+#[derive(SimpleObject)]
+pub struct FieldChange {
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+}
+
+#[Object]
+impl Revision {
+    pub async fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub async fn entity(&self) -> RevisionEntity {
+        RevisionEntity::from_str(&self.entity).unwrap_or(RevisionEntity::Expense)
+    }
+
+    pub async fn row_id(&self) -> &str {
+        &self.row_id
+    }
+
+    pub async fn action(&self) -> RevisionAction {
+        RevisionAction::from_str(&self.action).unwrap_or(RevisionAction::Update)
+    }
+
+    pub async fn actor_id(&self) -> &str {
+        &self.actor_id
+    }
+
+    pub async fn actor<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<User> {
+        let pool = get_pool_from_context(context).await?;
+        User::get_from_id(&self.actor_id, pool).await
+    }
+
+    pub async fn changes(&self) -> anyhow::Result<Vec<FieldChange>> {
+        let diff: Map<String, Value> = serde_json::from_str(&self.diff)?;
+        Ok(diff
+            .into_iter()
+            .map(|(field, change)| FieldChange {
+                field,
+                old_value: display_value(&change["old"]),
+                new_value: display_value(&change["new"]),
+            })
+            .collect())
+    }
+
+    pub async fn created_at(&self) -> &str {
+        &self.created_at
+    }
+}
+
+fn display_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(value) => Some(value.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+impl Revision {
+    pub async fn record_expense<'a>(
+        actor_id: &str,
+        before: Option<&Expense>,
+        after: Option<&Expense>,
+        transaction: &mut Transaction<'a, Sqlite>,
+    ) -> anyhow::Result<()> {
+        let Some(expense) = after.or(before) else {
+            return Ok(());
+        };
+        Self::record(
+            RevisionEntity::Expense,
+            &expense.id,
+            Some(&expense.id),
+            actor_id,
+            before,
+            after,
+            transaction,
+        )
+        .await
+    }
+
+    /// Records the difference between two sets of splits, matched by id.
+    /// Splits only in `before` were deleted, splits only in `after` inserted.
+    pub async fn record_splits<'a>(
+        actor_id: &str,
+        before: &[Split],
+        after: &[Split],
+        transaction: &mut Transaction<'a, Sqlite>,
+    ) -> anyhow::Result<()> {
+        for split in before.iter() {
+            let new = after.iter().find(|new| new.id == split.id);
+            Self::record(
+                RevisionEntity::Split,
+                &split.id,
+                split.expense_id.as_deref(),
+                actor_id,
+                Some(split),
+                new,
+                transaction,
+            )
+            .await?;
+        }
+        for split in after
+            .iter()
+            .filter(|split| !before.iter().any(|old| old.id == split.id))
+        {
+            Self::record(
+                RevisionEntity::Split,
+                &split.id,
+                split.expense_id.as_deref(),
+                actor_id,
+                None,
+                Some(split),
+                transaction,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn record<'a, T: Serialize>(
+        entity: RevisionEntity,
+        row_id: &str,
+        expense_id: Option<&str>,
+        actor_id: &str,
+        before: Option<&T>,
+        after: Option<&T>,
+        transaction: &mut Transaction<'a, Sqlite>,
+    ) -> anyhow::Result<()> {
+        let before = to_fields(before)?;
+        let after = to_fields(after)?;
+        let mut diff = Map::new();
+        for field in before.keys().chain(after.keys()) {
+            if IGNORED_FIELDS.contains(&field.as_str()) || diff.contains_key(field) {
+                continue;
+            }
+            let old = before.get(field).cloned().unwrap_or(Value::Null);
+            let new = after.get(field).cloned().unwrap_or(Value::Null);
+            if old != new {
+                diff.insert(field.clone(), serde_json::json!({ "old": old, "new": new }));
+            }
+        }
+        if diff.is_empty() {
+            return Ok(());
+        }
+        let action = if before.is_empty() {
+            RevisionAction::Insert
+        } else if after.is_empty() {
+            RevisionAction::Delete
+        } else {
+            match (before.get("deleted_at"), after.get("deleted_at")) {
+                (Some(Value::Null), Some(Value::String(_))) => RevisionAction::Delete,
+                (Some(Value::String(_)), Some(Value::Null)) => RevisionAction::Restore,
+                _ => RevisionAction::Update,
+            }
+        };
+        let id = uuid::Uuid::new_v4().to_string();
+        let entity = entity.to_string();
+        let action = action.to_string();
+        let diff = Value::Object(diff).to_string();
+        let time = chrono::Utc::now().to_rfc3339();
+        sqlx::query!(
+            "INSERT INTO revisions(id, entity, row_id, expense_id, actor_id, action, diff, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            id,
+            entity,
+            row_id,
+            expense_id,
+            actor_id,
+            action,
+            diff,
+            time
+        )
+        .execute(transaction.as_mut())
+        .await?;
+        Ok(())
+    }
+
+    /// Changes to the expense and all of its splits, oldest first.
+    pub async fn get_for_expense(
+        expense_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<Revision>> {
+        let revisions = sqlx::query_as!(
+            Revision,
+            "SELECT * FROM revisions WHERE expense_id = $1 ORDER BY created_at, rowid",
+            expense_id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(revisions)
+    }
+
+    pub async fn get_for_split(split_id: &str, pool: &SqlitePool) -> anyhow::Result<Vec<Revision>> {
+        let revisions = sqlx::query_as!(
+            Revision,
+            "SELECT * FROM revisions WHERE entity = $1 AND row_id = $2 ORDER BY created_at, rowid",
+            "Split",
+            split_id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(revisions)
+    }
+}
+
+fn to_fields<T: Serialize>(row: Option<&T>) -> anyhow::Result<Map<String, Value>> {
+    match row.map(serde_json::to_value).transpose()? {
+        Some(Value::Object(fields)) => Ok(fields),
+        _ => Ok(Map::new()),
+    }
+}
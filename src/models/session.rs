@@ -0,0 +1,182 @@
+use async_graphql::{Context, Object};
+use sqlx::SqlitePool;
+
+use crate::auth::CurrentSession;
+
+/// Sessions are listed until their refresh token would have expired.
+const SESSION_DAYS: i64 = 30;
+
+/// A signed in device. Every refresh replaces `refresh_token_id`, so a
+/// refresh token can only be used once.
+pub struct Session {
+    pub id: String,
+    pub user_id: String,
+    pub device_name: Option<String>,
+    pub ip: Option<String>,
+    pub refresh_token_id: String,
+    pub created_at: String,
+    pub last_used_at: String,
+    pub revoked_at: Option<String>,
+}
+
+#[Object]
+impl Session {
+    pub async fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub async fn device_name(&self) -> &Option<String> {
+        &self.device_name
+    }
+
+    pub async fn ip(&self) -> &Option<String> {
+        &self.ip
+    }
+
+    pub async fn created_at(&self) -> &str {
+        &self.created_at
+    }
+
+    pub async fn last_used_at(&self) -> &str {
+        &self.last_used_at
+    }
+
+    /// Whether this is the session making the request.
+    pub async fn is_current<'ctx>(&self, context: &Context<'ctx>) -> bool {
+        context
+            .data_opt::<CurrentSession>()
+            .is_some_and(|current| current.0 == self.id)
+    }
+}
+
+impl Session {
+    pub async fn create(
+        user_id: &str,
+        device_name: Option<&str>,
+        ip: Option<&str>,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Session> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let refresh_token_id = uuid::Uuid::new_v4().to_string();
+        let time = chrono::Utc::now().to_rfc3339();
+        let session = sqlx::query_as!(
+            Session,
+            r#"INSERT INTO sessions(id, user_id, device_name, ip, refresh_token_id, created_at, last_used_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $6)
+            RETURNING *
+            "#,
+            id,
+            user_id,
+            device_name,
+            ip,
+            refresh_token_id,
+            time
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(session)
+    }
+
+    pub async fn get_from_id(id: &str, pool: &SqlitePool) -> anyhow::Result<Session> {
+        let session = sqlx::query_as!(Session, "SELECT * FROM sessions WHERE id = $1", id)
+            .fetch_one(pool)
+            .await?;
+        Ok(session)
+    }
+
+    pub async fn is_active(id: &str, pool: &SqlitePool) -> anyhow::Result<bool> {
+        let session = sqlx::query!(
+            "SELECT id FROM sessions WHERE id = $1 AND revoked_at IS NULL",
+            id
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(session.is_some())
+    }
+
+    /// Sessions of the user that can still be refreshed, most recently used
+    /// first.
+    pub async fn get_for_user(user_id: &str, pool: &SqlitePool) -> anyhow::Result<Vec<Session>> {
+        let since = (chrono::Utc::now() - chrono::Duration::days(SESSION_DAYS)).to_rfc3339();
+        let sessions = sqlx::query_as!(
+            Session,
+            r#"
+            SELECT * FROM sessions
+            WHERE user_id = $1 AND revoked_at IS NULL AND last_used_at > $2
+            ORDER BY last_used_at DESC
+            "#,
+            user_id,
+            since
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(sessions)
+    }
+
+    /// Swaps the refresh token `token_id` for a new one. Presenting a token
+    /// that was already swapped means it leaked, so the session is revoked.
+    pub async fn rotate(
+        id: &str,
+        token_id: &str,
+        ip: Option<&str>,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Session> {
+        let new_token_id = uuid::Uuid::new_v4().to_string();
+        let time = chrono::Utc::now().to_rfc3339();
+        let session = sqlx::query_as!(
+            Session,
+            r#"UPDATE sessions SET refresh_token_id = $3, last_used_at = $4, ip = COALESCE($5, ip)
+            WHERE id = $1 AND refresh_token_id = $2 AND revoked_at IS NULL
+            RETURNING *
+            "#,
+            id,
+            token_id,
+            new_token_id,
+            time,
+            ip
+        )
+        .fetch_optional(pool)
+        .await?;
+        if let Some(session) = session {
+            return Ok(session);
+        }
+        let session = Self::get_from_id(id, pool).await?;
+        if session.revoked_at.is_some() {
+            return Err(anyhow::anyhow!("Session revoked"));
+        }
+        log::warn!("Refresh token reused for session {id}, revoking it");
+        Self::revoke(id, &session.user_id, pool).await?;
+        Err(anyhow::anyhow!(
+            "Refresh token already used, session revoked"
+        ))
+    }
+
+    pub async fn revoke(id: &str, user_id: &str, pool: &SqlitePool) -> anyhow::Result<()> {
+        let time = chrono::Utc::now().to_rfc3339();
+        let revoked = sqlx::query!(
+            "UPDATE sessions SET revoked_at = $3 WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+            id,
+            user_id,
+            time
+        )
+        .execute(pool)
+        .await?;
+        if revoked.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Session not found"));
+        }
+        Ok(())
+    }
+
+    /// Revokes every session of the user, returning how many there were.
+    pub async fn revoke_all(user_id: &str, pool: &SqlitePool) -> anyhow::Result<u64> {
+        let time = chrono::Utc::now().to_rfc3339();
+        let revoked = sqlx::query!(
+            "UPDATE sessions SET revoked_at = $2 WHERE user_id = $1 AND revoked_at IS NULL",
+            user_id,
+            time
+        )
+        .execute(pool)
+        .await?;
+        Ok(revoked.rows_affected())
+    }
+}
@@ -1,13 +1,18 @@
 use std::str::FromStr;
 
-use async_graphql::{Context, Enum, Object};
+use async_graphql::{ComplexObject, Context, Enum, Object, SimpleObject};
+use serde::Serialize;
 use sqlx::SqlitePool;
 use strum::{Display, EnumString};
 
 use crate::schema::get_pool_from_context;
 
-use super::{amount::Amount, expense::Expense, group::Group, user::User};
+use super::{
+    amount::Amount, comment::Comment, expense::Expense, group::Group, revision::Revision,
+    user::User,
+};
 
+#[derive(Serialize)]
 pub struct Split {
     pub id: String,
     pub expense_id: Option<String>,
@@ -30,6 +35,8 @@ pub struct Split {
     pub transaction_at: String,
 
     pub transaction_metadata: Option<String>,
+
+    pub deleted_at: Option<String>,
 }
 
 #[Object]
@@ -133,9 +140,10 @@ impl Split {
         if let Some(part) = &self.part_transaction {
             let splits = sqlx::query_as!(
                 Split,
-                "SELECT * FROM split_transactions WHERE part_transaction=$1 AND id!=$2",
+                "SELECT * FROM split_transactions WHERE part_transaction=$1 AND id!=$2 AND deleted_at IS $3",
                 part,
-                self.id
+                self.id,
+                self.deleted_at
             )
             .fetch_all(pool)
             .await?;
@@ -156,6 +164,20 @@ impl Split {
     pub async fn transaction_metadata(&self) -> &Option<String> {
         &self.transaction_metadata
     }
+
+    pub async fn deleted_at(&self) -> &Option<String> {
+        &self.deleted_at
+    }
+
+    pub async fn comments<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Vec<Comment>> {
+        let pool = get_pool_from_context(context).await?;
+        Comment::get_for_thread(None, Some(&self.id), pool).await
+    }
+
+    pub async fn history<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Vec<Revision>> {
+        let pool = get_pool_from_context(context).await?;
+        Revision::get_for_split(&self.id, pool).await
+    }
 }
 
 impl Split {
@@ -169,6 +191,75 @@ impl Split {
             .await?;
         Ok(split)
     }
+
+    /// Tombstones a payment together with the rest of the payment it was
+    /// part of, since auto settlement spreads one payment across groups.
+    pub async fn delete_settlement(
+        split_id: &str,
+        actor_id: &str,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<Split>> {
+        let split = Self::get_from_id(split_id, pool).await?;
+        if split.get_transaction_type() != TransactionType::CashPaid {
+            return Err(anyhow::anyhow!("Only payments can be deleted"));
+        }
+        if split.deleted_at.is_some() {
+            return Err(anyhow::anyhow!("Payment already deleted"));
+        }
+        let time = chrono::Utc::now().to_rfc3339();
+        let mut transaction = pool.begin().await?;
+        let old_splits = sqlx::query_as!(
+            Split,
+            "
+            SELECT * FROM split_transactions
+            WHERE (id = $1 OR part_transaction = $2) AND transaction_type = $3 AND deleted_at IS NULL
+            ",
+            split.id,
+            split.part_transaction,
+            split.transaction_type
+        )
+        .fetch_all(transaction.as_mut())
+        .await?;
+        let splits = sqlx::query_as!(
+            Split,
+            "
+            UPDATE split_transactions SET deleted_at = $3, updated_at = $3
+            WHERE (id = $1 OR part_transaction = $2) AND transaction_type = $4 AND deleted_at IS NULL
+            RETURNING *
+            ",
+            split.id,
+            split.part_transaction,
+            time,
+            split.transaction_type
+        )
+        .fetch_all(transaction.as_mut())
+        .await?;
+        Revision::record_splits(actor_id, &old_splits, &splits, &mut transaction).await?;
+        transaction.commit().await?;
+        Ok(splits)
+    }
+}
+
+/// One payment of a plan that settles every balance in a group.
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct SuggestedSettlement {
+    pub from_user_id: String,
+    pub to_user_id: String,
+    pub amount: Amount,
+}
+
+#[ComplexObject]
+impl SuggestedSettlement {
+    pub async fn from_user<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<User> {
+        let pool = get_pool_from_context(context).await?;
+        User::get_from_id(&self.from_user_id, pool).await
+    }
+
+    pub async fn to_user<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<User> {
+        let pool = get_pool_from_context(context).await?;
+        User::get_from_id(&self.to_user_id, pool).await
+    }
 }
 
 #[derive(EnumString, Enum, Clone, Copy, PartialEq, Eq, Display)]
@@ -178,4 +269,5 @@ pub enum TransactionType {
     CrossGroupSettlement,
     CurrencyConversion,
     CashPaid,
+    GroupSimplification,
 }
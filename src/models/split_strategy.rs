@@ -0,0 +1,392 @@
+use async_graphql::{Enum, InputObject, SimpleObject};
+use serde::{Deserialize, Serialize};
+
+use crate::schema::{mutation::SplitInput, IdValidator};
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum SplitStrategyKind {
+    /// Everyone pays the same, `value` is ignored.
+    Equal,
+    /// `value` is in basis points, all parts must add up to 10000.
+    Percentage,
+    /// `value` is a positive weight, e.g. 2 shares pays double of 1 share.
+    Shares,
+    /// `value` is the exact amount, all parts must add up to the expense amount.
+    Exact,
+    /// `value` is added to (or taken from) an otherwise equal share.
+    Adjustment,
+}
+
+#[derive(SimpleObject, InputObject, Clone, Debug, Serialize, Deserialize)]
+#[graphql(input_name = "SplitStrategyPartInput")]
+pub struct SplitStrategyPart {
+    #[graphql(validator(custom = r#"IdValidator::new("user_id")"#))]
+    pub user_id: String,
+    #[graphql(default)]
+    pub value: i64,
+}
+
+/// How an expense amount is divided between its participants, including the
+/// payer. Kept on the expense so it can be shown again while editing.
+#[derive(SimpleObject, InputObject, Clone, Debug, Serialize, Deserialize)]
+#[graphql(input_name = "SplitStrategyInput")]
+pub struct SplitStrategy {
+    pub kind: SplitStrategyKind,
+    pub parts: Vec<SplitStrategyPart>,
+}
+
+impl SplitStrategy {
+    /// Divides `amount` between all parts. Amounts are in the minor unit of the
+    /// currency (`10^-decimals`), so whatever does not divide evenly is handed
+    /// out one minor unit at a time, largest remainder first and then by user id.
+    pub fn resolve(&self, amount: i64) -> anyhow::Result<Vec<SplitInput>> {
+        let mut parts = self.parts.clone();
+        parts.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+        if parts.is_empty() {
+            return Err(anyhow::anyhow!("Split must have at least one user"));
+        }
+        if parts
+            .windows(2)
+            .any(|pair| pair[0].user_id == pair[1].user_id)
+        {
+            return Err(anyhow::anyhow!("Split has duplicate users"));
+        }
+        let values = parts.iter().map(|part| part.value).collect::<Vec<_>>();
+        let amounts = match self.kind {
+            SplitStrategyKind::Equal => allocate(amount, &vec![1; parts.len()]),
+            SplitStrategyKind::Percentage => {
+                if values.iter().any(|v| *v < 0) || checked_sum(values.iter().copied())? != 10000 {
+                    return Err(anyhow::anyhow!(
+                        "Percentages must add up to 10000 basis points"
+                    ));
+                }
+                allocate(amount, &values)
+            }
+            SplitStrategyKind::Shares => {
+                if values.iter().any(|v| *v < 0) || checked_sum(values.iter().copied())? <= 0 {
+                    return Err(anyhow::anyhow!("Shares must be positive"));
+                }
+                allocate(amount, &values)
+            }
+            SplitStrategyKind::Exact => {
+                if values.iter().any(|v| *v < 0) || checked_sum(values.iter().copied())? != amount {
+                    return Err(anyhow::anyhow!("Split amounts must add up to total"));
+                }
+                values
+            }
+            SplitStrategyKind::Adjustment => {
+                let remaining = amount
+                    .checked_sub(checked_sum(values.iter().copied())?)
+                    .ok_or_else(|| anyhow::anyhow!("Amounts are too large"))?;
+                if remaining < 0 {
+                    return Err(anyhow::anyhow!("Adjustments can not exceed total"));
+                }
+                let amounts = allocate(remaining, &vec![1; parts.len()])
+                    .into_iter()
+                    .zip(values)
+                    .map(|(share, adjustment)| {
+                        share
+                            .checked_add(adjustment)
+                            .ok_or_else(|| anyhow::anyhow!("Amounts are too large"))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                if amounts.iter().any(|a| *a < 0) {
+                    return Err(anyhow::anyhow!("Adjustment makes share negative"));
+                }
+                amounts
+            }
+        };
+        Ok(parts
+            .iter()
+            .zip(amounts)
+            .map(|(part, amount)| SplitInput {
+                user_id: part.user_id.clone(),
+                amount,
+            })
+            .collect())
+    }
+
+    /// Like [`SplitStrategy::resolve`], but leaves out the payer's own share
+    /// since they do not owe it to anyone.
+    pub fn resolve_owed_to(&self, amount: i64, payer_id: &str) -> anyhow::Result<Vec<SplitInput>> {
+        Ok(self
+            .resolve(amount)?
+            .into_iter()
+            .filter(|split| split.user_id != payer_id)
+            .collect())
+    }
+
+    /// An exact strategy for splits owed to `payer_id`, who keeps whatever
+    /// is left of `amount` as their own share.
+    pub fn from_splits(amount: i64, payer_id: &str, splits: &[SplitInput]) -> anyhow::Result<Self> {
+        let payer_share = amount
+            .checked_sub(checked_sum(splits.iter().map(|split| split.amount))?)
+            .ok_or_else(|| anyhow::anyhow!("Amounts are too large"))?;
+        if payer_share < 0 {
+            return Err(anyhow::anyhow!("Splits can not exceed total"));
+        }
+        let parts = splits
+            .iter()
+            .map(|split| SplitStrategyPart {
+                user_id: split.user_id.clone(),
+                value: split.amount,
+            })
+            .chain(std::iter::once(SplitStrategyPart {
+                user_id: payer_id.to_string(),
+                value: payer_share,
+            }))
+            .collect();
+        Ok(SplitStrategy {
+            kind: SplitStrategyKind::Exact,
+            parts,
+        })
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn from_json(value: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(value)?)
+    }
+}
+
+/// Adds up amounts sent by a client, failing instead of wrapping around.
+pub fn checked_sum(values: impl IntoIterator<Item = i64>) -> anyhow::Result<i64> {
+    values
+        .into_iter()
+        .try_fold(0i64, |sum, value| sum.checked_add(value))
+        .ok_or_else(|| anyhow::anyhow!("Amounts are too large"))
+}
+
+/// Divides `amount` proportionally to `weights` using the largest remainder
+/// method, so the result always adds up to `amount`.
+pub fn allocate(amount: i64, weights: &[i64]) -> Vec<i64> {
+    let total = weights.iter().map(|w| *w as i128).sum::<i128>();
+    if total == 0 {
+        return vec![0; weights.len()];
+    }
+    let mut amounts = Vec::with_capacity(weights.len());
+    let mut remainders = Vec::with_capacity(weights.len());
+    for (i, weight) in weights.iter().enumerate() {
+        let exact = amount as i128 * *weight as i128;
+        amounts.push((exact / total) as i64);
+        remainders.push((exact % total, i));
+    }
+    let leftover = amount - amounts.iter().sum::<i64>();
+    remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    for (_, i) in remainders.into_iter().take(leftover as usize) {
+        amounts[i] += 1;
+    }
+    amounts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strategy(kind: SplitStrategyKind, parts: &[(&str, i64)]) -> SplitStrategy {
+        SplitStrategy {
+            kind,
+            parts: parts
+                .iter()
+                .map(|(user_id, value)| SplitStrategyPart {
+                    user_id: user_id.to_string(),
+                    value: *value,
+                })
+                .collect(),
+        }
+    }
+
+    fn amounts(splits: Vec<SplitInput>) -> Vec<(String, i64)> {
+        splits
+            .into_iter()
+            .map(|split| (split.user_id, split.amount))
+            .collect()
+    }
+
+    fn resolve(kind: SplitStrategyKind, parts: &[(&str, i64)], amount: i64) -> Vec<(String, i64)> {
+        amounts(strategy(kind, parts).resolve(amount).unwrap())
+    }
+
+    fn expected(parts: &[(&str, i64)]) -> Vec<(String, i64)> {
+        parts
+            .iter()
+            .map(|(user_id, amount)| (user_id.to_string(), *amount))
+            .collect()
+    }
+
+    #[test]
+    fn allocate_adds_up_to_amount() {
+        assert_eq!(allocate(100, &[1, 1, 1]), vec![34, 33, 33]);
+        assert_eq!(allocate(10, &[1, 2]), vec![3, 7]);
+        assert_eq!(allocate(0, &[1, 1]), vec![0, 0]);
+        assert_eq!(allocate(5, &[0, 0]), vec![0, 0]);
+        assert_eq!(
+            allocate(i64::MAX, &[1, 1])
+                .iter()
+                .map(|a| *a as i128)
+                .sum::<i128>(),
+            i64::MAX as i128
+        );
+    }
+
+    #[test]
+    fn allocate_gives_leftover_to_largest_remainder() {
+        // 2/3 and 1/3 of 100 leave remainders of 2 and 1 (out of 3).
+        assert_eq!(allocate(100, &[2, 1]), vec![67, 33]);
+        assert_eq!(allocate(2, &[1, 1, 1]), vec![1, 1, 0]);
+    }
+
+    #[test]
+    fn equal_ignores_values_and_orders_by_user() {
+        assert_eq!(
+            resolve(
+                SplitStrategyKind::Equal,
+                &[("c", 5), ("a", 0), ("b", 0)],
+                100
+            ),
+            expected(&[("a", 34), ("b", 33), ("c", 33)])
+        );
+    }
+
+    #[test]
+    fn percentage_uses_basis_points() {
+        assert_eq!(
+            resolve(
+                SplitStrategyKind::Percentage,
+                &[("a", 2500), ("b", 7500)],
+                1001
+            ),
+            expected(&[("a", 250), ("b", 751)])
+        );
+        let err = strategy(SplitStrategyKind::Percentage, &[("a", 25), ("b", 75)])
+            .resolve(100)
+            .map(amounts)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Percentages must add up to 10000 basis points"
+        );
+        assert!(
+            strategy(SplitStrategyKind::Percentage, &[("a", -1), ("b", 10001)])
+                .resolve(100)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn shares_split_by_weight() {
+        assert_eq!(
+            resolve(
+                SplitStrategyKind::Shares,
+                &[("a", 2), ("b", 1), ("c", 1)],
+                100
+            ),
+            expected(&[("a", 50), ("b", 25), ("c", 25)])
+        );
+        assert!(strategy(SplitStrategyKind::Shares, &[("a", 0), ("b", 0)])
+            .resolve(100)
+            .is_err());
+        assert!(strategy(SplitStrategyKind::Shares, &[("a", 2), ("b", -1)])
+            .resolve(100)
+            .is_err());
+    }
+
+    #[test]
+    fn exact_must_match_total() {
+        assert_eq!(
+            resolve(SplitStrategyKind::Exact, &[("a", 30), ("b", 70)], 100),
+            expected(&[("a", 30), ("b", 70)])
+        );
+        assert!(strategy(SplitStrategyKind::Exact, &[("a", 30), ("b", 60)])
+            .resolve(100)
+            .is_err());
+        assert!(
+            strategy(SplitStrategyKind::Exact, &[("a", -10), ("b", 110)])
+                .resolve(100)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn adjustment_changes_equal_share() {
+        assert_eq!(
+            resolve(SplitStrategyKind::Adjustment, &[("a", 10), ("b", 0)], 100),
+            expected(&[("a", 55), ("b", 45)])
+        );
+        assert!(
+            strategy(SplitStrategyKind::Adjustment, &[("a", 110), ("b", 0)])
+                .resolve(100)
+                .is_err()
+        );
+        assert!(
+            strategy(SplitStrategyKind::Adjustment, &[("a", 20), ("b", -20)])
+                .resolve(10)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_overflowing_values() {
+        let huge = [("a", i64::MAX), ("b", i64::MAX), ("c", 102)];
+        for kind in [
+            SplitStrategyKind::Percentage,
+            SplitStrategyKind::Shares,
+            SplitStrategyKind::Exact,
+            SplitStrategyKind::Adjustment,
+        ] {
+            assert!(strategy(kind, &huge).resolve(100).is_err());
+        }
+        assert!(strategy(
+            SplitStrategyKind::Adjustment,
+            &[("a", i64::MAX), ("b", -i64::MAX + 10)]
+        )
+        .resolve(100)
+        .is_err());
+        let splits = [i64::MAX, i64::MAX, 102]
+            .into_iter()
+            .enumerate()
+            .map(|(i, amount)| SplitInput {
+                user_id: i.to_string(),
+                amount,
+            })
+            .collect::<Vec<_>>();
+        assert!(SplitStrategy::from_splits(100, "a", &splits).is_err());
+        assert!(checked_sum([i64::MAX, 1]).is_err());
+        assert_eq!(checked_sum([i64::MAX, -1, 1]).unwrap(), i64::MAX);
+    }
+
+    #[test]
+    fn rejects_empty_and_duplicate_parts() {
+        assert!(strategy(SplitStrategyKind::Equal, &[])
+            .resolve(100)
+            .is_err());
+        assert!(strategy(SplitStrategyKind::Equal, &[("a", 0), ("a", 0)])
+            .resolve(100)
+            .is_err());
+    }
+
+    #[test]
+    fn resolve_owed_to_leaves_out_payer() {
+        let splits = strategy(SplitStrategyKind::Equal, &[("a", 0), ("b", 0)])
+            .resolve_owed_to(100, "a")
+            .unwrap();
+        assert_eq!(amounts(splits), expected(&[("b", 50)]));
+    }
+
+    #[test]
+    fn from_splits_gives_payer_the_rest() {
+        let splits = vec![SplitInput {
+            user_id: "b".to_string(),
+            amount: 30,
+        }];
+        let strategy = SplitStrategy::from_splits(100, "a", &splits).unwrap();
+        assert_eq!(strategy.kind, SplitStrategyKind::Exact);
+        assert_eq!(
+            amounts(strategy.resolve(100).unwrap()),
+            expected(&[("a", 70), ("b", 30)])
+        );
+        assert!(SplitStrategy::from_splits(10, "a", &splits).is_err());
+    }
+}
@@ -1,5 +1,8 @@
-use async_graphql::{Context, Object, SimpleObject};
+use std::str::FromStr;
+
+use async_graphql::{ComplexObject, Context, Enum, Object, SimpleObject};
 use sqlx::SqlitePool;
+use strum::{Display, EnumString};
 
 use crate::{auth::AuthTypes, schema::get_pool_from_context};
 
@@ -109,15 +112,21 @@ impl User {
         Ok(user)
     }
 
-    pub async fn get_groups(&self, pool: &SqlitePool) -> anyhow::Result<Vec<Group>> {
+    pub async fn get_groups(
+        &self,
+        include_archived: bool,
+        pool: &SqlitePool,
+    ) -> anyhow::Result<Vec<Group>> {
         let groups = sqlx::query_as!(
             Group,
             r#"
             SELECT groups.* FROM 
                 users JOIN group_memberships ON users.id=group_memberships.user_id AND users.id=$1
                 JOIN groups ON group_memberships.group_id=groups.id
+            WHERE group_memberships.removed_at IS NULL AND ($2 OR groups.archived_at IS NULL)
             "#,
-            self.id
+            self.id,
+            include_archived
         )
         .fetch_all(pool)
         .await?;
@@ -142,8 +151,9 @@ impl User {
                 FROM 
                     split_transactions
                 WHERE 
-                    (from_user = $1 AND to_user = $2) OR 
-                    (from_user = $2 AND to_user = $1)
+                    ((from_user = $1 AND to_user = $2) OR 
+                    (from_user = $2 AND to_user = $1))
+                    AND deleted_at IS NULL
                 GROUP BY 
                     from_user, to_user, group_id, currency_id
             ) GROUP BY group_id, currency_id
@@ -355,9 +365,45 @@ pub struct OwedInGroup {
 }
 
 #[derive(SimpleObject)]
+#[graphql(complex)]
 pub struct UserConfig {
     pub user_id: String,
     pub default_currency_id: String,
+    #[graphql(skip)]
+    pub invitation_policy: String,
+}
+
+/// Whether being added to a group needs the user's consent.
+#[derive(EnumString, Enum, Clone, Copy, PartialEq, Eq, Display)]
+pub enum InvitationPolicy {
+    /// Join straight away when invited by someone already sharing a group.
+    AutoAcceptContacts,
+    AlwaysAsk,
+}
+
+#[ComplexObject]
+impl UserConfig {
+    pub async fn invitation_policy(&self) -> InvitationPolicy {
+        self.get_invitation_policy()
+    }
+}
+
+impl UserConfig {
+    pub fn get_invitation_policy(&self) -> InvitationPolicy {
+        InvitationPolicy::from_str(&self.invitation_policy)
+            .unwrap_or(InvitationPolicy::AutoAcceptContacts)
+    }
+
+    pub async fn get_for_user(user_id: &str, pool: &SqlitePool) -> anyhow::Result<UserConfig> {
+        let config = sqlx::query_as!(
+            UserConfig,
+            "SELECT * FROM user_config WHERE user_id = $1",
+            user_id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(config)
+    }
 }
 
 #[derive(SimpleObject)]
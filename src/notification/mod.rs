@@ -5,9 +5,9 @@ use std::{
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use crate::{FirebaseValues, FIREBASE_VALUES, REQWEST_CLIENT};
+use crate::{models::user::User, FIREBASE_VALUES, REQWEST_CLIENT};
 
-static GRANT_TYPE: &'static str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+static GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
 
 static BEARER_HOLDER: Lazy<Arc<RwLock<Option<String>>>> = Lazy::new(|| Arc::new(RwLock::new(None)));
 
@@ -27,7 +27,7 @@ pub async fn get_bearer_token() -> Result<String, anyhow::Error> {
         .expect("Time went backwards");
     let now_secs = since_the_epoch.as_secs();
     let header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
-    let key = jsonwebtoken::EncodingKey::from_rsa_pem(&FIREBASE_VALUES.private_key.as_bytes())?;
+    let key = jsonwebtoken::EncodingKey::from_rsa_pem(FIREBASE_VALUES.private_key.as_bytes())?;
     let claims = Claims {
             iss: FIREBASE_VALUES.client_email.to_string(),
             scope: "https://www.googleapis.com/auth/cloud-platform https://www.googleapis.com/auth/firebase.database https://www.googleapis.com/auth/firebase.messaging https://www.googleapis.com/auth/identitytoolkit https://www.googleapis.com/auth/userinfo.email".into(),
@@ -105,6 +105,35 @@ pub async fn send_message_notification_with_retry(
     }
 }
 
+/// Notifies `user` if they have registered a notification token.
+/// Failures are only logged, so callers can fire and forget.
+pub async fn notify_user(
+    user: &User,
+    title: &str,
+    description: &str,
+    android_channel_id: Option<&str>,
+) {
+    if let Some(token) = &user.notification_token {
+        if let Err(err) = send_message_notification_with_retry(
+            title,
+            "/",
+            "https://billdivide.app/",
+            description,
+            token,
+            android_channel_id,
+        )
+        .await
+        {
+            log::warn!("Failed to send notification {err:?}")
+        } else {
+            log::info!("Notification sent")
+        }
+    } else {
+        log::info!("Skipping notification, no token")
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn send_message_notification(
     title: &str,
     path_url: &str,
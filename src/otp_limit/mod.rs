@@ -0,0 +1,631 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use async_graphql::{Error, ErrorExtensions};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+use tokio::sync::Mutex;
+
+/// Wrong guesses for one email or phone number before it is locked.
+const MAX_FAILURES: u32 = 5;
+/// Wrong guesses from one IP, across all emails and phone numbers.
+const MAX_IP_FAILURES: u32 = 20;
+const FAILURE_WINDOW: Duration = Duration::from_secs(15 * 60);
+/// Longer than a passcode lives, so a locked code expires before the lock.
+const LOCKOUT: Duration = Duration::from_secs(15 * 60);
+const RESEND_COOLDOWN: Duration = Duration::from_secs(60);
+const MAX_DAILY_SENDS: u32 = 10;
+const MAX_IP_DAILY_SENDS: u32 = 50;
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Why a passcode could not be sent or checked. Each case has its own
+/// `code` extension so the app can tell them apart.
+#[derive(Debug)]
+pub enum OtpError {
+    Invalid {
+        attempts_left: u32,
+    },
+    Locked {
+        retry_after: Duration,
+    },
+    IpLocked {
+        retry_after: Duration,
+    },
+    ResendCooldown {
+        retry_after: Duration,
+    },
+    DailyLimit,
+    IpDailyLimit,
+    /// The limits could not be checked, e.g. the database is down.
+    Unavailable,
+}
+
+impl OtpError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            OtpError::Invalid { .. } => "OTP_INVALID",
+            OtpError::Locked { .. } => "OTP_LOCKED",
+            OtpError::IpLocked { .. } => "OTP_IP_LOCKED",
+            OtpError::ResendCooldown { .. } => "OTP_RESEND_COOLDOWN",
+            OtpError::DailyLimit => "OTP_DAILY_LIMIT",
+            OtpError::IpDailyLimit => "OTP_IP_DAILY_LIMIT",
+            OtpError::Unavailable => "OTP_UNAVAILABLE",
+        }
+    }
+}
+
+impl Display for OtpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OtpError::Invalid { .. } => write!(f, "OTP Mismatch or expired"),
+            OtpError::Locked { .. } => write!(f, "Too many wrong attempts, try again later"),
+            OtpError::IpLocked { .. } => write!(f, "Too many wrong attempts from this network"),
+            OtpError::ResendCooldown { .. } => write!(f, "Wait before requesting another OTP"),
+            OtpError::DailyLimit => write!(f, "Too many OTPs requested today"),
+            OtpError::IpDailyLimit => write!(f, "Too many OTPs requested from this network"),
+            OtpError::Unavailable => write!(f, "Something went wrong"),
+        }
+    }
+}
+
+impl ErrorExtensions for OtpError {
+    fn extend(&self) -> Error {
+        Error::new(self.to_string()).extend_with(|_, e| {
+            e.set("code", self.code());
+            match self {
+                OtpError::Invalid { attempts_left } => e.set("attemptsLeft", *attempts_left),
+                OtpError::Locked { retry_after }
+                | OtpError::IpLocked { retry_after }
+                | OtpError::ResendCooldown { retry_after } => {
+                    e.set("retryAfter", retry_after.as_secs().max(1))
+                }
+                OtpError::DailyLimit | OtpError::IpDailyLimit | OtpError::Unavailable => {}
+            }
+        })
+    }
+}
+
+/// Counts hits in a fixed window that starts with the first hit.
+struct Window {
+    count: u32,
+    started: Instant,
+}
+
+#[derive(Default)]
+struct Counters(HashMap<String, Window>);
+
+impl Counters {
+    fn get(&self, key: &str, length: Duration) -> u32 {
+        match self.0.get(key) {
+            Some(window) if window.started.elapsed() < length => window.count,
+            _ => 0,
+        }
+    }
+
+    fn hit(&mut self, key: &str, length: Duration) -> u32 {
+        let window = self.0.entry(key.to_string()).or_insert(Window {
+            count: 0,
+            started: Instant::now(),
+        });
+        if window.started.elapsed() >= length {
+            window.count = 0;
+            window.started = Instant::now();
+        }
+        window.count += 1;
+        window.count
+    }
+
+    fn cleanup(&mut self, length: Duration) {
+        self.0.retain(|_, window| window.started.elapsed() < length);
+    }
+}
+
+#[derive(Default)]
+struct State {
+    failures: Counters,
+    ip_failures: Counters,
+    sends: Counters,
+    ip_sends: Counters,
+    locked_until: HashMap<String, Instant>,
+    ip_locked_until: HashMap<String, Instant>,
+    last_sent: HashMap<String, Instant>,
+    last_cleanup: Option<Instant>,
+}
+
+impl State {
+    fn cleanup(&mut self) {
+        if self
+            .last_cleanup
+            .is_some_and(|last| last.elapsed() < CLEANUP_INTERVAL)
+        {
+            return;
+        }
+        let now = Instant::now();
+        self.failures.cleanup(FAILURE_WINDOW);
+        self.ip_failures.cleanup(FAILURE_WINDOW);
+        self.sends.cleanup(DAY);
+        self.ip_sends.cleanup(DAY);
+        self.locked_until.retain(|_, until| *until > now);
+        self.ip_locked_until.retain(|_, until| *until > now);
+        self.last_sent
+            .retain(|_, sent| sent.elapsed() < RESEND_COOLDOWN);
+        self.last_cleanup = Some(now);
+    }
+
+    fn check_locks(&self, key: &str, ip: Option<&str>) -> Result<(), OtpError> {
+        let now = Instant::now();
+        if let Some(until) = self.locked_until.get(key).filter(|until| **until > now) {
+            return Err(OtpError::Locked {
+                retry_after: *until - now,
+            });
+        }
+        if let Some(until) = ip
+            .and_then(|ip| self.ip_locked_until.get(ip))
+            .filter(|until| **until > now)
+        {
+            return Err(OtpError::IpLocked {
+                retry_after: *until - now,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Guards passcode logins against guessing and spam. `key` is the email or
+/// phone number a code is for, `ip` the client address if known.
+#[async_trait]
+pub trait OtpLimiter: Send + Sync {
+    /// Checks whether a code may be sent now and counts the send.
+    async fn check_send(&self, key: &str, ip: Option<&str>) -> Result<(), OtpError>;
+
+    /// Fails while `key` or `ip` is locked out.
+    async fn check_verify(&self, key: &str, ip: Option<&str>) -> Result<(), OtpError>;
+
+    /// Counts a wrong guess, locking `key` or `ip` once they have too many.
+    /// Returns the error to show for the guess.
+    async fn record_failure(&self, key: &str, ip: Option<&str>) -> OtpError;
+
+    /// Forgets the wrong guesses for `key` after a successful login.
+    async fn record_success(&self, key: &str);
+}
+
+pub type OtpLimits = Box<dyn OtpLimiter>;
+
+/// Picks the limiter matching `OTP_STORE`, so limits are shared by every
+/// instance whenever the codes are.
+pub fn limiter_from_env(pool: &SqlitePool) -> anyhow::Result<OtpLimits> {
+    match std::env::var("OTP_STORE").as_deref() {
+        Ok("sqlite") => Ok(Box::new(SqliteOtpLimiter::new(pool.clone()))),
+        Ok("memory") | Err(_) => Ok(Box::new(MemoryOtpLimiter::default())),
+        Ok(store) => Err(anyhow::anyhow!("Unknown OTP_STORE {store}")),
+    }
+}
+
+/// Limits of this process only, lost on restart.
+#[derive(Default)]
+pub struct MemoryOtpLimiter {
+    state: Mutex<State>,
+}
+
+#[async_trait]
+impl OtpLimiter for MemoryOtpLimiter {
+    async fn check_send(&self, key: &str, ip: Option<&str>) -> Result<(), OtpError> {
+        let mut state = self.state.lock().await;
+        state.cleanup();
+        state.check_locks(key, ip)?;
+        if let Some(sent) = state.last_sent.get(key) {
+            if sent.elapsed() < RESEND_COOLDOWN {
+                return Err(OtpError::ResendCooldown {
+                    retry_after: RESEND_COOLDOWN - sent.elapsed(),
+                });
+            }
+        }
+        if state.sends.get(key, DAY) >= MAX_DAILY_SENDS {
+            return Err(OtpError::DailyLimit);
+        }
+        if let Some(ip) = ip {
+            if state.ip_sends.get(ip, DAY) >= MAX_IP_DAILY_SENDS {
+                return Err(OtpError::IpDailyLimit);
+            }
+            state.ip_sends.hit(ip, DAY);
+        }
+        state.sends.hit(key, DAY);
+        state.last_sent.insert(key.to_string(), Instant::now());
+        Ok(())
+    }
+
+    async fn check_verify(&self, key: &str, ip: Option<&str>) -> Result<(), OtpError> {
+        let mut state = self.state.lock().await;
+        state.cleanup();
+        state.check_locks(key, ip)
+    }
+
+    async fn record_failure(&self, key: &str, ip: Option<&str>) -> OtpError {
+        let mut state = self.state.lock().await;
+        if let Some(ip) = ip {
+            if state.ip_failures.hit(ip, FAILURE_WINDOW) >= MAX_IP_FAILURES {
+                state
+                    .ip_locked_until
+                    .insert(ip.to_string(), Instant::now() + LOCKOUT);
+            }
+        }
+        let failures = state.failures.hit(key, FAILURE_WINDOW);
+        if failures >= MAX_FAILURES {
+            state
+                .locked_until
+                .insert(key.to_string(), Instant::now() + LOCKOUT);
+            return OtpError::Locked {
+                retry_after: LOCKOUT,
+            };
+        }
+        OtpError::Invalid {
+            attempts_left: MAX_FAILURES - failures,
+        }
+    }
+
+    async fn record_success(&self, key: &str) {
+        let mut state = self.state.lock().await;
+        state.failures.0.remove(key);
+    }
+}
+
+const FAILURES: &str = "failures";
+const IP_FAILURES: &str = "ip_failures";
+const SENDS: &str = "sends";
+const IP_SENDS: &str = "ip_sends";
+
+/// Limits in the database next to the codes, shared by every instance using
+/// it. Database errors refuse the request rather than skip the limits.
+pub struct SqliteOtpLimiter {
+    pool: SqlitePool,
+}
+
+impl SqliteOtpLimiter {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    async fn count(&self, kind: &str, key: &str, length: Duration) -> anyhow::Result<u32> {
+        let since = ago(length)?;
+        let row = sqlx::query!(
+            "SELECT count FROM otp_limits WHERE kind = $1 AND key = $2 AND window_started_at > $3",
+            kind,
+            key,
+            since
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|row| row.count as u32).unwrap_or(0))
+    }
+
+    /// Counts a hit in one statement, so instances do not lose each other's.
+    async fn hit(&self, kind: &str, key: &str, length: Duration) -> anyhow::Result<u32> {
+        let now = Utc::now().to_rfc3339();
+        let since = ago(length)?;
+        let row = sqlx::query!(
+            r#"INSERT INTO otp_limits(kind, key, count, window_started_at, last_at) VALUES ($1, $2, 1, $3, $3)
+            ON CONFLICT(kind, key) DO UPDATE SET
+                count = CASE WHEN window_started_at > $4 THEN count + 1 ELSE 1 END,
+                window_started_at = CASE WHEN window_started_at > $4 THEN window_started_at ELSE $3 END,
+                last_at = $3
+            RETURNING count
+            "#,
+            kind,
+            key,
+            now,
+            since
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row.count as u32)
+    }
+
+    async fn lock(&self, kind: &str, key: &str) -> anyhow::Result<()> {
+        let until = (Utc::now() + chrono::Duration::from_std(LOCKOUT)?).to_rfc3339();
+        sqlx::query!(
+            "UPDATE otp_limits SET locked_until = $3 WHERE kind = $1 AND key = $2",
+            kind,
+            key,
+            until
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// How long `key` stays locked, if it is.
+    async fn locked_for(&self, kind: &str, key: &str) -> anyhow::Result<Option<Duration>> {
+        let now = Utc::now().to_rfc3339();
+        let row = sqlx::query!(
+            "SELECT locked_until FROM otp_limits WHERE kind = $1 AND key = $2 AND locked_until > $3",
+            kind,
+            key,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        row.and_then(|row| row.locked_until)
+            .map(|locked_until| Ok(until(parse_time(&locked_until)?)))
+            .transpose()
+    }
+
+    async fn check_locks(
+        &self,
+        key: &str,
+        ip: Option<&str>,
+    ) -> anyhow::Result<Result<(), OtpError>> {
+        if let Some(retry_after) = self.locked_for(FAILURES, key).await? {
+            return Ok(Err(OtpError::Locked { retry_after }));
+        }
+        if let Some(ip) = ip {
+            if let Some(retry_after) = self.locked_for(IP_FAILURES, ip).await? {
+                return Ok(Err(OtpError::IpLocked { retry_after }));
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    async fn cleanup(&self) -> anyhow::Result<()> {
+        let now = Utc::now().to_rfc3339();
+        let since = ago(DAY)?;
+        sqlx::query!(
+            "DELETE FROM otp_limits WHERE window_started_at <= $1 AND (locked_until IS NULL OR locked_until <= $2)",
+            since,
+            now
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn try_check_send(
+        &self,
+        key: &str,
+        ip: Option<&str>,
+    ) -> anyhow::Result<Result<(), OtpError>> {
+        self.cleanup().await?;
+        if let Err(err) = self.check_locks(key, ip).await? {
+            return Ok(Err(err));
+        }
+        let since = ago(RESEND_COOLDOWN)?;
+        let last_sent = sqlx::query!(
+            "SELECT last_at FROM otp_limits WHERE kind = $1 AND key = $2 AND last_at > $3",
+            SENDS,
+            key,
+            since
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some(last_sent) = last_sent {
+            let resend_at =
+                parse_time(&last_sent.last_at)? + chrono::Duration::from_std(RESEND_COOLDOWN)?;
+            return Ok(Err(OtpError::ResendCooldown {
+                retry_after: until(resend_at),
+            }));
+        }
+        if self.count(SENDS, key, DAY).await? >= MAX_DAILY_SENDS {
+            return Ok(Err(OtpError::DailyLimit));
+        }
+        if let Some(ip) = ip {
+            if self.count(IP_SENDS, ip, DAY).await? >= MAX_IP_DAILY_SENDS {
+                return Ok(Err(OtpError::IpDailyLimit));
+            }
+            self.hit(IP_SENDS, ip, DAY).await?;
+        }
+        self.hit(SENDS, key, DAY).await?;
+        Ok(Ok(()))
+    }
+
+    async fn try_record_failure(&self, key: &str, ip: Option<&str>) -> anyhow::Result<OtpError> {
+        if let Some(ip) = ip {
+            if self.hit(IP_FAILURES, ip, FAILURE_WINDOW).await? >= MAX_IP_FAILURES {
+                self.lock(IP_FAILURES, ip).await?;
+            }
+        }
+        let failures = self.hit(FAILURES, key, FAILURE_WINDOW).await?;
+        if failures >= MAX_FAILURES {
+            self.lock(FAILURES, key).await?;
+            return Ok(OtpError::Locked {
+                retry_after: LOCKOUT,
+            });
+        }
+        Ok(OtpError::Invalid {
+            attempts_left: MAX_FAILURES - failures,
+        })
+    }
+}
+
+fn ago(length: Duration) -> anyhow::Result<String> {
+    Ok((Utc::now() - chrono::Duration::from_std(length)?).to_rfc3339())
+}
+
+fn parse_time(time: &str) -> anyhow::Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(time)?.with_timezone(&Utc))
+}
+
+/// Time from now until `time`, zero if it has passed.
+fn until(time: DateTime<Utc>) -> Duration {
+    (time - Utc::now()).to_std().unwrap_or_default()
+}
+
+fn unavailable(err: anyhow::Error) -> OtpError {
+    log::warn!("Failed to check otp limits {err:?}");
+    OtpError::Unavailable
+}
+
+#[async_trait]
+impl OtpLimiter for SqliteOtpLimiter {
+    async fn check_send(&self, key: &str, ip: Option<&str>) -> Result<(), OtpError> {
+        self.try_check_send(key, ip)
+            .await
+            .unwrap_or_else(|err| Err(unavailable(err)))
+    }
+
+    async fn check_verify(&self, key: &str, ip: Option<&str>) -> Result<(), OtpError> {
+        self.check_locks(key, ip)
+            .await
+            .unwrap_or_else(|err| Err(unavailable(err)))
+    }
+
+    async fn record_failure(&self, key: &str, ip: Option<&str>) -> OtpError {
+        self.try_record_failure(key, ip)
+            .await
+            .unwrap_or_else(unavailable)
+    }
+
+    async fn record_success(&self, key: &str) {
+        if let Err(err) = sqlx::query!(
+            "DELETE FROM otp_limits WHERE kind = $1 AND key = $2",
+            FAILURES,
+            key
+        )
+        .execute(&self.pool)
+        .await
+        {
+            log::warn!("Failed to reset otp failures {err:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "user@example.com";
+    const IP: &str = "1.2.3.4";
+
+    /// Lets `key` request another code without waiting for the cooldown.
+    async fn skip_cooldown(limiter: &MemoryOtpLimiter, key: &str) {
+        limiter.state.lock().await.last_sent.remove(key);
+    }
+
+    #[tokio::test]
+    async fn locks_key_after_max_failures() {
+        let limiter = MemoryOtpLimiter::default();
+        for attempt in 1..MAX_FAILURES {
+            match limiter.record_failure(KEY, Some(IP)).await {
+                OtpError::Invalid { attempts_left } => {
+                    assert_eq!(attempts_left, MAX_FAILURES - attempt)
+                }
+                err => panic!("unexpected {err:?}"),
+            }
+            assert!(limiter.check_verify(KEY, Some(IP)).await.is_ok());
+        }
+        assert!(matches!(
+            limiter.record_failure(KEY, Some(IP)).await,
+            OtpError::Locked { .. }
+        ));
+        assert!(matches!(
+            limiter.check_verify(KEY, Some(IP)).await,
+            Err(OtpError::Locked { .. })
+        ));
+        assert!(matches!(
+            limiter.check_send(KEY, Some(IP)).await,
+            Err(OtpError::Locked { .. })
+        ));
+        // Other keys from the same address are not locked yet.
+        assert!(limiter.check_verify("other", Some(IP)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn lock_expires() {
+        let limiter = MemoryOtpLimiter::default();
+        for _ in 0..MAX_FAILURES {
+            limiter.record_failure(KEY, None).await;
+        }
+        assert!(limiter.check_verify(KEY, None).await.is_err());
+        limiter
+            .state
+            .lock()
+            .await
+            .locked_until
+            .insert(KEY.to_string(), Instant::now());
+        assert!(limiter.check_verify(KEY, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn locks_ip_after_max_ip_failures() {
+        let limiter = MemoryOtpLimiter::default();
+        for attempt in 0..MAX_IP_FAILURES {
+            let key = format!("user{attempt}@example.com");
+            limiter.record_failure(&key, Some(IP)).await;
+        }
+        assert!(matches!(
+            limiter.check_verify(KEY, Some(IP)).await,
+            Err(OtpError::IpLocked { .. })
+        ));
+        assert!(limiter.check_verify(KEY, Some("5.6.7.8")).await.is_ok());
+        assert!(limiter.check_verify(KEY, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn success_resets_failures() {
+        let limiter = MemoryOtpLimiter::default();
+        for _ in 1..MAX_FAILURES {
+            limiter.record_failure(KEY, None).await;
+        }
+        limiter.record_success(KEY).await;
+        assert!(matches!(
+            limiter.record_failure(KEY, None).await,
+            OtpError::Invalid { attempts_left } if attempts_left == MAX_FAILURES - 1
+        ));
+    }
+
+    #[tokio::test]
+    async fn enforces_resend_cooldown() {
+        let limiter = MemoryOtpLimiter::default();
+        assert!(limiter.check_send(KEY, Some(IP)).await.is_ok());
+        match limiter.check_send(KEY, Some(IP)).await {
+            Err(OtpError::ResendCooldown { retry_after }) => {
+                assert!(retry_after <= RESEND_COOLDOWN)
+            }
+            res => panic!("unexpected {res:?}"),
+        }
+        assert!(limiter.check_send("other", Some(IP)).await.is_ok());
+        skip_cooldown(&limiter, KEY).await;
+        assert!(limiter.check_send(KEY, Some(IP)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn enforces_daily_limits() {
+        let limiter = MemoryOtpLimiter::default();
+        for _ in 0..MAX_DAILY_SENDS {
+            skip_cooldown(&limiter, KEY).await;
+            assert!(limiter.check_send(KEY, Some(IP)).await.is_ok());
+        }
+        skip_cooldown(&limiter, KEY).await;
+        assert!(matches!(
+            limiter.check_send(KEY, Some(IP)).await,
+            Err(OtpError::DailyLimit)
+        ));
+
+        for send in MAX_DAILY_SENDS..MAX_IP_DAILY_SENDS {
+            let key = format!("user{send}@example.com");
+            assert!(limiter.check_send(&key, Some(IP)).await.is_ok());
+        }
+        assert!(matches!(
+            limiter.check_send("other", Some(IP)).await,
+            Err(OtpError::IpDailyLimit)
+        ));
+        assert!(limiter.check_send("other", None).await.is_ok());
+    }
+
+    #[test]
+    fn counters_restart_after_window() {
+        let mut counters = Counters::default();
+        assert_eq!(counters.hit(KEY, FAILURE_WINDOW), 1);
+        assert_eq!(counters.hit(KEY, FAILURE_WINDOW), 2);
+        assert_eq!(counters.get(KEY, FAILURE_WINDOW), 2);
+        assert_eq!(counters.get("other", FAILURE_WINDOW), 0);
+        // Every window of zero length has already ended.
+        assert_eq!(counters.get(KEY, Duration::ZERO), 0);
+        assert_eq!(counters.hit(KEY, Duration::ZERO), 1);
+        counters.cleanup(Duration::ZERO);
+        assert!(counters.0.is_empty());
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha256;
+use sqlx::SqlitePool;
+use tokio::sync::RwLock;
+
+use crate::expire_map::ExpiringHashMap;
+
+/// How long a passcode can be used.
+pub const OTP_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Keeps the passcodes sent for logins. `key` is the email or phone number
+/// a code was sent to.
+#[async_trait]
+pub trait OtpStore: Send + Sync {
+    /// Passcode to send to `key`, valid for [`OTP_TTL`] from now.
+    async fn issue(&self, key: &str) -> anyhow::Result<String>;
+
+    /// Whether `otp` is the passcode sent to `key`. A correct code is used up.
+    async fn verify(&self, key: &str, otp: &str) -> anyhow::Result<bool>;
+}
+
+pub type OtpStorage = Box<dyn OtpStore>;
+
+/// Picks the store named by `OTP_STORE`. The in-memory one is the default,
+/// `sqlite` keeps codes across restarts and works with several instances.
+pub fn store_from_env(pool: &SqlitePool) -> anyhow::Result<OtpStorage> {
+    match std::env::var("OTP_STORE").as_deref() {
+        Ok("sqlite") => Ok(Box::new(SqliteOtpStore::new(pool.clone())?)),
+        Ok("memory") | Err(_) => Ok(Box::new(MemoryOtpStore::default())),
+        Ok(store) => Err(anyhow::anyhow!("Unknown OTP_STORE {store}")),
+    }
+}
+
+fn generate_otp() -> String {
+    format!("{:06}", rand::thread_rng().gen_range(0..1_000_000))
+}
+
+/// Codes of this process only, lost on restart.
+pub struct MemoryOtpStore {
+    codes: RwLock<ExpiringHashMap<String, String>>,
+}
+
+impl Default for MemoryOtpStore {
+    fn default() -> Self {
+        Self {
+            codes: RwLock::new(ExpiringHashMap::new(OTP_TTL)),
+        }
+    }
+}
+
+#[async_trait]
+impl OtpStore for MemoryOtpStore {
+    /// A code that is still valid is sent again rather than replaced, so
+    /// earlier messages keep working.
+    async fn issue(&self, key: &str) -> anyhow::Result<String> {
+        let mut codes = self.codes.write().await;
+        let otp = match codes.get(&key.to_string()) {
+            Some(otp) => otp.to_string(),
+            None => generate_otp(),
+        };
+        codes.insert(key.to_string(), otp.clone());
+        Ok(otp)
+    }
+
+    async fn verify(&self, key: &str, otp: &str) -> anyhow::Result<bool> {
+        let mut codes = self.codes.write().await;
+        if codes
+            .get(&key.to_string())
+            .is_some_and(|correct| correct == otp)
+        {
+            codes.remove(&key.to_string());
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+/// Codes in the database, shared by every instance using it. Only hashes
+/// are stored, so a new code replaces the previous one.
+pub struct SqliteOtpStore {
+    pool: SqlitePool,
+    secret: String,
+}
+
+impl SqliteOtpStore {
+    /// Hashes are keyed with `ACCESS_JWT_SECRET`, a leaked table alone is not
+    /// enough to try all million codes offline.
+    pub fn new(pool: SqlitePool) -> anyhow::Result<Self> {
+        let secret = std::env::var("ACCESS_JWT_SECRET")?;
+        Ok(Self { pool, secret })
+    }
+
+    fn hash(&self, key: &str, otp: &str) -> anyhow::Result<String> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())?;
+        mac.update(key.as_bytes());
+        mac.update(b":");
+        mac.update(otp.as_bytes());
+        Ok(format!("{:x}", mac.finalize().into_bytes()))
+    }
+}
+
+#[async_trait]
+impl OtpStore for SqliteOtpStore {
+    async fn issue(&self, key: &str) -> anyhow::Result<String> {
+        let otp = generate_otp();
+        let code_hash = self.hash(key, &otp)?;
+        let now = chrono::Utc::now();
+        let expires_at = (now + chrono::Duration::from_std(OTP_TTL)?).to_rfc3339();
+        let now = now.to_rfc3339();
+        sqlx::query!("DELETE FROM otp_codes WHERE expires_at <= $1", now)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query!(
+            r#"INSERT INTO otp_codes(key, code_hash, expires_at) VALUES ($1, $2, $3)
+            ON CONFLICT(key) DO UPDATE SET code_hash = excluded.code_hash, expires_at = excluded.expires_at
+            "#,
+            key,
+            code_hash,
+            expires_at
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(otp)
+    }
+
+    /// Checks and uses up the code in one statement, so two instances can
+    /// not both accept it.
+    async fn verify(&self, key: &str, otp: &str) -> anyhow::Result<bool> {
+        let code_hash = self.hash(key, otp)?;
+        let now = chrono::Utc::now().to_rfc3339();
+        let used = sqlx::query!(
+            "DELETE FROM otp_codes WHERE key = $1 AND code_hash = $2 AND expires_at > $3",
+            key,
+            code_hash,
+            now
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(used.rows_affected() > 0)
+    }
+}
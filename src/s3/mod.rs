@@ -4,6 +4,7 @@ use s3::{creds::Credentials, Bucket};
 // use aws_sdk_s3::{config::Credentials, presigning::PresigningConfig};
 use uuid::Uuid;
 
+#[derive(Clone)]
 pub struct S3 {
     // r2_access_key_id: String,
     // r2_secret_access_key: String,
@@ -1,30 +1,46 @@
 use std::collections::HashMap;
 
 use crate::{
-    models::user::PaymentMode, notification::send_message_notification_with_retry, s3::S3,
+    models::user::PaymentMode,
+    notification::{notify_user, send_message_notification_with_retry},
+    otp_limit::OtpLimits,
+    otp_store::OtpStorage,
+    s3::S3,
+    sms::{normalize_phone, send_phone_otp, SmsSender},
 };
-use async_graphql::{Context, InputObject, Object, SimpleObject};
+use async_graphql::{Context, ErrorExtensions, InputObject, Object, SimpleObject};
 use futures::{stream::FuturesUnordered, StreamExt};
 use ip2country::AsnDB;
 
-use rand::Rng;
 use sqlx::{Pool, Sqlite};
-use tokio::sync::RwLock;
 use uuid::Uuid;
 
 use crate::{
     auth::{
-        create_tokens, decode_refresh_token, AuthResult, AuthTypes, ForwardedHeader, UserSignedUp,
+        create_tokens, decode_refresh_token,
+        id_token::{verify_id_token, IdTokenProvider},
+        AuthResult, AuthTypes, CurrentSession, ForwardedHeader, PeerAddress, UserSignedUp,
     },
     email::{send_email_invite, send_email_otp},
-    expire_map::ExpiringHashMap,
     models::{
         amount::Amount,
+        budget::Budget,
+        category::Category,
+        comment::{Comment, CommentThread},
         currency::Currency,
         expense::Expense,
-        group::Group,
+        expense_item::ReceiptInput,
+        group::{Group, GroupRole},
+        group_event::{GroupEvent, GroupEventType},
+        group_invitation::GroupInvitation,
+        group_invite::GroupInvite,
+        pending_expense::PendingExpense,
+        recurring_expense::{RecurrenceRule, RecurringExpense},
+        revision::Revision,
+        session::Session,
         split::{Split, TransactionType},
-        user::{User, UserConfig},
+        split_strategy::{checked_sum, SplitStrategy},
+        user::{InvitationPolicy, User, UserConfig},
     },
 };
 
@@ -33,8 +49,6 @@ use super::{
     UpiIdValidator,
 };
 
-pub type OtpMap = RwLock<ExpiringHashMap<String, String>>;
-
 #[derive(Debug, SimpleObject)]
 pub struct SignupSuccess {
     pub user: User,
@@ -44,7 +58,8 @@ pub struct SignupSuccess {
 #[derive(SimpleObject)]
 pub struct NonGroupExpense {
     pub group: Group,
-    pub expense: Expense,
+    /// Empty while the expense waits for everyone invited to accept.
+    pub expense: Option<Expense>,
 }
 
 pub struct Mutation;
@@ -55,78 +70,140 @@ impl Mutation {
         &self,
         context: &Context<'ctx>,
         #[graphql(validator(email))] email: String,
-    ) -> anyhow::Result<bool> {
-        let otp_map = context
-            .data::<OtpMap>()
+    ) -> async_graphql::Result<bool> {
+        let otp_store = context
+            .data::<OtpStorage>()
             .map_err(|_e| anyhow::anyhow!("Something went wrong"))?;
-        let otp = {
-            let mut otp_map = otp_map.write().await;
-            if let Some(otp) = otp_map.get(&email) {
-                otp.to_string()
-            } else {
-                let mut rng = rand::thread_rng();
-                let random_number: u32 = rng.gen_range(0..1_000_000);
-                let random_string = format!("{:06}", random_number);
-                random_string
-            }
-        };
-        {
-            let mut otp_map = otp_map.write().await;
-            otp_map.insert(email.clone(), otp.clone());
-        }
+        get_otp_limiter(context)?
+            .check_send(&email, client_ip(context))
+            .await
+            .map_err(|e| e.extend())?;
+        let otp = otp_store.issue(&email).await?;
 
         send_email_otp(&email, &otp).await?;
         Ok(true)
     }
 
+    /// Texts a passcode to `phone`. The number may be formatted, but must
+    /// start with its country code.
+    pub async fn send_phone_otp<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(max_length = 30))] phone: String,
+    ) -> async_graphql::Result<bool> {
+        let phone = normalize_phone(&phone)?;
+        let otp_store = context
+            .data::<OtpStorage>()
+            .map_err(|_e| anyhow::anyhow!("Something went wrong"))?;
+        let sms = context
+            .data::<SmsSender>()
+            .map_err(|_e| anyhow::anyhow!("Something went wrong"))?;
+        get_otp_limiter(context)?
+            .check_send(&phone, client_ip(context))
+            .await
+            .map_err(|e| e.extend())?;
+        let otp = otp_store.issue(&phone).await?;
+
+        send_phone_otp(sms, &phone, &otp).await?;
+        Ok(true)
+    }
+
     pub async fn verify_otp<'ctx>(
         &self,
         context: &Context<'ctx>,
         #[graphql(validator(email))] email: String,
         #[graphql(validator(max_length = 6))] otp: String,
-    ) -> anyhow::Result<AuthResult> {
+        #[graphql(validator(max_length = 100))] device_name: Option<String>,
+    ) -> async_graphql::Result<AuthResult> {
         let pool = get_pool_from_context(context).await?;
 
-        let otp_map = context
-            .data::<OtpMap>()
+        let otp_store = context
+            .data::<OtpStorage>()
             .map_err(|_e| anyhow::anyhow!("Something went wrong"))?;
+        let limiter = get_otp_limiter(context)?;
+        let ip = client_ip(context);
         let correct_otp = 'otp: {
             if email == "guest@billdivide.app" && &otp == "123456" {
                 break 'otp true;
             };
-            let mut otp_map = otp_map.write().await;
-            let correct_otp = otp_map.get(&email);
-            if let Some(correct_otp) = correct_otp {
-                if &otp == correct_otp {
-                    otp_map.remove(&email);
-                    break 'otp true;
-                }
-            }
-            false
+            limiter
+                .check_verify(&email, ip)
+                .await
+                .map_err(|e| e.extend())?;
+            otp_store.verify(&email, &otp).await?
         };
         if correct_otp {
-            let user = User::get_from_email(&email, pool).await;
-            match user {
-                Ok(user) => {
-                    if user.name.is_none() {
-                        create_tokens(None, user.email, user.phone)
-                    } else {
-                        create_tokens(Some(user.id), user.email, user.phone)
-                    }
-                }
-                Err(_) => create_tokens(None, Some(email), None),
-            }
+            limiter.record_success(&email).await;
+            let user = User::get_from_email(&email, pool).await.ok();
+            Ok(sign_in(context, user, Some(email), None, device_name.as_deref()).await?)
+        } else {
+            Err(limiter.record_failure(&email, ip).await.extend())
+        }
+    }
+
+    pub async fn verify_phone_otp<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(max_length = 30))] phone: String,
+        #[graphql(validator(max_length = 6))] otp: String,
+        #[graphql(validator(max_length = 100))] device_name: Option<String>,
+    ) -> async_graphql::Result<AuthResult> {
+        let pool = get_pool_from_context(context).await?;
+        let phone = normalize_phone(&phone)?;
+
+        let otp_store = context
+            .data::<OtpStorage>()
+            .map_err(|_e| anyhow::anyhow!("Something went wrong"))?;
+        let limiter = get_otp_limiter(context)?;
+        let ip = client_ip(context);
+        limiter
+            .check_verify(&phone, ip)
+            .await
+            .map_err(|e| e.extend())?;
+        if otp_store.verify(&phone, &otp).await? {
+            limiter.record_success(&phone).await;
+            let user = User::get_from_phone(&phone, pool).await.ok();
+            Ok(sign_in(context, user, None, Some(phone), device_name.as_deref()).await?)
         } else {
-            Err(anyhow::anyhow!("OTP Mismatch or expired"))
+            Err(limiter.record_failure(&phone, ip).await.extend())
         }
     }
 
-    pub async fn refresh_token(
+    /// Signs in with an OpenID Connect ID token from Google or Apple. Like
+    /// `verifyOtp`, users without an account get a signup token.
+    pub async fn sign_in_with_id_token<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        provider: IdTokenProvider,
+        #[graphql(validator(max_length = 8000))] id_token: String,
+        #[graphql(validator(max_length = 100))] device_name: Option<String>,
+    ) -> anyhow::Result<AuthResult> {
+        let pool = get_pool_from_context(context).await?;
+        let email = verify_id_token(provider, &id_token).await?;
+        let user = User::get_from_email(&email, pool).await.ok();
+        sign_in(context, user, Some(email), None, device_name.as_deref()).await
+    }
+
+    /// Trades a refresh token for new tokens. Each refresh token works once.
+    pub async fn refresh_token<'ctx>(
         &self,
+        context: &Context<'ctx>,
         #[graphql(validator(max_length = 8000))] refresh_token: String,
     ) -> anyhow::Result<UserSignedUp> {
+        let pool = get_pool_from_context(context).await?;
         let claims = decode_refresh_token(&refresh_token)?;
-        let new_token = create_tokens(claims.user_id, claims.email, claims.phone_number)?;
+        // Refresh tokens from before sessions existed can not be revoked, so
+        // they are not accepted any more.
+        let (Some(session_id), Some(token_id)) = (&claims.session_id, &claims.token_id) else {
+            return Err(anyhow::anyhow!("Session expired, sign in again"));
+        };
+        let session = Session::rotate(session_id, token_id, client_ip(context), pool).await?;
+        let new_token = create_tokens(
+            claims.user_id,
+            claims.email,
+            claims.phone_number,
+            Some(&session),
+        )?;
         if let AuthResult::UserSignedUp(tokens) = new_token {
             Ok(tokens)
         } else {
@@ -143,6 +220,7 @@ impl Mutation {
             max_length = 20
         ))]
         name: String,
+        #[graphql(validator(max_length = 100))] device_name: Option<String>,
     ) -> anyhow::Result<SignupSuccess> {
         let name = name.trim();
         let auth_type = context
@@ -152,10 +230,11 @@ impl Mutation {
             AuthTypes::UnAuthorized => Err(anyhow::anyhow!("Unauthorized")),
             AuthTypes::AuthorizedNotSignedUp(claims) => {
                 let pool = get_pool_from_context(context).await?;
-                let email = claims
-                    .email
-                    .clone()
-                    .ok_or(anyhow::anyhow!("Only email is supported now"))?;
+                let existing_user = match (&claims.email, &claims.phone_number) {
+                    (Some(email), _) => User::get_from_email(email, pool).await,
+                    (None, Some(phone)) => User::get_from_phone(phone, pool).await,
+                    (None, None) => return Err(anyhow::anyhow!("No email or phone number")),
+                };
                 let currency_id = 'currency: {
                     let Ok(db) = context.data::<AsnDB>() else {
                         break 'currency "USD".to_string();
@@ -168,7 +247,7 @@ impl Mutation {
                     };
                     currency.id
                 };
-                let user = match User::get_from_email(&email, pool).await {
+                let user = match existing_user {
                     Ok(user) => User::set_user_name(&user.id, name, currency_id, pool).await?,
                     Err(_) => {
                         let id = uuid::Uuid::new_v4().to_string();
@@ -186,10 +265,14 @@ impl Mutation {
                     }
                 };
 
+                let session =
+                    Session::create(&user.id, device_name.as_deref(), client_ip(context), pool)
+                        .await?;
                 let tokens = create_tokens(
                     Some(user.id.clone()),
                     user.email.clone(),
                     user.phone.clone(),
+                    Some(&session),
                 )?
                 .try_into_user_signed_up()
                 .map_err(|_er| {
@@ -201,6 +284,44 @@ impl Mutation {
         }
     }
 
+    /// Signs out the current session, or another one of the caller's
+    /// sessions when `session_id` is given.
+    pub async fn logout<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("session_id")"#))] session_id: Option<
+            String,
+        >,
+    ) -> anyhow::Result<bool> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let session_id = match session_id {
+            Some(session_id) => session_id,
+            None => context
+                .data_opt::<CurrentSession>()
+                .map(|current| current.0.clone())
+                .ok_or_else(|| anyhow::anyhow!("Not signed in with a session"))?,
+        };
+        Session::revoke(&session_id, &self_user.id, pool).await?;
+        Ok(true)
+    }
+
+    /// Signs out every session of the caller, including the current one.
+    /// Returns how many sessions were signed out.
+    pub async fn logout_all_devices<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<u64> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        Session::revoke_all(&self_user.id, pool).await
+    }
+
     pub async fn create_group<'ctx>(
         &self,
         context: &Context<'ctx>,
@@ -224,6 +345,17 @@ impl Mutation {
                 let group = Group::create_group(&id, &_user.id, Some(name), pool)
                     .await
                     .map_err(|_e| anyhow::anyhow!("Can't create group"))?;
+                GroupEvent::record(
+                    &group.id,
+                    &_user.id,
+                    GroupEventType::GroupCreated,
+                    None,
+                    None,
+                    None,
+                    group.name.as_deref(),
+                    pool,
+                )
+                .await;
                 Ok(group)
             }
         }
@@ -268,9 +400,11 @@ impl Mutation {
                 };
 
                 let pool = get_pool_from_context(context).await?;
-                let user_groups = _user.get_groups(pool).await?;
                 let group = Group::get_from_id(&group_id, pool).await?;
-                if user_groups.iter().any(|group| group.id == group_id) {
+                let can_add = Group::get_role(&group_id, &_user.id, pool)
+                    .await
+                    .is_ok_and(|role| role.can_manage_members());
+                if can_add {
                     let user = User::get_from_email(&email, pool).await;
                     let user = match user {
                         Ok(user) => user,
@@ -281,324 +415,730 @@ impl Mutation {
                             user
                         }
                     };
-                    if let Some(token) = user.notification_token {
-                        if let Err(err) = send_message_notification_with_retry(
-                            format!(
-                                "{} added you to group {}",
-                                _user.name.as_ref().unwrap_or(&"Someone".to_string()),
-                                group.name.as_ref().unwrap_or(&"Direct Payment".to_string()),
-                            )
-                            .as_str(),
-                            "/",
-                            "https://billdivide.app/",
-                            format!(
-                                "you were added to group {} by {}",
-                                group.name.as_ref().unwrap_or(&"Direct Payment".to_string()),
-                                _user.name.as_ref().unwrap_or(&"Someone".to_string()),
-                            )
-                            .as_str(),
-                            &token,
+                    // Users who have not agreed to join get an invitation to answer instead.
+                    let invitation =
+                        GroupInvitation::invite(&group, _user, &user, GroupRole::Member, pool)
+                            .await?;
+                    if invitation.is_none() {
+                        GroupEvent::record(
+                            &group_id,
+                            &_user.id,
+                            GroupEventType::MemberJoined,
                             None,
+                            None,
+                            Some(&user.id),
+                            None,
+                            pool,
                         )
-                        .await
-                        {
-                            log::warn!("Failed to send notification {err:?}")
+                        .await;
+                        if let Some(token) = &user.notification_token {
+                            if let Err(err) = send_message_notification_with_retry(
+                                format!(
+                                    "{} added you to group {}",
+                                    _user.name.as_ref().unwrap_or(&"Someone".to_string()),
+                                    group.name.as_ref().unwrap_or(&"Direct Payment".to_string()),
+                                )
+                                .as_str(),
+                                "/",
+                                "https://billdivide.app/",
+                                format!(
+                                    "you were added to group {} by {}",
+                                    group.name.as_ref().unwrap_or(&"Direct Payment".to_string()),
+                                    _user.name.as_ref().unwrap_or(&"Someone".to_string()),
+                                )
+                                .as_str(),
+                                token,
+                                None,
+                            )
+                            .await
+                            {
+                                log::warn!("Failed to send notification {err:?}")
+                            } else {
+                                log::info!("Notification sent")
+                            }
                         } else {
-                            log::info!("Notification sent")
+                            log::info!("Skipping notification, no token")
                         }
-                    } else {
-                        log::info!("Skipping notification, no token")
                     }
-                    Group::add_to_group(&group_id, &user.id, pool)
-                        .await
-                        .map_err(|_e| anyhow::anyhow!("Can't create group"))?;
                     Ok("success")
                 } else {
-                    Err(anyhow::anyhow!("You must be in group to add other user"))
+                    Err(anyhow::anyhow!(
+                        "You are not allowed to add members to this group"
+                    ))
                 }
             }
         }
     }
 
-    #[allow(clippy::too_many_arguments)]
-    pub async fn add_non_group_expense<'ctx>(
+    /// Removes another member from the group. While they have an outstanding
+    /// balance this fails unless `force` is set.
+    pub async fn remove_group_member<'ctx>(
         &self,
         context: &Context<'ctx>,
-        #[graphql(validator(
-            custom = r#"NameValidator::new("title")"#,
-            min_length = 3,
-            max_length = 20
-        ))]
-        title: String,
-        amount: i64,
-        #[graphql(validator(max_length = 100))] currency_id: String,
-        splits: Vec<SplitInputNonGroup>,
-        #[graphql(validator(max_length = 300))] note: Option<String>,
-        #[graphql(validator(custom = r#"IdValidator::new("image_id")"#))] image_id: Option<String>,
-        #[graphql(default = "\"MISC\".to_string()", validator(max_length = 100))] category: String,
-        #[graphql(validator(custom = r#"DateTimeValidator::new("transaction_at")"#))]
-        transaction_at: Option<String>,
-    ) -> anyhow::Result<NonGroupExpense> {
-        let title = title.trim().to_string();
-        let auth_type = context
+        #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))] group_id: String,
+        #[graphql(validator(custom = r#"IdValidator::new("user_id")"#))] user_id: String,
+        #[graphql(default)] force: bool,
+    ) -> anyhow::Result<Group> {
+        let self_user = context
             .data::<AuthTypes>()
-            .map_err(|e| anyhow::anyhow!("{e:#?}"))?;
-        match auth_type {
-            AuthTypes::UnAuthorized => Err(anyhow::anyhow!("Unauthorized")),
-            AuthTypes::AuthorizedNotSignedUp(_phone) => Err(anyhow::anyhow!("Unauthorized")),
-            AuthTypes::AuthorizedUser(_user) => {
-                let pool = get_pool_from_context(context).await?;
-                let futures = FuturesUnordered::new();
-                let Some(name) = _user.name.clone() else {
-                    return Err(anyhow::anyhow!("wtf??"));
-                };
-
-                // let mut split_users = vec![];
-
-                async fn map_split_input_group_to_user(
-                    split: &SplitInputNonGroup,
-                    inviter: String,
-                    pool: &Pool<Sqlite>,
-                ) -> anyhow::Result<SplitInput> {
-                    if let Some(user_id) = &split.user_id {
-                        let user = User::get_from_id(user_id, pool).await?;
-                        Ok(SplitInput {
-                            user_id: user.id,
-                            amount: split.amount,
-                        })
-                    } else if let Some(email) = &split.email {
-                        if let Ok(user) = User::get_from_email(email, pool).await {
-                            Ok(SplitInput {
-                                user_id: user.id,
-                                amount: split.amount,
-                            })
-                        } else {
-                            let id = uuid::Uuid::new_v4().to_string();
-                            let user = User::new_invite_user(&id, email.to_string(), pool).await?;
-                            let _ = send_email_invite(email, &inviter).await;
-                            Ok(SplitInput {
-                                user_id: user.id,
-                                amount: split.amount,
-                            })
-                        }
-                    } else {
-                        Err(anyhow::anyhow!("User must have user id or email"))
-                    }
-                }
-                for split in splits.iter() {
-                    futures.push(map_split_input_group_to_user(split, name.clone(), pool))
-                }
-
-                let users = futures.collect::<Vec<_>>().await;
-                let mut splits = vec![];
-                for user in users {
-                    match user {
-                        Ok(user) => splits.push(user),
-                        Err(err) => return Err(anyhow::anyhow!("Can not get split user {err:?}")),
-                    }
-                }
-                let mut user_ids = vec![_user.id.clone()];
-                splits.iter().for_each(|f| user_ids.push(f.user_id.clone()));
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        if user_id == self_user.id {
+            return Err(anyhow::anyhow!("Use leaveGroup to leave a group"));
+        }
+        let group = Group::get_from_id(&group_id, pool).await?;
+        let role = Group::get_role(&group_id, &self_user.id, pool).await?;
+        if !role.can_manage_members() {
+            return Err(anyhow::anyhow!("You are not allowed to remove members"));
+        }
+        if Group::get_role(&group_id, &user_id, pool).await? == GroupRole::Owner
+            && role != GroupRole::Owner
+        {
+            return Err(anyhow::anyhow!("Only owners can remove owners"));
+        }
+        let member = User::get_from_id(&user_id, pool).await?;
+        group.remove_member(&user_id, force, pool).await?;
+        GroupEvent::record(
+            &group_id,
+            &self_user.id,
+            GroupEventType::MemberRemoved,
+            None,
+            None,
+            Some(&user_id),
+            None,
+            pool,
+        )
+        .await;
 
-                log::info!("Searching for groups with users {user_ids:?}");
-                let group = match Group::find_group_for_users(user_ids, pool).await {
-                    Ok(gid) => {
-                        log::info!("Found existing group {gid:?}");
-                        gid
-                    }
-                    Err(err) => {
-                        log::info!("Not found existing group {err:?}");
-                        let id = uuid::Uuid::new_v4().to_string();
-                        let group = Group::create_group(&id, &_user.id, None, pool).await?;
-                        let futures = FuturesUnordered::new();
-                        for user in splits.iter() {
-                            futures.push(Group::add_to_group(&group.id, &user.user_id, pool))
-                        }
-                        let result = futures.collect::<Vec<_>>().await;
-                        if result.iter().any(|v| v.is_err()) {
-                            return Err(anyhow::anyhow!("Cannot add everyone to group"));
-                        }
-                        group
-                    }
-                };
-                let expense = self
-                    .add_expense(
-                        context,
-                        group.id.to_string(),
-                        title,
-                        amount,
-                        currency_id,
-                        splits.clone(),
-                        note,
-                        image_id,
-                        category,
-                        transaction_at,
-                    )
-                    .await?;
-                for user in splits.into_iter() {
-                    let _ = self.simplify_cross_group(context, user.user_id).await;
-                }
+        let group_name = group.name.as_deref().unwrap_or("Direct Payment");
+        let remover = self_user.name.as_deref().unwrap_or("Someone");
+        notify_user(
+            &member,
+            &format!("{remover} removed you from group {group_name}"),
+            &format!("you were removed from group {group_name} by {remover}"),
+            None,
+        )
+        .await;
+        Ok(group)
+    }
 
-                Ok(NonGroupExpense { group, expense })
-            }
+    /// Leaves the group. While there is an outstanding balance this fails
+    /// unless `force` is set.
+    pub async fn leave_group<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))] group_id: String,
+        #[graphql(default)] force: bool,
+    ) -> anyhow::Result<Group> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let group = Group::get_from_id(&group_id, pool).await?;
+        let roles = Group::get_roles(&group_id, pool).await?;
+        let other_owner = roles
+            .iter()
+            .any(|(user_id, role)| user_id != &self_user.id && *role == GroupRole::Owner);
+        if roles.get(&self_user.id) == Some(&GroupRole::Owner) && roles.len() > 1 && !other_owner {
+            return Err(anyhow::anyhow!("Make another member owner before leaving"));
         }
+        group.remove_member(&self_user.id, force, pool).await?;
+        GroupEvent::record(
+            &group_id,
+            &self_user.id,
+            GroupEventType::MemberLeft,
+            None,
+            None,
+            Some(&self_user.id),
+            None,
+            pool,
+        )
+        .await;
+        Ok(group)
     }
 
-    // pub async fn edit_expense_title<'ctx>(
-    //     &self,
-    //     context: &Context<'ctx>,
-    //     #[graphql(validator(max_length = 100))] expense_id: String,
-    //     #[graphql(validator(
-    //         regex = r"^[^\p{P}\p{S}\p{C}0-9_]+(?: [^\p{P}\p{S}\p{C}0-9_]+)*$",
-    //         min_length = 3,
-    //         max_length = 20
-    //     ))]
-    //     title: String,
-    // ) -> anyhow::Result<Expense> {
-    //     let _self_user = context
-    //         .data::<AuthTypes>()
-    //         .map_err(|e| anyhow::anyhow!("{e:#?}"))?
-    //         .as_authorized_user()
-    //         .ok_or(anyhow::anyhow!("Unauthorized"))?;
-    //     let pool = get_pool_from_context(context).await?;
-    //     let update_time = chrono::Utc::now().to_rfc3339();
-
-    //     let result = sqlx::query_as!(
-    //         Expense,
-    //         "UPDATE expenses SET title=$1, updated_at=$3 WHERE id=$2 RETURNING *",
-    //         title,
-    //         expense_id,
-    //         update_time
-    //     )
-    //     .fetch_one(pool)
-    //     .await?;
-    //     Ok(result)
-    // }
-
-    // pub async fn edit_expense_category<'ctx>(
-    //     &self,
-    //     context: &Context<'ctx>,
-    //     #[graphql(validator(max_length = 100))] expense_id: String,
-    //     #[graphql(default = "\"MISC\".to_string()", validator(max_length = 100))] category: String,
-    // ) -> anyhow::Result<Expense> {
-    //     let _self_user = context
-    //         .data::<AuthTypes>()
-    //         .map_err(|e| anyhow::anyhow!("{e:#?}"))?
-    //         .as_authorized_user()
-    //         .ok_or(anyhow::anyhow!("Unauthorized"))?;
-    //     let pool = get_pool_from_context(context).await?;
-    //     let update_time = chrono::Utc::now().to_rfc3339();
-
-    //     let result = sqlx::query_as!(
-    //         Expense,
-    //         "UPDATE expenses SET category=$1, updated_at=$3 WHERE id=$2 RETURNING *",
-    //         category,
-    //         expense_id,
-    //         update_time
-    //     )
-    //     .fetch_one(pool)
-    //     .await?;
-    //     Ok(result)
-    // }
-
-    // pub async fn edit_expense<'ctx>(
-    //     &self,
-    //     context: &Context<'ctx>,
-    //     #[graphql(validator(max_length = 100))] expense_id: String,
-    //     #[graphql(validator(
-    //         regex = r"^[^\p{P}\p{S}\p{C}0-9_]+(?: [^\p{P}\p{S}\p{C}0-9_]+)*$",
-    //         min_length = 3,
-    //         max_length = 20
-    //     ))]
-    //     title: Option<String>,
-    //     amount: Option<i64>,
-    //     #[graphql(validator(max_length = 100))] currency_id: Option<String>,
-    //     splits: Option<Vec<SplitInput>>,
-    //     #[graphql(validator(max_length = 300))] note: Option<String>,
-    //     #[graphql(validator(max_length = 100))] image_id: Option<String>,
-    //     #[graphql(default = "\"MISC\".to_string()", validator(max_length = 100))] category: String,
-    //     #[graphql(default)] should_replace_image: bool,
-    // ) -> anyhow::Result<Expense> {
-    //     let s3 = context.data::<S3>().map_err(|e| anyhow::anyhow!("{e:?}"))?;
-    //     let self_user = context
-    //         .data::<AuthTypes>()
-    //         .map_err(|e| anyhow::anyhow!("{e:#?}"))?
-    //         .as_authorized_user()
-    //         .ok_or(anyhow::anyhow!("Unauthorized"))?;
-    //     let pool = get_pool_from_context(context).await?;
-
-    //     let expense = Expense::get_from_id(&expense_id, pool).await?;
-    //     if expense.created_by == self_user.id {
-    //         return Err(anyhow::anyhow!("You are not creator"));
-    //     }
-    //     let mut transaction = pool.begin().await?;
-    //     if let Some(title) = title {
-    //         let result = sqlx::query!(
-    //             "UPDATE expenses SET title=$1 WHERE id=$2",
-    //             title,
-    //             expense_id
-    //         )
-    //         .execute(transaction.as_mut())
-    //         .await?
-    //         .rows_affected();
-    //         if result != 1 {
-    //             return Err(anyhow::anyhow!("Something went wrong"));
-    //         }
-    //     }
-    //     if let Some(amount) = amount {
-    //         let (Some(splits), Some(currency_id)) = (splits, currency_id) else {
-    //             return Err(anyhow::anyhow!(
-    //                 "Must have split with amount and currency id"
-    //             ));
-    //         };
-    //         if splits.iter().any(|split| split.user_id == self_user.id) {
-    //             return Err(anyhow::anyhow!("Cant split to self"));
-    //         }
-    //         if amount <= 0 {
-    //             return Err(anyhow::anyhow!("Amount must be greater than 0"));
-    //         }
-    //         let splits = splits
-    //             .into_iter()
-    //             .filter(|f| f.amount > 0)
-    //             .collect::<Vec<_>>();
-    //         let group_members = Group::get_users(&expense.group_id, pool).await?;
-    //         if !splits
-    //             .iter()
-    //             .all(|s| group_members.iter().any(|user| user.id == s.user_id))
-    //         {
-    //             return Err(anyhow::anyhow!("Not everyone is group member"));
-    //         }
-    //         let result = sqlx::query!(
-    //             "UPDATE expenses SET amount=$1, currency_id=$2 WHERE id=$3",
-    //             amount,
-    //             currency_id,
-    //             expense_id
-    //         )
-    //         .execute(transaction.as_mut())
-    //         .await?
-    //         .rows_affected();
-    //         Expense::edit_expense_splits(&expense_id, splits, &self_user.id, s3, &mut transaction)
-    //             .await?;
-    //         if result != 1 {
-    //             return Err(anyhow::anyhow!("Something went wrong"));
-    //         }
-    //     }
-    //     todo!()
-    // }
-    #[allow(clippy::too_many_arguments)]
-    pub async fn add_expense<'ctx>(
+    /// Creates a shareable invite link. Links expire after a week unless
+    /// `expires_at` says otherwise.
+    pub async fn create_group_invite<'ctx>(
         &self,
         context: &Context<'ctx>,
         #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))] group_id: String,
-        #[graphql(validator(
-            custom = r#"NameValidator::new("title")"#,
-            min_length = 3,
-            max_length = 20
-        ))]
-        title: String,
+        #[graphql(default_with = "GroupRole::Member")] role: GroupRole,
+        max_uses: Option<i64>,
+        #[graphql(validator(custom = r#"DateTimeValidator::new("expires_at")"#))]
+        expires_at: Option<String>,
+    ) -> anyhow::Result<GroupInvite> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let group = Group::get_from_id(&group_id, pool).await?;
+        if group.name.is_none() {
+            return Err(anyhow::anyhow!("Can not invite to a direct payment group"));
+        }
+        let self_role = Group::get_role(&group_id, &self_user.id, pool).await?;
+        if !self_role.can_manage_members() {
+            return Err(anyhow::anyhow!(
+                "You are not allowed to add members to this group"
+            ));
+        }
+        match role {
+            GroupRole::Owner => return Err(anyhow::anyhow!("Invites can not make owners")),
+            GroupRole::Admin if !self_role.can_change_roles() => {
+                return Err(anyhow::anyhow!("Only owners can invite admins"))
+            }
+            _ => {}
+        }
+        GroupInvite::create(&group_id, &self_user.id, role, max_uses, expires_at, pool).await
+    }
+
+    /// Revokes an invite link. The creator of the link and anyone who can
+    /// manage members can do this.
+    pub async fn revoke_group_invite<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("invite_id")"#))] invite_id: String,
+    ) -> anyhow::Result<GroupInvite> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let invite = GroupInvite::get_from_id(&invite_id, pool).await?;
+        if invite.created_by != self_user.id
+            && !Group::get_role(&invite.group_id, &self_user.id, pool)
+                .await
+                .is_ok_and(|role| role.can_manage_members())
+        {
+            return Err(anyhow::anyhow!("You are not creator"));
+        }
+        GroupInvite::revoke(&invite_id, pool).await
+    }
+
+    pub async fn join_group_by_invite<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(min_length = 1, max_length = 100))] token: String,
+    ) -> anyhow::Result<Group> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let invite = GroupInvite::get_from_token(&token, pool).await?;
+        if Group::get_role(&invite.group_id, &self_user.id, pool)
+            .await
+            .is_ok()
+        {
+            return Err(anyhow::anyhow!("Already a group member"));
+        }
+        let invite = GroupInvite::claim(&token, pool).await?;
+        Group::add_to_group(&invite.group_id, &self_user.id, invite.get_role(), pool).await?;
+        GroupEvent::record(
+            &invite.group_id,
+            &self_user.id,
+            GroupEventType::MemberJoined,
+            None,
+            None,
+            Some(&self_user.id),
+            None,
+            pool,
+        )
+        .await;
+        let group = Group::get_from_id(&invite.group_id, pool).await?;
+
+        let inviter = User::get_from_id(&invite.created_by, pool).await?;
+        let group_name = group.name.as_deref().unwrap_or("Direct Payment");
+        notify_user(
+            &inviter,
+            &format!(
+                "{} joined group {group_name}",
+                self_user.name.as_deref().unwrap_or("Someone")
+            ),
+            &format!(
+                "{} joined group {group_name} with your invite link",
+                self_user.name.as_deref().unwrap_or("Someone")
+            ),
+            None,
+        )
+        .await;
+        Ok(group)
+    }
+
+    /// Accepts or declines an invitation to join a group.
+    pub async fn respond_to_invitation<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("invitation_id")"#))]
+        invitation_id: String,
+        accept: bool,
+    ) -> anyhow::Result<GroupInvitation> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let s3 = context.data::<S3>().map_err(|e| anyhow::anyhow!("{e:?}"))?;
+        let invitation = GroupInvitation::get_from_id(&invitation_id, pool).await?;
+        if invitation.user_id != self_user.id {
+            return Err(anyhow::anyhow!("Unauthorized"));
+        }
+        let invitation = invitation.respond(accept, s3, pool).await?;
+        if accept {
+            GroupEvent::record(
+                &invitation.group_id,
+                &self_user.id,
+                GroupEventType::MemberJoined,
+                None,
+                None,
+                Some(&self_user.id),
+                None,
+                pool,
+            )
+            .await;
+            let group = Group::get_from_id(&invitation.group_id, pool).await?;
+            let inviter = User::get_from_id(&invitation.invited_by, pool).await?;
+            let group_name = group.name.as_deref().unwrap_or("Direct Payment");
+            notify_user(
+                &inviter,
+                &format!(
+                    "{} joined group {group_name}",
+                    self_user.name.as_deref().unwrap_or("Someone")
+                ),
+                &format!(
+                    "{} accepted your invitation to {group_name}",
+                    self_user.name.as_deref().unwrap_or("Someone")
+                ),
+                None,
+            )
+            .await;
+        }
+        Ok(invitation)
+    }
+
+    /// Changes the role of a member. Only owners can do this, and a group
+    /// always keeps at least one owner.
+    pub async fn set_member_role<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))] group_id: String,
+        #[graphql(validator(custom = r#"IdValidator::new("user_id")"#))] user_id: String,
+        role: GroupRole,
+    ) -> anyhow::Result<Group> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let group = Group::get_from_id(&group_id, pool).await?;
+        let roles = Group::get_roles(&group_id, pool).await?;
+        if !roles
+            .get(&self_user.id)
+            .is_some_and(|role| role.can_change_roles())
+        {
+            return Err(anyhow::anyhow!("Only owners can change roles"));
+        }
+        let Some(old_role) = roles.get(&user_id) else {
+            return Err(anyhow::anyhow!("Not a group member"));
+        };
+        if *old_role == GroupRole::Owner
+            && role != GroupRole::Owner
+            && !roles
+                .iter()
+                .any(|(id, role)| id != &user_id && *role == GroupRole::Owner)
+        {
+            return Err(anyhow::anyhow!("Group must have an owner"));
+        }
+        Group::set_role(&group_id, &user_id, role, pool).await?;
+        GroupEvent::record(
+            &group_id,
+            &self_user.id,
+            GroupEventType::RoleChanged,
+            None,
+            None,
+            Some(&user_id),
+            Some(&role.to_string()),
+            pool,
+        )
+        .await;
+        Ok(group)
+    }
+
+    /// Archives a group. Unsettled groups are only archived with `force`.
+    pub async fn archive_group<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))] group_id: String,
+        #[graphql(default)] force: bool,
+    ) -> anyhow::Result<Group> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let group = Group::get_from_id(&group_id, pool).await?;
+        if !Group::get_role(&group_id, &self_user.id, pool)
+            .await?
+            .can_archive()
+        {
+            return Err(anyhow::anyhow!("You are not allowed to archive this group"));
+        }
+        let group = group.set_archived(true, force, pool).await?;
+        GroupEvent::record(
+            &group_id,
+            &self_user.id,
+            GroupEventType::GroupArchived,
+            None,
+            None,
+            None,
+            None,
+            pool,
+        )
+        .await;
+        Ok(group)
+    }
+
+    pub async fn unarchive_group<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))] group_id: String,
+    ) -> anyhow::Result<Group> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let group = Group::get_from_id(&group_id, pool).await?;
+        if !Group::get_role(&group_id, &self_user.id, pool)
+            .await?
+            .can_archive()
+        {
+            return Err(anyhow::anyhow!("You are not allowed to archive this group"));
+        }
+        let group = group.set_archived(false, false, pool).await?;
+        GroupEvent::record(
+            &group_id,
+            &self_user.id,
+            GroupEventType::GroupUnarchived,
+            None,
+            None,
+            None,
+            None,
+            pool,
+        )
+        .await;
+        Ok(group)
+    }
+
+    /// Changes the details of a group and tells the other members.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn update_group<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))] group_id: String,
+        #[graphql(validator(
+            custom = r#"NameValidator::new("name")"#,
+            min_length = 3,
+            max_length = 20
+        ))]
+        name: Option<String>,
+        #[graphql(validator(max_length = 300))] description: Option<String>,
+        #[graphql(validator(max_length = 100))] image_id: Option<String>,
+        #[graphql(validator(max_length = 100))] default_currency_id: Option<String>,
+    ) -> anyhow::Result<Group> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let s3 = context.data::<S3>().map_err(|e| anyhow::anyhow!("{e:?}"))?;
+        let old_group = Group::get_from_id(&group_id, pool).await?;
+        if !Group::get_role(&group_id, &self_user.id, pool)
+            .await?
+            .can_edit_details()
+        {
+            return Err(anyhow::anyhow!("You are not allowed to change this group"));
+        }
+        // An empty image or currency clears it.
+        if let Some(image_id) = image_id.as_deref().filter(|id| !id.is_empty()) {
+            Uuid::parse_str(image_id).map_err(|_e| anyhow::anyhow!("Invalid image_id"))?;
+        }
+        if let Some(currency_id) = default_currency_id.as_deref().filter(|id| !id.is_empty()) {
+            Currency::get_for_id(pool, currency_id).await?;
+        }
+        let name = name.as_deref().map(|name| name.trim());
+        let description = description.as_deref().map(|description| description.trim());
+        let group = old_group
+            .update(
+                name,
+                description,
+                image_id.as_deref(),
+                default_currency_id.as_deref(),
+                s3,
+                pool,
+            )
+            .await?;
+
+        let mut changes = vec![];
+        if group.name != old_group.name {
+            changes.push(format!(
+                "renamed the group to {}",
+                group.name.as_deref().unwrap_or_default()
+            ));
+            GroupEvent::record(
+                &group_id,
+                &self_user.id,
+                GroupEventType::GroupRenamed,
+                None,
+                None,
+                None,
+                group.name.as_deref(),
+                pool,
+            )
+            .await;
+        }
+        let renamed = changes.len();
+        if group.description != old_group.description {
+            changes.push("changed the description".to_string());
+        }
+        if group.image_id != old_group.image_id {
+            changes.push(match group.image_id {
+                Some(_) => "changed the picture".to_string(),
+                None => "removed the picture".to_string(),
+            });
+        }
+        if group.default_currency_id != old_group.default_currency_id {
+            changes.push(match &group.default_currency_id {
+                Some(currency_id) => format!("set the default currency to {currency_id}"),
+                None => "removed the default currency".to_string(),
+            });
+        }
+        if changes.len() > renamed {
+            GroupEvent::record(
+                &group_id,
+                &self_user.id,
+                GroupEventType::GroupUpdated,
+                None,
+                None,
+                None,
+                Some(&changes[renamed..].join(", ")),
+                pool,
+            )
+            .await;
+        }
+        if !changes.is_empty() {
+            let group_name = old_group.name.as_deref().unwrap_or("Direct Payment");
+            let actor = self_user.name.as_deref().unwrap_or("Someone");
+            for user in Group::get_users(&group_id, pool)
+                .await?
+                .iter()
+                .filter(|user| user.id != self_user.id)
+            {
+                notify_user(
+                    user,
+                    &format!("{actor} updated group {group_name}"),
+                    &format!("{actor} {}", changes.join(", ")),
+                    None,
+                )
+                .await;
+            }
+        }
+        Ok(group)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn add_non_group_expense<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(
+            custom = r#"NameValidator::new("title")"#,
+            min_length = 3,
+            max_length = 20
+        ))]
+        title: String,
         amount: i64,
         #[graphql(validator(max_length = 100))] currency_id: String,
-        splits: Vec<SplitInput>,
+        splits: Vec<SplitInputNonGroup>,
+        #[graphql(validator(max_length = 300))] note: Option<String>,
+        #[graphql(validator(custom = r#"IdValidator::new("image_id")"#))] image_id: Option<String>,
+        #[graphql(default = "\"MISC\".to_string()", validator(max_length = 100))] category: String,
+        #[graphql(validator(custom = r#"DateTimeValidator::new("transaction_at")"#))]
+        transaction_at: Option<String>,
+    ) -> anyhow::Result<NonGroupExpense> {
+        let title = title.trim().to_string();
+        let auth_type = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?;
+        match auth_type {
+            AuthTypes::UnAuthorized => Err(anyhow::anyhow!("Unauthorized")),
+            AuthTypes::AuthorizedNotSignedUp(_phone) => Err(anyhow::anyhow!("Unauthorized")),
+            AuthTypes::AuthorizedUser(_user) => {
+                let pool = get_pool_from_context(context).await?;
+                let futures = FuturesUnordered::new();
+                let Some(name) = _user.name.clone() else {
+                    return Err(anyhow::anyhow!("wtf??"));
+                };
+
+                // let mut split_users = vec![];
+
+                async fn map_split_input_group_to_user(
+                    split: &SplitInputNonGroup,
+                    inviter: String,
+                    pool: &Pool<Sqlite>,
+                ) -> anyhow::Result<SplitInput> {
+                    if let Some(user_id) = &split.user_id {
+                        let user = User::get_from_id(user_id, pool).await?;
+                        Ok(SplitInput {
+                            user_id: user.id,
+                            amount: split.amount,
+                        })
+                    } else if let Some(email) = &split.email {
+                        if let Ok(user) = User::get_from_email(email, pool).await {
+                            Ok(SplitInput {
+                                user_id: user.id,
+                                amount: split.amount,
+                            })
+                        } else {
+                            let id = uuid::Uuid::new_v4().to_string();
+                            let user = User::new_invite_user(&id, email.to_string(), pool).await?;
+                            let _ = send_email_invite(email, &inviter).await;
+                            Ok(SplitInput {
+                                user_id: user.id,
+                                amount: split.amount,
+                            })
+                        }
+                    } else {
+                        Err(anyhow::anyhow!("User must have user id or email"))
+                    }
+                }
+                for split in splits.iter() {
+                    futures.push(map_split_input_group_to_user(split, name.clone(), pool))
+                }
+
+                let users = futures.collect::<Vec<_>>().await;
+                let mut splits = vec![];
+                for user in users {
+                    match user {
+                        Ok(user) => splits.push(user),
+                        Err(err) => return Err(anyhow::anyhow!("Can not get split user {err:?}")),
+                    }
+                }
+                // Checked before any group or invitation is created for it.
+                if amount <= 0 {
+                    return Err(anyhow::anyhow!("Amount must be greater than 0"));
+                }
+                if splits.iter().any(|split| split.user_id == _user.id) {
+                    return Err(anyhow::anyhow!("Cant split to self"));
+                }
+                Currency::get_for_id(pool, &currency_id).await?;
+                let split_strategy = SplitStrategy::from_splits(amount, &_user.id, &splits)?;
+                let mut user_ids = vec![_user.id.clone()];
+                splits.iter().for_each(|f| user_ids.push(f.user_id.clone()));
+
+                log::info!("Searching for groups with users {user_ids:?}");
+                let group = match Group::find_group_for_users(user_ids, pool).await {
+                    Ok(gid) => {
+                        log::info!("Found existing group {gid:?}");
+                        gid
+                    }
+                    Err(err) => {
+                        log::info!("Not found existing group {err:?}");
+                        let id = uuid::Uuid::new_v4().to_string();
+                        let group = Group::create_group(&id, &_user.id, None, pool).await?;
+                        for split in splits.iter() {
+                            let user = User::get_from_id(&split.user_id, pool).await?;
+                            GroupInvitation::invite(&group, _user, &user, GroupRole::Member, pool)
+                                .await
+                                .map_err(|_e| anyhow::anyhow!("Cannot add everyone to group"))?;
+                        }
+                        group
+                    }
+                };
+                // Direct groups need consent too, the expense is kept aside until
+                // everyone invited has accepted.
+                if GroupInvitation::has_pending(&group.id, pool).await? {
+                    let category =
+                        Category::resolve(&category, &_user.id, Some(&group.id), pool).await?;
+                    PendingExpense::create(
+                        &_user.id,
+                        &group.id,
+                        &title,
+                        &Amount {
+                            amount,
+                            currency_id,
+                        },
+                        &split_strategy,
+                        &category,
+                        note,
+                        image_id,
+                        transaction_at,
+                        pool,
+                    )
+                    .await?;
+                    return Ok(NonGroupExpense {
+                        group,
+                        expense: None,
+                    });
+                }
+                let expense = self
+                    .add_expense(
+                        context,
+                        group.id.to_string(),
+                        title,
+                        amount,
+                        Some(currency_id),
+                        Some(splits.clone()),
+                        None,
+                        None,
+                        None,
+                        note,
+                        image_id,
+                        category,
+                        transaction_at,
+                    )
+                    .await?;
+                for user in splits.into_iter() {
+                    let _ = self.simplify_cross_group(context, user.user_id).await;
+                }
+
+                Ok(NonGroupExpense {
+                    group,
+                    expense: Some(expense),
+                })
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn add_expense<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))] group_id: String,
+        #[graphql(validator(
+            custom = r#"NameValidator::new("title")"#,
+            min_length = 3,
+            max_length = 20
+        ))]
+        title: String,
+        amount: i64,
+        #[graphql(validator(max_length = 100))] currency_id: Option<String>,
+        splits: Option<Vec<SplitInput>>,
+        split_strategy: Option<SplitStrategy>,
+        receipt: Option<ReceiptInput>,
+        payers: Option<Vec<PayerInput>>,
         #[graphql(validator(max_length = 300))] note: Option<String>,
         #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))] image_id: Option<String>,
         #[graphql(default = "\"MISC\".to_string()", validator(max_length = 100))] category: String,
@@ -615,86 +1155,690 @@ impl Mutation {
             AuthTypes::UnAuthorized => Err(anyhow::anyhow!("Unauthorized")),
             AuthTypes::AuthorizedNotSignedUp(_phone) => Err(anyhow::anyhow!("Unauthorized")),
             AuthTypes::AuthorizedUser(_user) => {
-                if splits.iter().any(|split| split.user_id == _user.id) {
-                    return Err(anyhow::anyhow!("Cant split to self"));
-                }
                 if amount <= 0 {
                     return Err(anyhow::anyhow!("Amount must be greater than 0"));
                 }
-                let pool = get_pool_from_context(context).await?;
-                let currency = Currency::get_for_id(pool, &currency_id).await?;
-                let group = Group::get_from_id(&group_id, pool).await?;
-                let splits = splits
-                    .into_iter()
-                    .filter(|f| f.amount > 0)
-                    .collect::<Vec<_>>();
-                let group_members = Group::get_users(&group_id, pool).await?;
+                let splits = match (splits, &split_strategy, &receipt) {
+                    (Some(splits), None, None) => {
+                        if splits.iter().any(|split| split.user_id == _user.id) {
+                            return Err(anyhow::anyhow!("Cant split to self"));
+                        }
+                        splits
+                    }
+                    (None, Some(strategy), None) => strategy.resolve_owed_to(amount, &_user.id)?,
+                    (None, None, Some(receipt)) => {
+                        if receipt.total()? != amount {
+                            return Err(anyhow::anyhow!("Receipt total must match amount"));
+                        }
+                        receipt.resolve_owed_to(&_user.id)?
+                    }
+                    _ => {
+                        return Err(anyhow::anyhow!(
+                            "Must have either splits, split strategy or receipt"
+                        ))
+                    }
+                };
+                let pool = get_pool_from_context(context).await?;
+                let group = Group::get_from_id(&group_id, pool).await?;
+                group.check_not_archived()?;
+                // Without a currency the group's default is used, then the caller's.
+                let currency_id = match currency_id.or(group.default_currency_id) {
+                    Some(currency_id) => currency_id,
+                    None => {
+                        UserConfig::get_for_user(&_user.id, pool)
+                            .await?
+                            .default_currency_id
+                    }
+                };
+                Currency::get_for_id(pool, &currency_id).await?;
+                check_can_add(&group_id, &_user.id, pool).await?;
+                let category =
+                    Category::resolve(&category, &_user.id, Some(&group_id), pool).await?;
+                let group_members = Group::get_users(&group_id, pool).await?;
+                if !splits
+                    .iter()
+                    .all(|s| group_members.iter().any(|user| user.id == s.user_id))
+                {
+                    return Err(anyhow::anyhow!("Not everyone is group member"));
+                }
+                let splits = splits
+                    .into_iter()
+                    .filter(|f| f.amount > 0)
+                    .collect::<Vec<_>>();
+                let payers = payers.unwrap_or_else(|| {
+                    vec![PayerInput {
+                        amount,
+                        user_id: _user.id.clone(),
+                    }]
+                });
+                validate_payers(&payers, amount, &group_members)?;
+                let single_payer = payers.len() == 1 && payers[0].user_id == _user.id;
+                let expense = Expense::new_expense(
+                    &_user.id,
+                    title,
+                    &group_id,
+                    &Amount {
+                        amount,
+                        currency_id,
+                    },
+                    splits.clone(),
+                    payers,
+                    split_strategy.as_ref(),
+                    receipt.as_ref(),
+                    &category,
+                    note,
+                    image_id,
+                    transaction_at,
+                    s3,
+                    pool,
+                )
+                .await?;
+                expense
+                    .notify_new_expense(_user, &splits, single_payer, pool)
+                    .await?;
+                for split in expense.get_splits(pool).await?.iter() {
+                    let _ =
+                        Group::simplify_cross_group(&split.to_user, &split.from_user, pool).await;
+                }
+                GroupEvent::record(
+                    &group_id,
+                    &_user.id,
+                    GroupEventType::ExpenseAdded,
+                    Some(&expense.id),
+                    None,
+                    None,
+                    None,
+                    pool,
+                )
+                .await;
+                if let Err(err) = Budget::check_alerts(&group_id, pool).await {
+                    log::warn!("Failed to check budgets {err:?}")
+                }
+
+                Ok(expense)
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn edit_expense<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("expense_id")"#))] expense_id: String,
+        #[graphql(validator(
+            custom = r#"NameValidator::new("title")"#,
+            min_length = 3,
+            max_length = 20
+        ))]
+        title: Option<String>,
+        amount: Option<i64>,
+        #[graphql(validator(max_length = 100))] currency_id: Option<String>,
+        splits: Option<Vec<SplitInput>>,
+        split_strategy: Option<SplitStrategy>,
+        receipt: Option<ReceiptInput>,
+        payers: Option<Vec<PayerInput>>,
+        #[graphql(validator(max_length = 300))] note: Option<String>,
+        #[graphql(validator(custom = r#"IdValidator::new("image_id")"#))] image_id: Option<String>,
+        #[graphql(validator(max_length = 100))] category: Option<String>,
+        #[graphql(validator(custom = r#"DateTimeValidator::new("transaction_at")"#))]
+        transaction_at: Option<String>,
+    ) -> anyhow::Result<Expense> {
+        let title = title.as_ref().map(|title| title.trim());
+        let s3 = context.data::<S3>().map_err(|e| anyhow::anyhow!("{e:?}"))?;
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+
+        let expense = Expense::get_from_id(&expense_id, pool).await?;
+        check_can_edit(&expense.group_id, &expense.created_by, &self_user.id, pool).await?;
+        if expense.deleted_at.is_some() {
+            return Err(anyhow::anyhow!("Expense is deleted"));
+        }
+        if let Some(amount) = amount {
+            if amount <= 0 {
+                return Err(anyhow::anyhow!("Amount must be greater than 0"));
+            }
+        }
+        if let Some(currency_id) = &currency_id {
+            Currency::get_for_id(pool, currency_id).await?;
+        }
+        let category = match category {
+            Some(category) => Some(
+                Category::resolve(&category, &self_user.id, Some(&expense.group_id), pool).await?,
+            ),
+            None => None,
+        };
+        // Changing the amount or payers re-applies the saved strategy unless the
+        // split is given again explicitly.
+        if [
+            splits.is_some(),
+            split_strategy.is_some(),
+            receipt.is_some(),
+        ]
+        .iter()
+        .filter(|given| **given)
+        .count()
+            > 1
+        {
+            return Err(anyhow::anyhow!(
+                "Must have either splits, split strategy or receipt"
+            ));
+        }
+        let split_strategy = match (&splits, split_strategy, &receipt) {
+            (None, None, None) if amount.is_some() || currency_id.is_some() || payers.is_some() => {
+                expense
+                    .split_strategy
+                    .as_deref()
+                    .map(SplitStrategy::from_json)
+                    .transpose()?
+            }
+            (_, split_strategy, _) => split_strategy,
+        };
+        let splits = match (splits, &split_strategy, &receipt) {
+            (Some(splits), _, _)
+                if splits
+                    .iter()
+                    .any(|split| split.user_id == expense.created_by) =>
+            {
+                return Err(anyhow::anyhow!("Cant split to self"));
+            }
+            (None, Some(strategy), _) => Some(
+                strategy.resolve_owed_to(amount.unwrap_or(expense.amount), &expense.created_by)?,
+            ),
+            (None, None, Some(receipt)) => {
+                if receipt.total()? != amount.unwrap_or(expense.amount) {
+                    return Err(anyhow::anyhow!("Receipt total must match amount"));
+                }
+                Some(receipt.resolve_owed_to(&expense.created_by)?)
+            }
+            (splits, _, _) => splits,
+        };
+        let (splits, payers) = match splits {
+            Some(splits) => {
+                let group_members = Group::get_users(&expense.group_id, pool).await?;
                 if !splits
                     .iter()
                     .all(|s| group_members.iter().any(|user| user.id == s.user_id))
                 {
                     return Err(anyhow::anyhow!("Not everyone is group member"));
                 }
-                let expense = Expense::new_expense(
-                    &_user.id,
-                    title,
-                    &group_id,
-                    &Amount {
-                        amount,
-                        currency_id,
-                    },
-                    splits.clone(),
-                    &category,
-                    note,
-                    image_id,
-                    transaction_at,
-                    s3,
-                    pool,
-                )
-                .await?;
-                for split in splits.iter() {
-                    let to_user_model = User::get_from_id(&split.user_id, pool).await;
-                    if let Ok(to_user_model) = to_user_model {
-                        if let Some(token) = to_user_model.notification_token {
-                            if let Err(err) = send_message_notification_with_retry(
-                                format!(
-                                    "{} added expense {}",
-                                    _user.name.as_ref().unwrap_or(&"Someone".to_string()),
-                                    title,
-                                )
-                                .as_str(),
-                                "/",
-                                "https://billdivide.app/",
-                                format!(
-                                    "you owe {}{} to {} in group {}",
-                                    currency.symbol,
-                                    ((split.amount as f64) / 10_f64.powi(currency.decimals as i32))
-                                        as i64,
-                                    _user.name.as_ref().unwrap_or(&"Someone".to_string()),
-                                    group.name.as_ref().unwrap_or(&"Direct Payment".to_string())
-                                )
-                                .as_str(),
-                                &token,
-                                Some("new_expense"),
-                            )
-                            .await
-                            {
-                                log::warn!("Failed to send notification {err:?}")
-                            } else {
-                                log::info!("Notification sent")
+                let splits = splits
+                    .into_iter()
+                    .filter(|f| f.amount > 0)
+                    .collect::<Vec<_>>();
+                let new_amount = amount.unwrap_or(expense.amount);
+                let payers = match payers {
+                    Some(payers) => payers,
+                    None => {
+                        let mut payers = expense
+                            .get_payers(pool)
+                            .await?
+                            .into_iter()
+                            .map(|payer| PayerInput {
+                                amount: payer.amount.amount,
+                                user_id: payer.user_id,
+                            })
+                            .collect::<Vec<_>>();
+                        // A lone payer keeps paying the whole amount, several
+                        // payers must say again who paid what.
+                        match payers.as_mut_slice() {
+                            [payer] => payer.amount = new_amount,
+                            _ if new_amount != expense.amount => {
+                                return Err(anyhow::anyhow!(
+                                    "Must have payers when changing amount"
+                                ))
                             }
-                        } else {
-                            log::info!("Skipping notification, no token")
+                            _ => {}
                         }
+                        payers
                     }
+                };
+                validate_payers(&payers, new_amount, &group_members)?;
+                (Some(splits), Some(payers))
+            }
+            None if payers.is_some() => {
+                return Err(anyhow::anyhow!("Must have splits when changing payers"))
+            }
+            None => (None, None),
+        };
+        let old_splits = expense.get_splits(pool).await?;
+
+        let expense = Expense::edit_expense(
+            &expense_id,
+            &self_user.id,
+            title,
+            amount,
+            currency_id,
+            splits,
+            payers,
+            split_strategy.as_ref(),
+            receipt.as_ref(),
+            category,
+            note,
+            image_id,
+            transaction_at,
+            s3,
+            pool,
+        )
+        .await?;
+        let new_splits = expense.get_splits(pool).await?;
+
+        // Net amount each user owes on this expense, before and after the edit.
+        type Share = Option<(i64, String)>;
+        let mut shares: HashMap<String, (Share, Share)> = HashMap::new();
+        for split in old_splits.iter() {
+            for (user_id, amount) in [
+                (&split.from_user, split.amount),
+                (&split.to_user, -split.amount),
+            ] {
+                shares
+                    .entry(user_id.clone())
+                    .or_default()
+                    .0
+                    .get_or_insert((0, split.currency_id.clone()))
+                    .0 += amount;
+            }
+        }
+        for split in new_splits.iter() {
+            for (user_id, amount) in [
+                (&split.from_user, split.amount),
+                (&split.to_user, -split.amount),
+            ] {
+                shares
+                    .entry(user_id.clone())
+                    .or_default()
+                    .1
+                    .get_or_insert((0, split.currency_id.clone()))
+                    .0 += amount;
+            }
+        }
+        let currency = Currency::get_for_id(pool, &expense.currency_id).await?;
+        let group = Group::get_from_id(&expense.group_id, pool).await?;
+        for (user_id, (old_share, new_share)) in shares.iter() {
+            if old_share == new_share || *user_id == self_user.id {
+                continue;
+            }
+            let Ok(user) = User::get_from_id(user_id, pool).await else {
+                continue;
+            };
+            let description = match new_share {
+                Some((amount, _)) if *amount > 0 => format!(
+                    "you now owe {}{} for {} in group {}",
+                    currency.symbol,
+                    ((*amount as f64) / 10_f64.powi(currency.decimals as i32)) as i64,
+                    expense.title,
+                    group.name.as_ref().unwrap_or(&"Direct Payment".to_string())
+                ),
+                Some((amount, _)) if *amount < 0 => format!(
+                    "you are now owed {}{} for {} in group {}",
+                    currency.symbol,
+                    ((-*amount as f64) / 10_f64.powi(currency.decimals as i32)) as i64,
+                    expense.title,
+                    group.name.as_ref().unwrap_or(&"Direct Payment".to_string())
+                ),
+                _ => format!(
+                    "you no longer owe anything for {} in group {}",
+                    expense.title,
+                    group.name.as_ref().unwrap_or(&"Direct Payment".to_string())
+                ),
+            };
+            notify_user(
+                &user,
+                format!(
+                    "{} edited expense {}",
+                    self_user.name.as_ref().unwrap_or(&"Someone".to_string()),
+                    expense.title,
+                )
+                .as_str(),
+                description.as_str(),
+                Some("new_expense"),
+            )
+            .await;
+        }
+        for split in old_splits.iter().chain(new_splits.iter()) {
+            let _ = Group::simplify_cross_group(&split.to_user, &split.from_user, pool).await;
+        }
+        GroupEvent::record(
+            &expense.group_id,
+            &self_user.id,
+            GroupEventType::ExpenseEdited,
+            Some(&expense.id),
+            None,
+            None,
+            None,
+            pool,
+        )
+        .await;
+        if let Err(err) = Budget::check_alerts(&expense.group_id, pool).await {
+            log::warn!("Failed to check budgets {err:?}")
+        }
+
+        Ok(expense)
+    }
+
+    pub async fn delete_expense<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("expense_id")"#))] expense_id: String,
+    ) -> anyhow::Result<Expense> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+
+        let expense = Expense::get_from_id(&expense_id, pool).await?;
+        check_can_edit(&expense.group_id, &expense.created_by, &self_user.id, pool).await?;
+        let splits = expense.get_splits(pool).await?;
+        let expense = Expense::delete_expense(&expense_id, &self_user.id, pool).await?;
+        GroupEvent::record(
+            &expense.group_id,
+            &self_user.id,
+            GroupEventType::ExpenseDeleted,
+            Some(&expense.id),
+            None,
+            None,
+            None,
+            pool,
+        )
+        .await;
+        for split in splits.iter() {
+            let _ = Group::simplify_cross_group(&split.to_user, &split.from_user, pool).await;
+        }
+        Ok(expense)
+    }
+
+    pub async fn restore_expense<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("expense_id")"#))] expense_id: String,
+    ) -> anyhow::Result<Expense> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+
+        let expense = Expense::get_from_id(&expense_id, pool).await?;
+        check_can_edit(&expense.group_id, &expense.created_by, &self_user.id, pool).await?;
+        let expense = Expense::restore_expense(&expense_id, &self_user.id, pool).await?;
+        GroupEvent::record(
+            &expense.group_id,
+            &self_user.id,
+            GroupEventType::ExpenseRestored,
+            Some(&expense.id),
+            None,
+            None,
+            None,
+            pool,
+        )
+        .await;
+        for split in expense.get_splits(pool).await?.iter() {
+            let _ = Group::simplify_cross_group(&split.to_user, &split.from_user, pool).await;
+        }
+        Ok(expense)
+    }
+
+    pub async fn delete_settlement<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("split_id")"#))] split_id: String,
+    ) -> anyhow::Result<Vec<Split>> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+
+        let split = Split::get_from_id(&split_id, pool).await?;
+        check_can_edit(&split.group_id, &split.created_by, &self_user.id, pool).await?;
+        let splits = Split::delete_settlement(&split_id, &self_user.id, pool).await?;
+        for split in splits.iter() {
+            GroupEvent::record(
+                &split.group_id,
+                &self_user.id,
+                GroupEventType::PaymentDeleted,
+                None,
+                Some(&split.id),
+                Some(&split.to_user),
+                None,
+                pool,
+            )
+            .await;
+        }
+        let _ = Group::simplify_cross_group(&split.to_user, &split.from_user, pool).await;
+        Ok(splits)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_recurring_expense<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))] group_id: String,
+        #[graphql(validator(
+            custom = r#"NameValidator::new("title")"#,
+            min_length = 3,
+            max_length = 20
+        ))]
+        title: String,
+        amount: i64,
+        #[graphql(validator(max_length = 100))] currency_id: String,
+        splits: Option<Vec<SplitInput>>,
+        split_strategy: Option<SplitStrategy>,
+        rule: RecurrenceRule,
+        #[graphql(validator(custom = r#"DateTimeValidator::new("starts_at")"#))] starts_at: Option<
+            String,
+        >,
+        #[graphql(validator(max_length = 300))] note: Option<String>,
+        #[graphql(default = "\"MISC\".to_string()", validator(max_length = 100))] category: String,
+    ) -> anyhow::Result<RecurringExpense> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        if amount <= 0 {
+            return Err(anyhow::anyhow!("Amount must be greater than 0"));
+        }
+        rule.validate()?;
+        Currency::get_for_id(pool, &currency_id).await?;
+        Group::get_from_id(&group_id, pool)
+            .await?
+            .check_not_archived()?;
+        check_can_add(&group_id, &self_user.id, pool).await?;
+        let group_members = Group::get_users(&group_id, pool).await?;
+        let category = Category::resolve(&category, &self_user.id, Some(&group_id), pool).await?;
+        let split_strategy = match (splits, split_strategy) {
+            (Some(splits), None) => {
+                if splits.iter().any(|split| split.user_id == self_user.id) {
+                    return Err(anyhow::anyhow!("Cant split to self"));
                 }
-                for user in splits.into_iter() {
-                    let _ = self.simplify_cross_group(context, user.user_id).await;
-                }
+                SplitStrategy::from_splits(amount, &self_user.id, &splits)?
+            }
+            (None, Some(split_strategy)) => split_strategy,
+            _ => return Err(anyhow::anyhow!("Must have either splits or split strategy")),
+        };
+        validate_recurring_split(&split_strategy, amount, &self_user.id, &group_members)?;
+        RecurringExpense::create(
+            &self_user.id,
+            &group_id,
+            title.trim(),
+            &Amount {
+                amount,
+                currency_id,
+            },
+            &split_strategy,
+            &category,
+            note,
+            rule,
+            starts_at,
+            pool,
+        )
+        .await
+    }
 
-                Ok(expense)
+    #[allow(clippy::too_many_arguments)]
+    pub async fn edit_recurring_expense<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("recurring_expense_id")"#))]
+        recurring_expense_id: String,
+        #[graphql(validator(
+            custom = r#"NameValidator::new("title")"#,
+            min_length = 3,
+            max_length = 20
+        ))]
+        title: Option<String>,
+        amount: Option<i64>,
+        #[graphql(validator(max_length = 100))] currency_id: Option<String>,
+        splits: Option<Vec<SplitInput>>,
+        split_strategy: Option<SplitStrategy>,
+        rule: Option<RecurrenceRule>,
+        #[graphql(validator(custom = r#"DateTimeValidator::new("starts_at")"#))] starts_at: Option<
+            String,
+        >,
+        #[graphql(validator(max_length = 300))] note: Option<String>,
+        #[graphql(validator(max_length = 100))] category: Option<String>,
+    ) -> anyhow::Result<RecurringExpense> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let recurring = RecurringExpense::get_from_id(&recurring_expense_id, pool).await?;
+        check_can_edit(
+            &recurring.group_id,
+            &recurring.created_by,
+            &self_user.id,
+            pool,
+        )
+        .await?;
+        if let Some(amount) = amount {
+            if amount <= 0 {
+                return Err(anyhow::anyhow!("Amount must be greater than 0"));
+            }
+        }
+        if let Some(currency_id) = &currency_id {
+            Currency::get_for_id(pool, currency_id).await?;
+        }
+        let category = match category {
+            Some(category) => Some(
+                Category::resolve(&category, &self_user.id, Some(&recurring.group_id), pool)
+                    .await?,
+            ),
+            None => None,
+        };
+        if let Some(rule) = &rule {
+            rule.validate()?;
+        }
+        let new_amount = amount.unwrap_or(recurring.amount);
+        let split_strategy = match (splits, split_strategy) {
+            (Some(_), Some(_)) => {
+                return Err(anyhow::anyhow!("Must have either splits or split strategy"))
+            }
+            (Some(splits), None) => {
+                if splits
+                    .iter()
+                    .any(|split| split.user_id == recurring.created_by)
+                {
+                    return Err(anyhow::anyhow!("Cant split to self"));
+                }
+                Some(SplitStrategy::from_splits(
+                    new_amount,
+                    &recurring.created_by,
+                    &splits,
+                )?)
             }
+            (None, split_strategy) => split_strategy,
+        };
+        // The saved split has to still work for a new amount.
+        let group_members = Group::get_users(&recurring.group_id, pool).await?;
+        match &split_strategy {
+            Some(split_strategy) => validate_recurring_split(
+                split_strategy,
+                new_amount,
+                &recurring.created_by,
+                &group_members,
+            )?,
+            None => validate_recurring_split(
+                &SplitStrategy::from_json(&recurring.split_strategy)?,
+                new_amount,
+                &recurring.created_by,
+                &group_members,
+            )?,
+        }
+        RecurringExpense::edit(
+            &recurring_expense_id,
+            title.as_deref().map(|title| title.trim()),
+            amount,
+            currency_id,
+            split_strategy.as_ref(),
+            category,
+            note,
+            rule,
+            starts_at,
+            pool,
+        )
+        .await
+    }
+
+    pub async fn pause_recurring_expense<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("recurring_expense_id")"#))]
+        recurring_expense_id: String,
+        paused: bool,
+    ) -> anyhow::Result<RecurringExpense> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let recurring = RecurringExpense::get_from_id(&recurring_expense_id, pool).await?;
+        check_can_edit(
+            &recurring.group_id,
+            &recurring.created_by,
+            &self_user.id,
+            pool,
+        )
+        .await?;
+        if recurring.paused_at.is_some() == paused {
+            return Ok(recurring);
         }
+        RecurringExpense::set_paused(&recurring_expense_id, paused, pool).await
+    }
+
+    pub async fn delete_recurring_expense<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("recurring_expense_id")"#))]
+        recurring_expense_id: String,
+    ) -> anyhow::Result<bool> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let recurring = RecurringExpense::get_from_id(&recurring_expense_id, pool).await?;
+        check_can_edit(
+            &recurring.group_id,
+            &recurring.created_by,
+            &self_user.id,
+            pool,
+        )
+        .await?;
+        RecurringExpense::delete(&recurring_expense_id, pool).await?;
+        Ok(true)
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -718,6 +1862,7 @@ impl Mutation {
         let s3 = context.data::<S3>().map_err(|e| anyhow::anyhow!("{e:?}"))?;
 
         let group = Group::get_from_id(&group_id, pool).await?;
+        check_can_add(&group_id, &self_user.id, pool).await?;
         let members = Group::get_users(&group_id, pool).await?;
         let currency = Currency::get_for_id(pool, &currency_id).await?;
         let to_user_model = User::get_from_id(&to_user, pool).await?;
@@ -747,6 +1892,17 @@ impl Mutation {
             s3.move_to_be(&image_id).await?;
         }
         transaction.commit().await?;
+        GroupEvent::record(
+            &group_id,
+            &self_user.id,
+            GroupEventType::PaymentRecorded,
+            None,
+            Some(&split.id),
+            Some(&to_user),
+            None,
+            pool,
+        )
+        .await;
         let _ = self.simplify_cross_group(context, to_user).await;
         if let Some(token) = to_user_model.notification_token {
             if let Err(err) = send_message_notification_with_retry(
@@ -782,10 +1938,12 @@ impl Mutation {
         Ok(split)
     }
 
-    pub async fn simplify_cross_group<'ctx>(
+    /// Rewrites the debts in a group so that settling up takes the payments
+    /// from `suggestedSettlements`.
+    pub async fn apply_suggested_settlements<'ctx>(
         &self,
         context: &Context<'ctx>,
-        #[graphql(validator(custom = r#"IdValidator::new("with_user")"#))] with_user: String,
+        #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))] group_id: String,
     ) -> anyhow::Result<Vec<Split>> {
         let self_user = context
             .data::<AuthTypes>()
@@ -793,91 +1951,42 @@ impl Mutation {
             .as_authorized_user()
             .ok_or(anyhow::anyhow!("Unauthorized"))?;
         let pool = get_pool_from_context(context).await?;
-        let owes = User::get_owes_with_group(&self_user.id, &with_user, pool).await?;
-        let mut grouped_positives = HashMap::new();
-        for owe in owes {
-            grouped_positives
-                .entry(owe.amount.currency_id.clone())
-                .or_insert_with(Vec::new)
-                .push((owe.amount.amount, owe.group_id));
-        }
-        let mut transaction = pool.begin().await?;
-        let mut splits = vec![];
-
-        for (currency, owes) in grouped_positives.iter() {
-            let positives = owes.iter().filter(|ow| ow.0 > 0);
-            let mut negatives = owes.iter().filter(|ow| ow.0 < 0);
-            let mut negative = negatives.next();
-            let mut negative_settled = 0_i64;
-            let part_id = uuid::Uuid::new_v4().to_string();
-            'po: for positive in positives {
-                log::info!("Positive: {positive:?}");
-                let mut remaining_positive = positive.0;
-                while let Some(negative_val) = negative.or_else(|| negatives.next()) {
-                    negative = Some(negative_val);
-                    log::info!("Negative: {negative:?}");
-                    let remaining_negative = negative_val.0.abs() - negative_settled;
-                    if remaining_negative > 0 {
-                        let (amt_settle, is_neg) = if remaining_negative > remaining_positive {
-                            negative_settled += remaining_positive;
-                            (remaining_positive, true)
-                        } else {
-                            remaining_positive -= remaining_negative;
-                            (remaining_negative, false)
-                        };
-                        log::info!("Amount settle {amt_settle} is_neg {is_neg}");
-                        splits.push(
-                            Group::settle_for_group(
-                                &positive.1,
-                                &self_user.id,
-                                &with_user,
-                                amt_settle,
-                                &self_user.id,
-                                Some(part_id.clone()),
-                                TransactionType::CrossGroupSettlement,
-                                &mut transaction,
-                                Some(negative_val.1.clone()),
-                                currency,
-                                None,
-                                None,
-                                None,
-                            )
-                            .await?,
-                        );
-                        splits.push(
-                            Group::settle_for_group(
-                                &negative_val.1,
-                                &with_user,
-                                &self_user.id,
-                                amt_settle,
-                                &self_user.id,
-                                Some(part_id.clone()),
-                                TransactionType::CrossGroupSettlement,
-                                &mut transaction,
-                                Some(positive.1.clone()),
-                                currency,
-                                None,
-                                None,
-                                None,
-                            )
-                            .await?,
-                        );
-                        if is_neg {
-                            continue 'po;
-                        }
-                    }
-                    negative = None;
-                    negative_settled = 0;
-                    log::info!("Next!")
-                }
-            }
+        let group = Group::get_from_id(&group_id, pool).await?;
+        check_can_add(&group_id, &self_user.id, pool).await?;
+        let splits = group
+            .apply_suggested_settlements(&self_user.id, pool)
+            .await?;
+        if let Some(split) = splits.first() {
+            GroupEvent::record(
+                &group_id,
+                &self_user.id,
+                GroupEventType::SettlementsSimplified,
+                None,
+                Some(&split.id),
+                None,
+                None,
+                pool,
+            )
+            .await;
         }
-
-        transaction.commit().await?;
-
         Ok(splits)
     }
 
+    pub async fn simplify_cross_group<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("with_user")"#))] with_user: String,
+    ) -> anyhow::Result<Vec<Split>> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        Group::simplify_cross_group(&self_user.id, &with_user, pool).await
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn auto_settle_with_user<'ctx>(
         &self,
         context: &Context<'ctx>,
@@ -898,7 +2007,7 @@ impl Mutation {
         let currency = Currency::get_for_id(pool, &currency_id).await?;
 
         let with_user_model = User::get_from_id(&with_user, pool).await?;
-        let mut owes = User::get_owes_with_group(&with_user, &self_user.id, pool)
+        let owes = User::get_owes_with_group(&with_user, &self_user.id, pool)
             .await?
             .into_iter()
             .filter_map(|val| {
@@ -909,7 +2018,52 @@ impl Mutation {
                 }
             })
             .collect::<Vec<_>>();
-        owes.sort_by(|a, b| b.1.cmp(&a.1));
+        // Groups where the caller is a viewer are left alone, like in
+        // settle_in_group.
+        let mut addable = vec![];
+        for owed in owes {
+            if check_can_add(&owed.0, &self_user.id, pool).await.is_ok() {
+                addable.push(owed);
+            }
+        }
+        let mut owes = addable;
+        owes.sort_by_key(|owed| std::cmp::Reverse(owed.1));
+        // Whatever is not owed in shared groups goes to the direct group, which
+        // is found or set up before anything is recorded.
+        let owed_total = owes.iter().map(|owed| owed.1.max(0)).sum::<i64>();
+        let direct_group = if amount > owed_total {
+            let user_ids = vec![self_user.id.clone(), with_user.clone()];
+            let group = match Group::find_group_for_users(user_ids, pool).await {
+                Ok(gid) => {
+                    log::info!("Found existing group {gid:?}");
+                    gid
+                }
+                Err(err) => {
+                    log::info!("Not found existing group {err:?}");
+                    let id = uuid::Uuid::new_v4().to_string();
+                    let group = Group::create_group(&id, &self_user.id, None, pool).await?;
+                    GroupInvitation::invite(
+                        &group,
+                        self_user,
+                        &with_user_model,
+                        GroupRole::Member,
+                        pool,
+                    )
+                    .await
+                    .map_err(|_e| anyhow::anyhow!("Cannot add everyone to group"))?;
+                    group
+                }
+            };
+            if GroupInvitation::has_pending(&group.id, pool).await? {
+                return Err(anyhow::anyhow!(
+                    "Waiting for everyone to accept the invitation"
+                ));
+            }
+            check_can_add(&group.id, &self_user.id, pool).await?;
+            Some(group)
+        } else {
+            None
+        };
         let mut remaining_amount = amount;
         let mut splits = vec![];
         let part_id = uuid::Uuid::new_v4().to_string();
@@ -948,30 +2102,7 @@ impl Mutation {
         }
         transaction.commit().await?;
 
-        if remaining_amount > 0 {
-            let user_ids = vec![self_user.id.clone(), with_user.clone()];
-            let group = match Group::find_group_for_users(user_ids.clone(), pool).await {
-                Ok(gid) => {
-                    log::info!("Found existing group {gid:?}");
-                    gid
-                }
-                Err(err) => {
-                    log::info!("Not found existing group {err:?}");
-                    let id = uuid::Uuid::new_v4().to_string();
-                    let group = Group::create_group(&id, &self_user.id, None, pool).await?;
-                    let futures = FuturesUnordered::new();
-                    for user_id in user_ids.iter() {
-                        if user_id != &self_user.id {
-                            futures.push(Group::add_to_group(&group.id, user_id.as_str(), pool))
-                        }
-                    }
-                    let result = futures.collect::<Vec<_>>().await;
-                    if let Some(err) = result.iter().find(|v| v.is_err()) {
-                        return Err(anyhow::anyhow!("Cannot add everyone to group {err:?}"));
-                    }
-                    group
-                }
-            };
+        if let Some(group) = direct_group {
             let mut transaction = pool.begin().await?;
             splits.push(
                 Group::settle_for_group(
@@ -996,6 +2127,19 @@ impl Mutation {
             }
             transaction.commit().await?;
         }
+        for split in splits.iter() {
+            GroupEvent::record(
+                &split.group_id,
+                &self_user.id,
+                GroupEventType::PaymentRecorded,
+                None,
+                Some(&split.id),
+                Some(&with_user),
+                None,
+                pool,
+            )
+            .await;
+        }
         let _ = self.simplify_cross_group(context, with_user).await;
         if let Some(token) = with_user_model.notification_token {
             if let Err(err) = send_message_notification_with_retry(
@@ -1143,6 +2287,29 @@ impl Mutation {
         Ok(config)
     }
 
+    pub async fn set_invitation_policy<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        policy: InvitationPolicy,
+    ) -> anyhow::Result<UserConfig> {
+        let user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or_else(|| anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let policy = policy.to_string();
+        let config = sqlx::query_as!(
+            UserConfig,
+            "UPDATE user_config SET invitation_policy=$1 WHERE user_id = $2 RETURNING * ",
+            policy,
+            user.id,
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(config)
+    }
+
     pub async fn change_name<'ctx>(
         &self,
         context: &Context<'ctx>,
@@ -1185,6 +2352,7 @@ impl Mutation {
             .as_authorized_user()
             .ok_or_else(|| anyhow::anyhow!("Unauthorized"))?;
         let pool = get_pool_from_context(context).await?;
+        check_can_add(&group_id, &user.id, pool).await?;
         let owed = sqlx::query!(
             r"
             SELECT SUM(net_owed_amount) as amount FROM (
@@ -1199,6 +2367,7 @@ impl Mutation {
                     (from_user = $2 AND to_user = $1))
                     AND group_id = $3
                     AND currency_id = $4
+                    AND deleted_at IS NULL
                 GROUP BY
                     from_user, to_user, group_id, currency_id
             )
@@ -1325,11 +2494,272 @@ impl Mutation {
                 )
                 .fetch_one(transaction.as_mut())
                 .await?;
+                let splits = vec![rev, forw];
+                Revision::record_splits(&user.id, &[], &splits, &mut transaction).await?;
                 transaction.commit().await?;
+                GroupEvent::record(
+                    &group_id,
+                    &user.id,
+                    GroupEventType::CurrencyConverted,
+                    None,
+                    Some(&splits[1].id),
+                    Some(&with_user),
+                    None,
+                    pool,
+                )
+                .await;
                 let _ = self.simplify_cross_group(context, with_user).await;
-                Ok(vec![rev, forw])
+                Ok(splits)
+            }
+        }
+    }
+
+    /// Adds a custom category for `group_id`, or just for the caller when no
+    /// group is given.
+    pub async fn create_category<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(min_length = 1, max_length = 40))] display_name: String,
+        #[graphql(default = "\"misc\".to_string()", validator(max_length = 50))] icon_key: String,
+        #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))] group_id: Option<String>,
+    ) -> anyhow::Result<Category> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let display_name = display_name.trim();
+        if display_name.is_empty() {
+            return Err(anyhow::anyhow!("Category name can not be empty"));
+        }
+        if let Some(group_id) = &group_id {
+            check_can_add(group_id, &self_user.id, pool).await?;
+        }
+        if Category::resolve(display_name, &self_user.id, group_id.as_deref(), pool)
+            .await
+            .is_ok()
+        {
+            return Err(anyhow::anyhow!("Category already exists"));
+        }
+        let user_id = group_id.is_none().then_some(self_user.id.as_str());
+        Category::new_category(display_name, &icon_key, group_id.as_deref(), user_id, pool).await
+    }
+
+    /// Adds a budget for the whole group, or for one category. Without a
+    /// currency the group's default is used.
+    pub async fn create_budget<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))] group_id: String,
+        #[graphql(validator(max_length = 100))] category: Option<String>,
+        amount: i64,
+        #[graphql(validator(max_length = 100))] currency_id: Option<String>,
+        thresholds: Option<Vec<i64>>,
+    ) -> anyhow::Result<Budget> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let group = Group::get_from_id(&group_id, pool).await?;
+        if !Group::get_role(&group_id, &self_user.id, pool)
+            .await?
+            .can_edit_details()
+        {
+            return Err(anyhow::anyhow!("You are not allowed to change budgets"));
+        }
+        let currency_id = match currency_id.or(group.default_currency_id) {
+            Some(currency_id) => currency_id,
+            None => {
+                UserConfig::get_for_user(&self_user.id, pool)
+                    .await?
+                    .default_currency_id
+            }
+        };
+        Currency::get_for_id(pool, &currency_id).await?;
+        let category = match category {
+            Some(category) => {
+                Some(Category::resolve(&category, &self_user.id, Some(&group_id), pool).await?)
             }
+            None => None,
+        };
+        let budget = Budget::create(
+            &group_id,
+            category.as_deref(),
+            &Amount {
+                amount,
+                currency_id,
+            },
+            thresholds,
+            &self_user.id,
+            pool,
+        )
+        .await?;
+        if let Err(err) = Budget::check_alerts(&group_id, pool).await {
+            log::warn!("Failed to check budgets {err:?}")
+        }
+        Ok(budget)
+    }
+
+    pub async fn edit_budget<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("budget_id")"#))] budget_id: String,
+        amount: Option<i64>,
+        #[graphql(validator(max_length = 100))] currency_id: Option<String>,
+        thresholds: Option<Vec<i64>>,
+    ) -> anyhow::Result<Budget> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let budget = Budget::get_from_id(&budget_id, pool).await?;
+        if !Group::get_role(&budget.group_id, &self_user.id, pool)
+            .await?
+            .can_edit_details()
+        {
+            return Err(anyhow::anyhow!("You are not allowed to change budgets"));
+        }
+        if let Some(currency_id) = &currency_id {
+            Currency::get_for_id(pool, currency_id).await?;
+        }
+        let budget = Budget::edit(&budget_id, amount, currency_id, thresholds, pool).await?;
+        if let Err(err) = Budget::check_alerts(&budget.group_id, pool).await {
+            log::warn!("Failed to check budgets {err:?}")
+        }
+        Ok(budget)
+    }
+
+    pub async fn delete_budget<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("budget_id")"#))] budget_id: String,
+    ) -> anyhow::Result<bool> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let budget = Budget::get_from_id(&budget_id, pool).await?;
+        if !Group::get_role(&budget.group_id, &self_user.id, pool)
+            .await?
+            .can_edit_details()
+        {
+            return Err(anyhow::anyhow!("You are not allowed to change budgets"));
+        }
+        Budget::delete(&budget_id, pool).await?;
+        Ok(true)
+    }
+
+    pub async fn add_comment<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("expense_id")"#))] expense_id: Option<
+            String,
+        >,
+        #[graphql(validator(custom = r#"IdValidator::new("split_id")"#))] split_id: Option<String>,
+        #[graphql(validator(custom = r#"IdValidator::new("parent_id")"#))] parent_id: Option<
+            String,
+        >,
+        #[graphql(validator(min_length = 1, max_length = 1000))] body: String,
+        #[graphql(validator(custom = r#"IdValidator::new("image_id")"#))] image_id: Option<String>,
+    ) -> anyhow::Result<Comment> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let s3 = context.data::<S3>().map_err(|e| anyhow::anyhow!("{e:?}"))?;
+        let body = body.trim();
+        if body.is_empty() {
+            return Err(anyhow::anyhow!("Comment can not be empty"));
+        }
+
+        let thread = CommentThread::get(expense_id.as_deref(), split_id.as_deref(), pool).await?;
+        let members = Group::get_users(&thread.group_id, pool).await?;
+        if !members.iter().any(|u| u.id == self_user.id) {
+            return Err(anyhow::anyhow!("Unauthorized"));
+        }
+        let comment = Comment::new_comment(
+            &self_user.id,
+            expense_id.as_deref(),
+            split_id.as_deref(),
+            parent_id.as_deref(),
+            body,
+            image_id,
+            s3,
+            pool,
+        )
+        .await?;
+        for user in members
+            .iter()
+            .filter(|user| user.id != self_user.id && thread.user_ids.contains(&user.id))
+        {
+            notify_user(
+                user,
+                format!(
+                    "{} commented on {}",
+                    self_user.name.as_ref().unwrap_or(&"Someone".to_string()),
+                    thread.title,
+                )
+                .as_str(),
+                body,
+                Some("new_comment"),
+            )
+            .await;
+        }
+        Ok(comment)
+    }
+
+    pub async fn edit_comment<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("comment_id")"#))] comment_id: String,
+        #[graphql(validator(min_length = 1, max_length = 1000))] body: Option<String>,
+        #[graphql(validator(custom = r#"IdValidator::new("image_id")"#))] image_id: Option<String>,
+    ) -> anyhow::Result<Comment> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let s3 = context.data::<S3>().map_err(|e| anyhow::anyhow!("{e:?}"))?;
+        let body = body.as_ref().map(|body| body.trim());
+        if body.is_some_and(|body| body.is_empty()) {
+            return Err(anyhow::anyhow!("Comment can not be empty"));
+        }
+
+        let comment = Comment::get_from_id(&comment_id, pool).await?;
+        if comment.author_id != self_user.id {
+            return Err(anyhow::anyhow!("You are not author"));
+        }
+        Comment::edit_comment(&comment_id, body, image_id, s3, pool).await
+    }
+
+    pub async fn delete_comment<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("comment_id")"#))] comment_id: String,
+    ) -> anyhow::Result<Comment> {
+        let self_user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or(anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+
+        let comment = Comment::get_from_id(&comment_id, pool).await?;
+        if comment.author_id != self_user.id {
+            return Err(anyhow::anyhow!("You are not author"));
         }
+        Comment::delete_comment(&comment_id, pool).await
     }
 
     pub async fn upload_image<'ctx>(
@@ -1364,6 +2794,131 @@ pub struct SplitInput {
     pub user_id: String,
 }
 
+#[derive(InputObject, Clone)]
+pub struct PayerInput {
+    pub amount: i64,
+    #[graphql(validator(custom = r#"IdValidator::new("user_id")"#))]
+    pub user_id: String,
+}
+
+/// Viewers can not add anything to a group.
+async fn check_can_add(group_id: &str, user_id: &str, pool: &Pool<Sqlite>) -> anyhow::Result<()> {
+    if !Group::get_role(group_id, user_id, pool)
+        .await?
+        .can_add_expenses()
+    {
+        return Err(anyhow::anyhow!("Viewers can not add to the group"));
+    }
+    Ok(())
+}
+
+/// Members can change what they added themselves, changing what others
+/// added takes a role that allows it.
+async fn check_can_edit(
+    group_id: &str,
+    created_by: &str,
+    user_id: &str,
+    pool: &Pool<Sqlite>,
+) -> anyhow::Result<()> {
+    let role = Group::get_role(group_id, user_id, pool).await?;
+    if !role.can_add_expenses() {
+        return Err(anyhow::anyhow!("Viewers can not change the group"));
+    }
+    if created_by != user_id && !role.can_edit_others_expenses() {
+        return Err(anyhow::anyhow!("You are not creator"));
+    }
+    Ok(())
+}
+
+/// Checks that every payer is a group member who paid something, and that
+/// together they paid exactly `amount`.
+fn validate_payers(payers: &[PayerInput], amount: i64, members: &[User]) -> anyhow::Result<()> {
+    if payers.is_empty() {
+        return Err(anyhow::anyhow!("Must have at least one payer"));
+    }
+    if payers.iter().any(|payer| payer.amount <= 0) {
+        return Err(anyhow::anyhow!("Payer amount must be greater than 0"));
+    }
+    if checked_sum(payers.iter().map(|payer| payer.amount))? != amount {
+        return Err(anyhow::anyhow!("Payer amounts must add up to total"));
+    }
+    let mut user_ids = payers
+        .iter()
+        .map(|payer| &payer.user_id)
+        .collect::<Vec<_>>();
+    user_ids.sort();
+    user_ids.dedup();
+    if user_ids.len() != payers.len() {
+        return Err(anyhow::anyhow!("Payer has duplicate users"));
+    }
+    if !payers
+        .iter()
+        .all(|payer| members.iter().any(|user| user.id == payer.user_id))
+    {
+        return Err(anyhow::anyhow!("Not everyone is group member"));
+    }
+    Ok(())
+}
+
+/// Checks that a recurring expense can be split, which is done again
+/// every time it runs.
+fn validate_recurring_split(
+    split_strategy: &SplitStrategy,
+    amount: i64,
+    payer_id: &str,
+    members: &[User],
+) -> anyhow::Result<()> {
+    split_strategy.resolve(amount)?;
+    if !split_strategy
+        .parts
+        .iter()
+        .all(|part| part.user_id == payer_id || members.iter().any(|user| user.id == part.user_id))
+    {
+        return Err(anyhow::anyhow!("Not everyone is group member"));
+    }
+    Ok(())
+}
+
+fn get_otp_limiter<'ctx>(context: &Context<'ctx>) -> anyhow::Result<&'ctx OtpLimits> {
+    context
+        .data::<OtpLimits>()
+        .map_err(|_e| anyhow::anyhow!("Something went wrong"))
+}
+
+/// Client address from the trusted `X-Forwarded-For` entry, or the socket
+/// peer when the request did not come through a proxy or proxies are not
+/// trusted.
+fn client_ip<'ctx>(context: &Context<'ctx>) -> Option<&'ctx str> {
+    context
+        .data_opt::<ForwardedHeader>()
+        .and_then(|header| header.client_ip())
+        .or_else(|| {
+            context
+                .data_opt::<PeerAddress>()
+                .map(|peer| peer.0.as_str())
+        })
+}
+
+/// Tokens for someone who proved they own an email or phone number. Users
+/// who finished signup get a new session, everyone else a signup token.
+async fn sign_in<'ctx>(
+    context: &Context<'ctx>,
+    user: Option<User>,
+    email: Option<String>,
+    phone: Option<String>,
+    device_name: Option<&str>,
+) -> anyhow::Result<AuthResult> {
+    match user {
+        Some(user) if user.name.is_some() => {
+            let pool = get_pool_from_context(context).await?;
+            let session = Session::create(&user.id, device_name, client_ip(context), pool).await?;
+            create_tokens(Some(user.id), user.email, user.phone, Some(&session))
+        }
+        Some(user) => create_tokens(None, user.email, user.phone, None),
+        None => create_tokens(None, email, phone, None),
+    }
+}
+
 #[derive(InputObject)]
 pub struct SplitInputNonGroup {
     pub amount: i64,
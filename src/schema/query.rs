@@ -6,16 +6,22 @@ use crate::{
     auth::AuthTypes,
     models::{
         amount::Amount,
+        budget::{Budget, BudgetStatus},
+        category::Category,
+        comment::{Comment, CommentThread},
         currency::Currency,
-        expense::Expense,
+        expense::{Expense, ExpenseSearchFilter},
         group::Group,
-        split::Split,
+        group_invitation::GroupInvitation,
+        group_invite::{GroupInvite, GroupInvitePreview},
+        session::Session,
+        split::{Split, SuggestedSettlement},
         user::{User, UserConfig},
     },
     s3::S3,
 };
 
-use super::get_pool_from_context;
+use super::{get_pool_from_context, IdValidator};
 
 pub struct Query;
 
@@ -55,8 +61,7 @@ impl Query {
                 let group = Group::get_from_id(&id, pool)
                     .await
                     .map_err(|_e| anyhow::anyhow!("Group not found"))?;
-                let users = Group::get_users(&group.id, pool).await?;
-                if users.iter().any(|u| u.id == user.id) {
+                if Group::had_member(&group.id, &user.id, pool).await? {
                     Ok(group)
                 } else {
                     Err(anyhow::anyhow!("Unauthorized"))
@@ -65,6 +70,61 @@ impl Query {
         }
     }
 
+    /// How much of each budget of the group has been spent.
+    pub async fn budget_status<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))] group_id: String,
+    ) -> anyhow::Result<Vec<BudgetStatus>> {
+        let user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or_else(|| anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        if !Group::had_member(&group_id, &user.id, pool).await? {
+            return Err(anyhow::anyhow!("Unauthorized"));
+        }
+        Budget::get_status_for_group(&group_id, pool).await
+    }
+
+    /// Fewest payments that would settle everyone in the group. Nothing is
+    /// recorded.
+    pub async fn suggested_settlements<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))] group_id: String,
+    ) -> anyhow::Result<Vec<SuggestedSettlement>> {
+        let user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or_else(|| anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        if !Group::had_member(&group_id, &user.id, pool).await? {
+            return Err(anyhow::anyhow!("Unauthorized"));
+        }
+        Group::get_from_id(&group_id, pool)
+            .await?
+            .suggested_settlements(pool)
+            .await
+    }
+
+    /// Shows the group behind an invite link before joining it.
+    pub async fn group_invite_preview<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(min_length = 1, max_length = 100))] token: String,
+    ) -> anyhow::Result<GroupInvitePreview> {
+        let _user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or_else(|| anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        GroupInvite::preview(&token, pool).await
+    }
+
     pub async fn user_by_id<'a>(&self, context: &Context<'a>, id: String) -> anyhow::Result<User> {
         let auth_type = context
             .data::<AuthTypes>()
@@ -116,6 +176,27 @@ impl Query {
         Ok(split)
     }
 
+    pub async fn comments<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("expense_id")"#))] expense_id: Option<
+            String,
+        >,
+        #[graphql(validator(custom = r#"IdValidator::new("split_id")"#))] split_id: Option<String>,
+    ) -> anyhow::Result<Vec<Comment>> {
+        let user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or_else(|| anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let thread = CommentThread::get(expense_id.as_deref(), split_id.as_deref(), pool).await?;
+        if !Group::had_member(&thread.group_id, &user.id, pool).await? {
+            return Err(anyhow::anyhow!("Unauthorized"));
+        }
+        Comment::get_for_thread(expense_id.as_deref(), split_id.as_deref(), pool).await
+    }
+
     pub async fn splits_by_part<'ctx>(
         &self,
         context: &Context<'ctx>,
@@ -129,7 +210,7 @@ impl Query {
         let pool: &sqlx::Pool<sqlx::Sqlite> = get_pool_from_context(context).await?;
         let split = sqlx::query_as!(
             Split,
-            "SELECT * FROM split_transactions WHERE part_transaction = $1",
+            "SELECT * FROM split_transactions WHERE part_transaction = $1 AND deleted_at IS NULL",
             part_id
         )
         .fetch_all(pool)
@@ -179,7 +260,11 @@ impl Query {
         Ok(users)
     }
 
-    pub async fn groups<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Vec<Group>> {
+    pub async fn groups<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(default)] include_archived: bool,
+    ) -> anyhow::Result<Vec<Group>> {
         let auth = context
             .data::<AuthTypes>()
             .map_err(|_e| anyhow::anyhow!("Unauthorized"))?
@@ -187,10 +272,35 @@ impl Query {
             .ok_or_else(|| anyhow::anyhow!("Unauthorized"))?;
 
         let pool = get_pool_from_context(context).await?;
-        let groups = auth.get_groups(pool).await?;
+        let groups = auth.get_groups(include_archived, pool).await?;
         Ok(groups)
     }
 
+    /// Group invitations waiting for the caller to accept or decline.
+    pub async fn invitations<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+    ) -> anyhow::Result<Vec<GroupInvitation>> {
+        let user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or_else(|| anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        GroupInvitation::get_pending_for_user(&user.id, pool).await
+    }
+
+    /// Devices the caller is signed in on.
+    pub async fn sessions<'ctx>(&self, context: &Context<'ctx>) -> anyhow::Result<Vec<Session>> {
+        let user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or_else(|| anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        Session::get_for_user(&user.id, pool).await
+    }
+
     pub async fn find_user_by_email<'ctx>(
         &self,
         context: &Context<'ctx>,
@@ -235,8 +345,9 @@ impl Query {
                 FROM
                     split_transactions
                 WHERE
-                    (from_user = $1) OR
-                    (to_user = $1)
+                    ((from_user = $1) OR
+                    (to_user = $1))
+                    AND deleted_at IS NULL
                 GROUP BY
                     from_user, to_user, currency_id
             ) GROUP BY currency_id
@@ -310,10 +421,11 @@ impl Query {
                         e.note AS expense_note,
                         e.image_id AS expense_image_id,
                         e.updated_at AS expense_updated_at,
-                        e.transaction_at AS expense_transaction_at
+                        e.transaction_at AS expense_transaction_at,
+                        e.split_strategy AS expense_split_strategy
                     FROM expenses e
-                    LEFT JOIN split_transactions st ON st.expense_id = e.id AND (st.to_user = $1 OR st.from_user = $1)
-                    WHERE e.group_id = $3
+                    LEFT JOIN split_transactions st ON st.expense_id = e.id AND (st.to_user = $1 OR st.from_user = $1) AND st.deleted_at IS NULL
+                    WHERE e.group_id = $3 AND e.deleted_at IS NULL
                     ),
                     split_transactions_right_join AS (
                         SELECT
@@ -344,10 +456,12 @@ impl Query {
                         e.note AS expense_note,
                         e.image_id AS expense_image_id,
                         e.updated_at AS expense_updated_at,
-                        e.transaction_at AS expense_transaction_at
+                        e.transaction_at AS expense_transaction_at,
+                        e.split_strategy AS expense_split_strategy
                     FROM split_transactions st
                     LEFT JOIN expenses e ON st.expense_id = e.id
-                    WHERE ((st.to_user = $1 AND st.from_user = $2) OR (st.from_user = $1 AND st.to_user = $2)))
+                    WHERE ((st.to_user = $1 AND st.from_user = $2) OR (st.from_user = $1 AND st.to_user = $2))
+                        AND st.deleted_at IS NULL)
 
                     SELECT *
                     FROM (
@@ -385,7 +499,9 @@ impl Query {
                             note: row.expense_note,
                             image_id: row.expense_image_id,
                             updated_at: row.expense_updated_at.unwrap(),
-                            transaction_at: row.expense_transaction_at.unwrap()
+                            transaction_at: row.expense_transaction_at.unwrap(),
+                            split_strategy: row.expense_split_strategy,
+                            deleted_at: None,
                         })
                     }else{
                         None
@@ -409,6 +525,7 @@ impl Query {
                             updated_at: row.split_transaction_updated_at.unwrap(),
                             transaction_at: row.split_transaction_transaction_at.unwrap(),
                             transaction_metadata:row.split_transaction_metadata,
+                            deleted_at: None,
                         })
                     }else{
                         None
@@ -438,6 +555,7 @@ impl Query {
             "
                 SELECT * from split_transactions WHERE
                 ((from_user = $1 AND to_user = $2) OR (from_user = $2 AND to_user = $1))
+                AND deleted_at IS NULL
                 ORDER BY transaction_at DESC LIMIT $3
                 OFFSET $4
                 ",
@@ -476,6 +594,7 @@ impl Query {
                 SELECT * from split_transactions WHERE
                 (from_user = $1  OR to_user = $1)
                 AND group_id = $2
+                AND deleted_at IS NULL
                 ORDER BY transaction_at DESC LIMIT $3
                 OFFSET $4
                 ",
@@ -532,13 +651,15 @@ impl Query {
                 e.note AS expense_note,
                 e.image_id AS expense_image_id,
                 e.updated_at AS expense_updated_at,
-                e.transaction_at AS expense_transaction_at
+                e.transaction_at AS expense_transaction_at,
+                e.split_strategy AS expense_split_strategy
             FROM
                 split_transactions st
             LEFT JOIN
                 expenses e ON st.expense_id = e.id
             WHERE
                 (st.from_user = $1 OR st.to_user = $1)
+                AND st.deleted_at IS NULL
             ORDER BY
                 st.transaction_at DESC
             LIMIT $3
@@ -566,6 +687,8 @@ impl Query {
                     image_id: row.expense_image_id,
                     updated_at: row.expense_updated_at.unwrap(),
                     transaction_at: row.expense_transaction_at.unwrap(),
+                    split_strategy: row.expense_split_strategy,
+                    deleted_at: None,
                 })
             } else {
                 None
@@ -588,6 +711,7 @@ impl Query {
                 updated_at: row.transaction_updated_at,
                 transaction_at: row.transaction_transaction_at,
                 transaction_metadata: row.split_transaction_metadata,
+                deleted_at: None,
             }),
         })
         .collect();
@@ -640,10 +764,11 @@ impl Query {
                         e.note AS expense_note,
                         e.image_id AS expense_image_id,
                         e.updated_at AS expense_updated_at,
-                        e.transaction_at as expense_transaction_at
+                        e.transaction_at as expense_transaction_at,
+                        e.split_strategy AS expense_split_strategy
                     FROM expenses e
-                    LEFT JOIN split_transactions st ON st.expense_id = e.id AND (st.to_user = $1 OR st.from_user = $1)
-                    WHERE e.group_id = $2
+                    LEFT JOIN split_transactions st ON st.expense_id = e.id AND (st.to_user = $1 OR st.from_user = $1) AND st.deleted_at IS NULL
+                    WHERE e.group_id = $2 AND e.deleted_at IS NULL
                     ),
                     split_transactions_right_join AS (
                         SELECT
@@ -674,11 +799,13 @@ impl Query {
                         e.note AS expense_note,
                         e.image_id AS expense_image_id,
                         e.updated_at AS expense_updated_at,
-                        e.transaction_at AS expense_transaction_at
+                        e.transaction_at AS expense_transaction_at,
+                        e.split_strategy AS expense_split_strategy
                     FROM split_transactions st
                     LEFT JOIN expenses e ON st.expense_id = e.id
                     WHERE (st.to_user = $1 OR st.from_user = $1)
                         AND st.group_id = $2
+                        AND st.deleted_at IS NULL
                     )
 
                     SELECT *
@@ -717,6 +844,8 @@ impl Query {
                             image_id: row.expense_image_id,
                             updated_at: row.expense_updated_at.unwrap(),
                             transaction_at: row.expense_transaction_at.unwrap(),
+                            split_strategy: row.expense_split_strategy,
+                            deleted_at: None,
                         })
                     }else{
                         None
@@ -740,6 +869,7 @@ impl Query {
                             updated_at: row.split_transaction_updated_at.unwrap(),
                             transaction_at: row.split_transaction_transaction_at.unwrap(),
                             transaction_metadata: row.split_transaction_metadata,
+                            deleted_at: None,
                         })
                     }else{
                         None
@@ -783,6 +913,49 @@ impl Query {
         Ok(s3.get_public_url(&id))
     }
 
+    pub async fn search_expenses<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(min_length = 1, max_length = 200))] query: String,
+        filter: Option<ExpenseSearchFilter>,
+        #[graphql(default = 20, validator(maximum = 100))] limit: u32,
+        #[graphql(default = 0)] offset: u32,
+    ) -> anyhow::Result<Vec<Expense>> {
+        let user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or_else(|| anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        let mut filter = filter.unwrap_or_default();
+        if let Some(category) = &filter.category {
+            filter.category = Some(
+                Category::resolve(category, &user.id, filter.group_id.as_deref(), pool).await?,
+            );
+        }
+        Expense::search(&user.id, &query, &filter, limit, offset, pool).await
+    }
+
+    pub async fn categories<'ctx>(
+        &self,
+        context: &Context<'ctx>,
+        #[graphql(validator(custom = r#"IdValidator::new("group_id")"#))] group_id: Option<String>,
+    ) -> anyhow::Result<Vec<Category>> {
+        let user = context
+            .data::<AuthTypes>()
+            .map_err(|e| anyhow::anyhow!("{e:#?}"))?
+            .as_authorized_user()
+            .ok_or_else(|| anyhow::anyhow!("Unauthorized"))?;
+        let pool = get_pool_from_context(context).await?;
+        if let Some(group_id) = &group_id {
+            let members = Group::get_users(group_id, pool).await?;
+            if !members.iter().any(|u| u.id == user.id) {
+                return Err(anyhow::anyhow!("Unauthorized"));
+            }
+        }
+        Category::get_available(&user.id, group_id.as_deref(), pool).await
+    }
+
     pub async fn expense_summary_by_category<'ctx>(
         &self,
         context: &Context<'ctx>,
@@ -801,8 +974,8 @@ impl Query {
                          ELSE COALESCE(st.amount,0)
                        END) AS total_spent, e.id, e.category AS category, e.currency_id AS currency_id
              FROM expenses AS e
-             LEFT JOIN split_transactions AS st ON st.expense_id = e.id
-             WHERE (e.group_id = $2 OR $2 IS NULL) AND (e.created_by = $1 OR st.from_user = $1) AND (e.created_at >= $3)
+             LEFT JOIN split_transactions AS st ON st.expense_id = e.id AND st.deleted_at IS NULL
+             WHERE e.deleted_at IS NULL AND (e.group_id = $2 OR $2 IS NULL) AND (e.created_by = $1 OR st.from_user = $1) AND (e.created_at >= $3)
              GROUP BY e.id
             ) GROUP BY category, currency_id
         ",user.id, group_id, from_time).fetch_all(pool).await?;
@@ -811,7 +984,7 @@ impl Query {
             categorised_amount.push(CategorisedAmount {
                 category: rec.category,
                 amount: Amount {
-                    amount: rec.total_final_spent.unwrap_or_default() as i64,
+                    amount: rec.total_final_spent.unwrap_or_default(),
                     currency_id: rec.currency_id,
                 },
             });
@@ -860,21 +1033,22 @@ impl Query {
                     Expense,
                     r#"
                     WITH expense_users AS (
-                        SELECT e.id, e.title, e.created_at, e.created_by, e.group_id, e.amount, e.currency_id, e.category, e.note, e.image_id, e.updated_at, e.transaction_at
+                        SELECT e.id, e.title, e.created_at, e.created_by, e.group_id, e.amount, e.currency_id, e.category, e.note, e.image_id, e.updated_at, e.transaction_at, e.split_strategy, e.deleted_at
                         FROM expenses e
                         JOIN split_transactions s ON e.id = s.expense_id
                         WHERE ((s.from_user = $1 AND s.to_user = $2) OR (s.from_user = $2 AND s.to_user = $1))
+                            AND s.deleted_at IS NULL
                     ),
                     expense_group AS (
-                        SELECT e.id, e.title, e.created_at, e.created_by, e.group_id, e.amount, e.currency_id, e.category, e.note, e.image_id, e.updated_at, e.transaction_at
+                        SELECT e.id, e.title, e.created_at, e.created_by, e.group_id, e.amount, e.currency_id, e.category, e.note, e.image_id, e.updated_at, e.transaction_at, e.split_strategy, e.deleted_at
                         FROM expenses e
-                        WHERE e.group_id = $5
+                        WHERE e.group_id = $5 AND e.deleted_at IS NULL
                     ),
                     all_expenses AS (
-                        SELECT id, title, created_at, created_by, group_id, amount, currency_id, category, note, image_id, updated_at, transaction_at
+                        SELECT id, title, created_at, created_by, group_id, amount, currency_id, category, note, image_id, updated_at, transaction_at, split_strategy, deleted_at
                         FROM expense_users
                         UNION ALL
-                        SELECT id, title, created_at, created_by, group_id, amount, currency_id, category, note, image_id, updated_at, transaction_at
+                        SELECT id, title, created_at, created_by, group_id, amount, currency_id, category, note, image_id, updated_at, transaction_at, split_strategy, deleted_at
                         FROM expense_group
                     )
                     SELECT *
@@ -897,11 +1071,12 @@ impl Query {
         let expenses = sqlx::query_as!(
                 Expense,
                 r#"
-    SELECT e.id, e.title, e.created_at as created_at, e.created_by, e.group_id, e.amount, e.currency_id, e.category, e.note, e.image_id, e.updated_at, e.transaction_at
+    SELECT e.id, e.title, e.created_at as created_at, e.created_by, e.group_id, e.amount, e.currency_id, e.category, e.note, e.image_id, e.updated_at, e.transaction_at, e.split_strategy, e.deleted_at
     FROM expenses e
     JOIN split_transactions s ON e.id = s.expense_id
     WHERE ((s.from_user = $1 AND s.to_user = $2)
     OR (s.from_user = $2 AND s.to_user = $1))
+    AND s.deleted_at IS NULL
     ORDER BY e.transaction_at DESC LIMIT $3 OFFSET $4
                 "#,
                 user_1,
@@ -0,0 +1,218 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::REQWEST_CLIENT;
+
+/// Sends text messages. Which implementation is used is picked at startup by
+/// [`provider_from_env`].
+#[async_trait]
+pub trait SmsProvider: Send + Sync {
+    async fn send_sms(&self, to: &str, body: &str) -> anyhow::Result<()>;
+}
+
+pub type SmsSender = Arc<dyn SmsProvider>;
+
+/// Picks the provider named by `SMS_PROVIDER`. It has to be set in release
+/// builds, debug builds fall back to logging the messages.
+pub fn provider_from_env() -> anyhow::Result<SmsSender> {
+    match std::env::var("SMS_PROVIDER").as_deref() {
+        Ok("twilio") => Ok(Arc::new(TwilioSmsProvider::from_env()?)),
+        Ok("memory") => Ok(Arc::new(MemorySmsProvider::default())),
+        Ok("log") => Ok(Arc::new(LogSmsProvider)),
+        Err(_) if cfg!(debug_assertions) => Ok(Arc::new(LogSmsProvider)),
+        Err(_) => Err(anyhow::anyhow!("SMS_PROVIDER is not set")),
+        Ok(provider) => Err(anyhow::anyhow!("Unknown SMS_PROVIDER {provider}")),
+    }
+}
+
+/// Writes messages to the log instead of sending them.
+pub struct LogSmsProvider;
+
+#[async_trait]
+impl SmsProvider for LogSmsProvider {
+    async fn send_sms(&self, to: &str, body: &str) -> anyhow::Result<()> {
+        log::info!("SMS to {to}: {body}");
+        Ok(())
+    }
+}
+
+/// Keeps messages in memory so tests can read back what was sent.
+#[derive(Default)]
+pub struct MemorySmsProvider {
+    sent: Mutex<Vec<(String, String)>>,
+}
+
+impl MemorySmsProvider {
+    /// Body of the last message sent to `to`.
+    pub fn last_sent_to(&self, to: &str) -> Option<String> {
+        self.sent
+            .lock()
+            .ok()?
+            .iter()
+            .rev()
+            .find(|(phone, _)| phone == to)
+            .map(|(_, body)| body.clone())
+    }
+}
+
+#[async_trait]
+impl SmsProvider for MemorySmsProvider {
+    async fn send_sms(&self, to: &str, body: &str) -> anyhow::Result<()> {
+        self.sent
+            .lock()
+            .map_err(|_e| anyhow::anyhow!("SMS store poisoned"))?
+            .push((to.to_string(), body.to_string()));
+        Ok(())
+    }
+}
+
+pub struct TwilioSmsProvider {
+    account_sid: String,
+    auth_token: String,
+    from: String,
+}
+
+impl TwilioSmsProvider {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            account_sid: std::env::var("TWILIO_ACCOUNT_SID")?,
+            auth_token: std::env::var("TWILIO_AUTH_TOKEN")?,
+            from: std::env::var("TWILIO_FROM_NUMBER")?,
+        })
+    }
+}
+
+#[async_trait]
+impl SmsProvider for TwilioSmsProvider {
+    async fn send_sms(&self, to: &str, body: &str) -> anyhow::Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct ReqBody<'a> {
+            to: &'a str,
+            from: &'a str,
+            body: &'a str,
+        }
+
+        let body = serde_urlencoded::to_string(ReqBody {
+            to,
+            from: &self.from,
+            body,
+        })?;
+        let request = REQWEST_CLIENT
+            .post(format!(
+                "https://api.twilio.com/2010-04-01/Accounts/{}/Messages.json",
+                self.account_sid
+            ))
+            .basic_auth(&self.account_sid, Some(&self.auth_token))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await?;
+        if !request.status().is_success() {
+            let err = request.text().await;
+            Err(anyhow::anyhow!("Cant send sms {err:?}"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+pub async fn send_phone_otp(sms: &SmsSender, to_phone: &str, otp: &str) -> anyhow::Result<()> {
+    sms.send_sms(
+        to_phone,
+        &format!("{otp} is your Bill Divide passcode. It is valid for 5 minutes."),
+    )
+    .await
+}
+
+/// Brings a phone number into E.164 form, e.g. `+919876543210`. Spaces,
+/// dashes, dots and brackets are dropped, a leading `00` is read as `+` and
+/// a trunk `(0)` after the country code is left out. Numbers without a
+/// country code are rejected.
+pub fn normalize_phone(phone: &str) -> anyhow::Result<String> {
+    let phone = phone
+        .replace("(0)", "")
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '.' | '(' | ')'))
+        .collect::<String>();
+    let digits = phone
+        .strip_prefix('+')
+        .or_else(|| phone.strip_prefix("00"))
+        .ok_or_else(|| anyhow::anyhow!("Phone number must include the country code"))?;
+    if !digits.chars().all(|c| c.is_ascii_digit())
+        || digits.starts_with('0')
+        || !(7..=15).contains(&digits.len())
+    {
+        return Err(anyhow::anyhow!("Invalid phone number"));
+    }
+    Ok(format!("+{digits}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn memory_provider_keeps_sent_otp() {
+        let memory = Arc::new(MemorySmsProvider::default());
+        let sms: SmsSender = memory.clone();
+        send_phone_otp(&sms, "+919876543210", "123456")
+            .await
+            .unwrap();
+        send_phone_otp(&sms, "+919876543210", "654321")
+            .await
+            .unwrap();
+        assert!(memory
+            .last_sent_to("+919876543210")
+            .unwrap()
+            .starts_with("654321 "));
+        assert_eq!(memory.last_sent_to("+15551234567"), None);
+    }
+
+    #[test]
+    fn normalize_phone_reads_00_as_plus() {
+        assert_eq!(normalize_phone("00919876543210").unwrap(), "+919876543210");
+        assert_eq!(normalize_phone("+919876543210").unwrap(), "+919876543210");
+    }
+
+    #[test]
+    fn normalize_phone_drops_separators() {
+        assert_eq!(
+            normalize_phone("+1 (555) 123-4567").unwrap(),
+            "+15551234567"
+        );
+        assert_eq!(
+            normalize_phone("+49.30.1234.5678").unwrap(),
+            "+493012345678"
+        );
+    }
+
+    #[test]
+    fn normalize_phone_checks_length() {
+        assert!(normalize_phone("+123456").is_err());
+        assert_eq!(normalize_phone("+1234567").unwrap(), "+1234567");
+        assert_eq!(
+            normalize_phone("+123456789012345").unwrap(),
+            "+123456789012345"
+        );
+        assert!(normalize_phone("+1234567890123456").is_err());
+    }
+
+    #[test]
+    fn normalize_phone_handles_zero_after_country_code() {
+        assert_eq!(
+            normalize_phone("+44 (0)7911 123456").unwrap(),
+            "+447911123456"
+        );
+        assert!(normalize_phone("+07911123456").is_err());
+        assert!(normalize_phone("000447911123456").is_err());
+    }
+
+    #[test]
+    fn normalize_phone_needs_country_code() {
+        assert!(normalize_phone("9876543210").is_err());
+        assert!(normalize_phone("+91 98765 4321a").is_err());
+    }
+}